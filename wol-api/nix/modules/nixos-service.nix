@@ -26,6 +26,33 @@
           example = "tour";
           description = "The machine name identify as to the backend";
         };
+
+        ca-file = mkOption {
+          type = types.nullOr types.path;
+          default = null;
+          example = "/etc/wol-agent/caddy-root.crt";
+          description = ''
+            PEM bundle of the certificate authorities to trust instead of the system ones.
+            The backend deployed with `tls internal` is signed by the local CA of Caddy, get it with
+            `docker compose exec caddy cat /data/caddy/pki/authorities/local/root.crt`
+          '';
+        };
+
+        pinned-fingerprint = mkOption {
+          type = types.nullOr types.str;
+          default = null;
+          example = "AB:CD:...:EF";
+          description = ''
+            Sha256 fingerprint of the backend certificate, trusted even if self-signed.
+            Caddy renews its internal certificates every day, prefer `ca-file` with it
+          '';
+        };
+
+        insecure = mkOption {
+          type = types.bool;
+          default = false;
+          description = "Accept any backend certificate, only use this for testing";
+        };
       };
 
       config =
@@ -37,9 +64,26 @@
               start_vdi_cmd: "echo removed vdi!"
               machine_name: "${cfg.machine-name}"
               domain: "${cfg.domain}"
+              secret_file: "/var/lib/eldolfin.wol-agent/agent-secret"
+            ''
+            + optionalString (cfg.ca-file != null) ''
+              ca_file: "${cfg.ca-file}"
+            ''
+            + optionalString (cfg.pinned-fingerprint != null) ''
+              pinned_fingerprint: "${cfg.pinned-fingerprint}"
+            ''
+            + optionalString cfg.insecure ''
+              insecure: true
             '';
           };
         in {
+          assertions = [
+            {
+              assertion = count id [(cfg.ca-file != null) (cfg.pinned-fingerprint != null) cfg.insecure] <= 1;
+              message = "Only one of `ca-file`, `pinned-fingerprint` and `insecure` can be set for the wol-agent";
+            }
+          ];
+
           systemd.services."eldolfin.wol-agent" = {
             wantedBy = ["multi-user.target"];
             environment = {
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use rand::Rng as _;
+
+/// Capped exponential backoff with jitter, used by the agent to reconnect to the backend
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub const fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            attempt: 0,
+        }
+    }
+
+    /// Upper bound of the next delay: `initial * 2^attempt`, capped at `max`
+    pub fn ceiling(&self) -> Duration {
+        2u32.checked_pow(self.attempt)
+            .and_then(|factor| self.initial.checked_mul(factor))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+
+    /// Returns a random delay between half the ceiling and the ceiling, so agents
+    /// restarted at the same time don't all reconnect together
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self.ceiling();
+        self.attempt = self.attempt.saturating_add(1);
+        rand::thread_rng().gen_range(ceiling / 2..=ceiling)
+    }
+
+    /// To be called once connected
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INITIAL: Duration = Duration::from_secs(1);
+    const MAX: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let mut backoff = Backoff::new(INITIAL, MAX);
+        for expected in [1, 2, 4, 8, 16, 32, 60, 60, 60, 60] {
+            let ceiling = backoff.ceiling();
+            assert_eq!(ceiling, Duration::from_secs(expected));
+            let delay = backoff.next_delay();
+            assert!(
+                ceiling / 2 <= delay && delay <= ceiling,
+                "{delay:?} not in range"
+            );
+        }
+
+        for _ in 0u8..100u8 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.ceiling(), MAX, "should not overflow");
+    }
+
+    #[test]
+    fn test_backoff_reset() {
+        let mut backoff = Backoff::new(INITIAL, MAX);
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+        assert_eq!(backoff.ceiling(), INITIAL);
+    }
+}
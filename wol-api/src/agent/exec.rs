@@ -0,0 +1,189 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::bail;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use super::messages::{AgentMessage, OutputStream};
+
+/// Commands started on an agent with `ServerMessage::Exec`, waiting for their output
+#[derive(Debug, Default, Clone)]
+pub struct Executions {
+    next_id: Arc<AtomicU64>,
+    running: Arc<Mutex<BTreeMap<u64, UnboundedSender<AgentMessage>>>>,
+}
+
+impl Executions {
+    /// Reserves an id for a new command, its `ExecOutput` and `ExecExit` will be sent to the receiver
+    pub fn start(&self) -> (u64, UnboundedReceiver<AgentMessage>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (send, recv) = mpsc::unbounded_channel();
+        self.running.lock().unwrap().insert(id, send);
+        (id, recv)
+    }
+
+    /// Forgets a command, its next messages will be dropped
+    pub fn cancel(&self, id: u64) {
+        self.running.lock().unwrap().remove(&id);
+    }
+
+    /// Forwards the messages of the running commands, returns the other messages
+    #[expect(
+        clippy::rest_pattern_accessible_field,
+        reason = "only the variant matters"
+    )]
+    pub fn dispatch(&self, msg: AgentMessage) -> Option<AgentMessage> {
+        let Some(id) = msg.exec_id() else {
+            return Some(msg);
+        };
+        let mut running = self.running.lock().unwrap();
+        let is_exit = matches!(msg, AgentMessage::ExecExit { .. });
+        if let Some(send) = running.get(&id) {
+            // the receiver may have stopped waiting, the output is lost anyway
+            let _res = send.send(msg);
+        }
+        if is_exit {
+            running.remove(&id);
+        }
+        None
+    }
+
+    /// The agent disconnected, every command waiting for it fails
+    pub fn clear(&self) {
+        self.running.lock().unwrap().clear();
+    }
+}
+
+/// Everything a command printed and its exit code
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExecResult {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ExecResult {
+    pub const fn success(&self) -> bool {
+        matches!(self.code, Some(0i32))
+    }
+
+    /// Collects the messages of a command until it exits
+    #[expect(
+        clippy::rest_pattern_accessible_field,
+        reason = "the id was checked by `Executions::dispatch`"
+    )]
+    pub async fn wait(mut events: UnboundedReceiver<AgentMessage>) -> anyhow::Result<Self> {
+        let mut result = Self::default();
+        while let Some(event) = events.recv().await {
+            match event {
+                AgentMessage::ExecOutput {
+                    stream: OutputStream::Stdout,
+                    data,
+                    ..
+                } => result.stdout.push_str(&data),
+                AgentMessage::ExecOutput {
+                    stream: OutputStream::Stderr,
+                    data,
+                    ..
+                } => result.stderr.push_str(&data),
+                AgentMessage::ExecExit {
+                    error: Some(error), ..
+                } => bail!("The agent could not run the command: {error}"),
+                AgentMessage::ExecExit { code, .. } => {
+                    result.code = code;
+                    return Ok(result);
+                }
+                _ => {}
+            }
+        }
+        bail!("The agent disconnected before the command exited")
+    }
+}
+
+impl fmt::Display for ExecResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "stderr: {}\nstdout: {}\nreturn code: {}",
+            self.stderr,
+            self.stdout,
+            self.code
+                .map_or_else(|| "killed by a signal".to_owned(), |code| code.to_string())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(id: u64, stream: OutputStream, data: &str) -> AgentMessage {
+        AgentMessage::ExecOutput {
+            id,
+            stream,
+            data: data.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_executions() {
+        let executions = Executions::default();
+        let (first, first_events) = executions.start();
+        let (second, second_events) = executions.start();
+        assert_ne!(first, second);
+
+        for msg in [
+            output(first, OutputStream::Stdout, "hello "),
+            output(second, OutputStream::Stderr, "oops"),
+            output(first, OutputStream::Stdout, "world"),
+            output(first, OutputStream::Stderr, "warning"),
+            AgentMessage::ExecExit {
+                id: first,
+                code: Some(0i32),
+                error: None,
+            },
+            output(first, OutputStream::Stdout, "dropped"),
+            output(42, OutputStream::Stdout, "unknown command"),
+        ] {
+            assert_eq!(executions.dispatch(msg), None);
+        }
+        assert_eq!(
+            executions.dispatch(AgentMessage::VdiClosed),
+            Some(AgentMessage::VdiClosed)
+        );
+
+        let result = ExecResult::wait(first_events).await.unwrap();
+        assert_eq!(
+            result,
+            ExecResult {
+                code: Some(0i32),
+                stdout: "hello world".to_owned(),
+                stderr: "warning".to_owned(),
+            }
+        );
+        assert!(result.success());
+
+        executions.clear();
+        ExecResult::wait(second_events)
+            .await
+            .expect_err("the agent disconnected");
+    }
+
+    #[tokio::test]
+    async fn test_spawn_error() {
+        let executions = Executions::default();
+        let (id, events) = executions.start();
+        executions.dispatch(AgentMessage::ExecExit {
+            id,
+            code: None,
+            error: Some("No such file or directory".to_owned()),
+        });
+        ExecResult::wait(events).await.unwrap_err();
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Websocket pings sent by both the agent and the backend to detect dead connections
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HeartbeatCfg {
+    /// Seconds between two pings
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// The peer is considered disconnected after this many intervals without hearing from it
+    #[serde(default = "default_missed_beats")]
+    pub missed_beats: u32,
+}
+
+const fn default_interval_secs() -> u64 {
+    10
+}
+
+const fn default_missed_beats() -> u32 {
+    3
+}
+
+impl Default for HeartbeatCfg {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_interval_secs(),
+            missed_beats: default_missed_beats(),
+        }
+    }
+}
+
+impl HeartbeatCfg {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.interval() * self.missed_beats.max(1)
+    }
+
+    /// Returns true if nothing was received from the peer for too long
+    pub fn is_expired(&self, last_seen: Instant, now: Instant) -> bool {
+        now.saturating_duration_since(last_seen) > self.timeout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(HeartbeatCfg::default(), 30, false)]
+    #[case(HeartbeatCfg::default(), 31, true)]
+    #[case(HeartbeatCfg { interval_secs: 5, missed_beats: 2 }, 10, false)]
+    #[case(HeartbeatCfg { interval_secs: 5, missed_beats: 2 }, 11, true)]
+    #[case(HeartbeatCfg { interval_secs: 0, missed_beats: 0 }, 2, true)]
+    fn test_is_expired(
+        #[case] heartbeat: HeartbeatCfg,
+        #[case] silent_secs: u64,
+        #[case] expected: bool,
+    ) {
+        let last_seen = Instant::now();
+        assert_eq!(
+            heartbeat.is_expired(last_seen, last_seen + Duration::from_secs(silent_secs)),
+            expected
+        );
+    }
+}
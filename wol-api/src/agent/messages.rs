@@ -1,22 +1,293 @@
-use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
 
-use crate::machine::application::ApplicationInfo;
+use serde::{
+    de::{self, DeserializeOwned, Visitor},
+    forward_to_deserialize_any, Deserialize, Deserializer, Serialize,
+};
+
+use super::metrics::Metrics;
+use crate::machine::application::{ApplicationInfo, IconData, RunningApplication};
 pub type WebtransportCertificateHash = Vec<u8>;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Bumped on every change of the messages that older peers could misunderstand.
+/// Agents that don't send it speak version 0
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Older agents can't be paired, the backend refuses them
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Features implemented by this build, sent by both the agent and the backend
+pub const CAPABILITIES: &[Capability] = &[
+    Capability::Vdi,
+    Capability::ShutdownNotifications,
+    Capability::Pairing,
+    Capability::Metrics,
+    Capability::Exec,
+    Capability::Power,
+    Capability::PowerResults,
+    Capability::ApplicationUpdates,
+    Capability::Icons,
+    Capability::RunningApplications,
+];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// `OpenVdi`, `VdiCertificateHash` and `VdiClosed`
+    Vdi,
+    /// `ShutdownWarning`, `ShutdownCancelled`, `PostponeShutdown` and `VetoShutdown`
+    ShutdownNotifications,
+    /// `Paired` and `Rejected`
+    Pairing,
+    /// `Metrics`
+    Metrics,
+    /// `Exec`, `ExecOutput` and `ExecExit`
+    Exec,
+    /// `PowerAction`
+    Power,
+    /// `Power`, answered with an `ExecExit`
+    PowerResults,
+    /// `ApplicationsAdded` and `ApplicationsRemoved`
+    ApplicationUpdates,
+    /// `RequestIcons` and `Icons`
+    Icons,
+    /// `RunningApplications` and `CloseApplication`
+    RunningApplications,
+    /// Capability of a newer peer
+    #[serde(other)]
+    Unknown,
+}
+
+/// A message received from a peer that may speak another protocol version
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decoded<T> {
+    Known(T),
+    /// A message added in a newer protocol version, holds its name
+    Unknown(String),
+}
+
+/// Decodes a json message, without failing on message kinds we don't know about
+pub fn decode<T>(msg: &str) -> serde_json::Result<Decoded<T>>
+where
+    T: DeserializeOwned,
+{
+    let value: serde_json::Value = serde_json::from_str(msg)?;
+    let kind = match &value {
+        serde_json::Value::String(kind) => Some(kind.clone()),
+        serde_json::Value::Object(map) if map.len() == 1 => map.keys().next().cloned(),
+        _ => None,
+    };
+    match kind {
+        Some(kind) if !variants::<T>().contains(&kind.as_str()) => Ok(Decoded::Unknown(kind)),
+        _ => serde_json::from_value(value).map(Decoded::Known),
+    }
+}
+
+/// Names of the variants of the enum `T`, serde hands them to `Deserializer::deserialize_enum`
+fn variants<T>() -> &'static [&'static str]
+where
+    T: DeserializeOwned,
+{
+    struct Introspect<'variants>(&'variants mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for Introspect<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            Err(de::Error::custom("not an enum"))
+        }
+
+        fn deserialize_enum<V>(
+            self,
+            _name: &'static str,
+            variants: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            *self.0 = variants;
+            Err(de::Error::custom("only the variants are needed"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier
+            ignored_any
+        }
+    }
+
+    let mut variants: &'static [&'static str] = &[];
+    // always fails, once the variants are known
+    let _res = T::deserialize(Introspect(&mut variants));
+    variants
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct AgentHello {
     pub machine_name: String,
     pub applications: Vec<ApplicationInfo>,
+    /// Set when the agent reconnects while a vdi it opened is still running
+    #[serde(default)]
+    pub vdi_certificate_hash: Option<WebtransportCertificateHash>,
+    #[serde(default)]
+    pub credentials: Option<AgentCredentials>,
+    #[serde(default)]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AgentCredentials {
+    /// Secret received when the agent was paired
+    Secret(String),
+    /// Asks an admin to pair the agent, `code` is shown on both sides to tell agents apart
+    PairingRequest { code: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum AgentMessage {
     Hello(AgentHello),
     VdiCertificateHash(WebtransportCertificateHash),
     VdiClosed,
+    /// A user asked to postpone the idle shutdown from the desktop notification
+    PostponeShutdown,
+    /// A user asked to keep the machine on from the desktop notification
+    VetoShutdown,
+    /// Sent every `metrics_interval_secs` to backends with the `Metrics` capability
+    Metrics(Metrics),
+    /// Applications installed or changed since the hello
+    ApplicationsAdded(Vec<ApplicationInfo>),
+    /// Names of the applications uninstalled since the hello
+    ApplicationsRemoved(Vec<String>),
+    /// Answer to `RequestIcons`, split in several messages if there are many
+    Icons(Vec<IconData>),
+    /// Applications opened with a named `Exec` that are still running, sent on every change
+    RunningApplications(Vec<RunningApplication>),
+    /// Output of a command started with `Exec`, sent as it is produced
+    ExecOutput {
+        id: u64,
+        stream: OutputStream,
+        data: String,
+    },
+    /// The command started with `Exec` is done, `code` is `None` if it was killed by a signal.
+    /// `error` is set if it could not be started
+    ExecExit {
+        id: u64,
+        code: Option<i32>,
+        #[serde(default)]
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ServerMessage {
+    /// First message sent once the agent is accepted
+    Welcome {
+        protocol_version: u32,
+        capabilities: Vec<Capability>,
+    },
     OpenVdi,
+    /// The machine will be shut down in `in_secs` seconds, `cancellable` if users can
+    /// postpone or veto it
+    ShutdownWarning {
+        in_secs: u64,
+        cancellable: bool,
+    },
+    ShutdownCancelled,
+    /// An admin approved the pairing request, the agent must send this secret from now on
+    Paired {
+        secret: String,
+    },
+    /// The agent is not allowed to connect, `pairing_required` if it should forget its
+    /// secret and ask to be paired again
+    Rejected {
+        reason: String,
+        pairing_required: bool,
+    },
+    /// Runs `argv` as the user of the agent, without a shell. The agent answers with
+    /// `ExecOutput` and `ExecExit` tagged with the same `id`.
+    /// `detach`ed commands are not waited for, `ExecExit` is sent with a code 0 once they
+    /// are started
+    Exec {
+        id: u64,
+        argv: Vec<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+        #[serde(default)]
+        cwd: Option<PathBuf>,
+        #[serde(default)]
+        detach: bool,
+        /// Detached commands with a name are reported in `RunningApplications`
+        #[serde(default)]
+        name: Option<String>,
+        /// Runs `argv` in a terminal emulator of the desktop
+        #[serde(default)]
+        terminal: bool,
+    },
+    /// Turns the machine off, the users of the desktop are notified first
+    PowerAction(PowerAction),
+    /// Like `PowerAction`, but the agent answers with an `ExecExit` tagged with `id` once
+    /// logind or systemctl accepted the action, or with the `error` if none did
+    Power {
+        id: u64,
+        action: PowerAction,
+    },
+    /// Asks for the icons with these hashes, the backend didn't cache them yet
+    RequestIcons(Vec<String>),
+    /// Asks the application to terminate, it's killed if it still runs a few seconds later
+    CloseApplication {
+        pid: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PowerAction {
+    Shutdown,
+    Reboot,
+    Suspend,
+}
+
+impl AgentMessage {
+    /// Id of the command this message is about, if it is an `Exec` reply
+    #[expect(clippy::rest_pattern_accessible_field, reason = "only the id matters")]
+    pub const fn exec_id(&self) -> Option<u64> {
+        match self {
+            Self::ExecOutput { id, .. } | Self::ExecExit { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+impl ServerMessage {
+    /// Capability the agent needs to understand this message
+    #[expect(
+        clippy::rest_pattern_accessible_field,
+        reason = "only the variant matters"
+    )]
+    pub const fn capability(&self) -> Option<Capability> {
+        match self {
+            Self::Welcome { .. } => None,
+            Self::OpenVdi => Some(Capability::Vdi),
+            Self::ShutdownWarning { .. } | Self::ShutdownCancelled => {
+                Some(Capability::ShutdownNotifications)
+            }
+            Self::Paired { .. } | Self::Rejected { .. } => Some(Capability::Pairing),
+            Self::Exec { .. } => Some(Capability::Exec),
+            Self::PowerAction(_) => Some(Capability::Power),
+            Self::Power { .. } => Some(Capability::PowerResults),
+            Self::RequestIcons(_) => Some(Capability::Icons),
+            Self::CloseApplication { .. } => Some(Capability::RunningApplications),
+        }
+    }
 }
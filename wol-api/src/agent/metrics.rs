@@ -0,0 +1,606 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+
+use itertools::Itertools as _;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{machine::idle::CpuTimes, utils::time::unix_timestamp};
+
+/// Size of a sector in `/proc/diskstats`, whatever the real sector size of the disk
+const SECTOR_SIZE: u64 = 512;
+/// Hardware monitors of graphic cards, their temperatures are not reported
+const GPU_HWMONS: &[&str] = &["amdgpu", "radeon", "nouveau", "i915", "xe"];
+/// Virtual block devices that are not reported
+const VIRTUAL_DISKS: &[&str] = &["loop", "ram", "zram", "dm-"];
+
+/// System metrics periodically sent by the agent
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct Metrics {
+    /// Unix timestamp (in seconds) of the measure
+    #[schema(example = 1_735_689_600)]
+    pub collected_at: u64,
+    /// Cpu usage in percent since the previous measure
+    #[schema(example = 12)]
+    pub cpu_usage: Option<u8>,
+    pub memory: MemoryMetrics,
+    /// Load averages over 1, 5 and 15 minutes, multiplied by 100
+    #[schema(example = json!([52, 58, 59]))]
+    pub load_average: Vec<u32>,
+    #[schema(example = 86_400)]
+    pub uptime_secs: u64,
+    /// Temperatures of the cpu, motherboard and disks sensors
+    pub temperatures: Vec<Temperature>,
+    pub disks: Vec<DiskMetrics>,
+    /// Space used on the mounted block devices, empty for older agents
+    #[serde(default)]
+    pub filesystems: Vec<FilesystemMetrics>,
+    pub network: Vec<NetworkMetrics>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct MemoryMetrics {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    pub swap_total_bytes: u64,
+    pub swap_free_bytes: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct Temperature {
+    #[schema(example = "k10temp Tctl")]
+    pub sensor: String,
+    #[schema(example = 45_500)]
+    pub millicelsius: i32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct DiskMetrics {
+    #[schema(example = "nvme0n1")]
+    pub name: String,
+    pub size_bytes: u64,
+    /// `None` on the first measure
+    pub read_bytes_per_sec: Option<u64>,
+    /// `None` on the first measure
+    pub written_bytes_per_sec: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct FilesystemMetrics {
+    #[schema(example = "/dev/nvme0n1p2")]
+    pub device: String,
+    #[schema(example = "/home")]
+    pub mount_point: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    /// Free space usable by unprivileged users, the rest is reserved for root
+    pub available_bytes: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct NetworkMetrics {
+    #[schema(example = "eth0")]
+    pub interface: String,
+    /// `None` on the first measure
+    pub received_bytes_per_sec: Option<u64>,
+    /// `None` on the first measure
+    pub sent_bytes_per_sec: Option<u64>,
+}
+
+/// Cumulated (read, written) or (received, sent) bytes
+type Counters = BTreeMap<String, (u64, u64)>;
+
+/// Reads the metrics from `/proc` and `/sys`, keeping the counters of the previous
+/// measure to compute the usage and throughputs
+#[derive(Debug)]
+pub struct MetricsCollector {
+    root: PathBuf,
+    previous: Option<Instant>,
+    cpu: Option<CpuTimes>,
+    disks: Counters,
+    network: Counters,
+}
+
+impl Default for MetricsCollector {
+    fn default() -> Self {
+        Self::new("/".into())
+    }
+}
+
+impl MetricsCollector {
+    /// `root` is where `proc` and `sys` are mounted, only changed in tests
+    pub const fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            previous: None,
+            cpu: None,
+            disks: BTreeMap::new(),
+            network: BTreeMap::new(),
+        }
+    }
+
+    fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.root.join(path)).unwrap_or_default()
+    }
+
+    /// Usage of the filesystems of `/proc/mounts` on block devices, once per device
+    fn filesystems(&self) -> Vec<FilesystemMetrics> {
+        parse_mounts(&self.read("proc/mounts"))
+            .into_iter()
+            .unique_by(|(device, _mount_point)| device.clone())
+            .filter_map(|(device, mount_point)| {
+                let path = self.root.join(mount_point.trim_start_matches('/'));
+                let stats = rustix::fs::statvfs(&path).ok()?;
+                Some(FilesystemMetrics {
+                    device,
+                    mount_point,
+                    total_bytes: stats.f_blocks * stats.f_frsize,
+                    used_bytes: stats.f_blocks.saturating_sub(stats.f_bfree) * stats.f_frsize,
+                    available_bytes: stats.f_bavail * stats.f_frsize,
+                })
+            })
+            .collect()
+    }
+
+    /// Reads files of `/proc` and `/sys` and calls `statvfs`, which may block on a busy disk
+    pub fn collect(&mut self, now: Instant) -> Metrics {
+        let elapsed_secs = self
+            .previous
+            .map(|previous| now.saturating_duration_since(previous).as_secs())
+            .filter(|secs| *secs > 0);
+        self.previous = Some(now);
+
+        let cpu = self
+            .read("proc/stat")
+            .lines()
+            .next()
+            .and_then(CpuTimes::parse);
+        let cpu_usage = cpu
+            .zip(self.cpu)
+            .and_then(|(current, previous)| current.usage_since(&previous));
+        self.cpu = cpu;
+
+        let disks = parse_diskstats(&self.read("proc/diskstats"))
+            .into_iter()
+            .filter(|(name, _counters)| {
+                !VIRTUAL_DISKS.iter().any(|prefix| name.starts_with(prefix))
+                    && self.root.join("sys/block").join(name).exists()
+            })
+            .collect();
+        let previous_disks = core::mem::replace(&mut self.disks, disks);
+        let network = parse_net_dev(&self.read("proc/net/dev"));
+        let previous_network = core::mem::replace(&mut self.network, network);
+
+        Metrics {
+            collected_at: unix_timestamp(SystemTime::now()),
+            cpu_usage,
+            memory: parse_meminfo(&self.read("proc/meminfo")),
+            load_average: parse_loadavg(&self.read("proc/loadavg")),
+            uptime_secs: parse_uptime(&self.read("proc/uptime")).unwrap_or_default(),
+            temperatures: read_temperatures(&self.root.join("sys/class/hwmon")),
+            filesystems: self.filesystems(),
+            disks: self
+                .disks
+                .iter()
+                .map(|(name, &(read, written))| {
+                    let (read_rate, written_rate) =
+                        rates(previous_disks.get(name), (read, written), elapsed_secs);
+                    DiskMetrics {
+                        name: name.clone(),
+                        size_bytes: self
+                            .read(&format!("sys/block/{name}/size"))
+                            .trim()
+                            .parse::<u64>()
+                            .unwrap_or_default()
+                            * SECTOR_SIZE,
+                        read_bytes_per_sec: read_rate,
+                        written_bytes_per_sec: written_rate,
+                    }
+                })
+                .collect(),
+            network: self
+                .network
+                .iter()
+                .map(|(interface, &(received, sent))| {
+                    let (received_rate, sent_rate) = rates(
+                        previous_network.get(interface),
+                        (received, sent),
+                        elapsed_secs,
+                    );
+                    NetworkMetrics {
+                        interface: interface.clone(),
+                        received_bytes_per_sec: received_rate,
+                        sent_bytes_per_sec: sent_rate,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Per second rates of both counters, `None` without a previous measure or if a counter wrapped
+fn rates(
+    previous: Option<&(u64, u64)>,
+    current: (u64, u64),
+    elapsed_secs: Option<u64>,
+) -> (Option<u64>, Option<u64>) {
+    let rate = |previous: u64, current: u64| Some(current.checked_sub(previous)? / elapsed_secs?);
+    previous.map_or((None, None), |&(first, second)| {
+        (rate(first, current.0), rate(second, current.1))
+    })
+}
+
+/// Parses `/proc/meminfo`, values are in kB
+fn parse_meminfo(content: &str) -> MemoryMetrics {
+    let mut memory = MemoryMetrics::default();
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let Some(bytes) = value
+            .split_whitespace()
+            .next()
+            .and_then(|kb| kb.parse::<u64>().ok())
+            .map(|kb| kb * 1024)
+        else {
+            continue;
+        };
+        match key {
+            "MemTotal" => memory.total_bytes = bytes,
+            "MemAvailable" => memory.available_bytes = bytes,
+            "SwapTotal" => memory.swap_total_bytes = bytes,
+            "SwapFree" => memory.swap_free_bytes = bytes,
+            _ => {}
+        }
+    }
+    memory
+}
+
+/// Parses a line like `0.52 0.58 0.59 1/1234 5678`
+fn parse_loadavg(content: &str) -> Vec<u32> {
+    content
+        .split_whitespace()
+        .take(3)
+        .map_while(parse_hundredths)
+        .collect()
+}
+
+/// Parses a decimal number like `0.52` without going through floats
+fn parse_hundredths(value: &str) -> Option<u32> {
+    let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+    let frac = format!("{frac:0<2}");
+    Some(int.parse::<u32>().ok()? * 100 + frac.get(..2)?.parse::<u32>().ok()?)
+}
+
+/// Parses a line like `12345.67 54321.00`
+fn parse_uptime(content: &str) -> Option<u64> {
+    content
+        .split_whitespace()
+        .next()?
+        .split('.')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Parses `/proc/diskstats` into the bytes read and written by each block device
+fn parse_diskstats(content: &str) -> Counters {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // major minor name reads merged sectors_read ms writes merged sectors_written ...
+            let read = fields.get(5)?.parse::<u64>().ok()?;
+            let written = fields.get(9)?.parse::<u64>().ok()?;
+            Some((
+                (*fields.get(2)?).to_owned(),
+                (read * SECTOR_SIZE, written * SECTOR_SIZE),
+            ))
+        })
+        .collect()
+}
+
+/// Parses `/proc/net/dev` into the bytes received and sent by each interface but the loopback
+fn parse_net_dev(content: &str) -> Counters {
+    content
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (interface, stats) = line.split_once(':')?;
+            let interface = interface.trim();
+            if interface == "lo" {
+                return None;
+            }
+            let fields: Vec<&str> = stats.split_whitespace().collect();
+            // 8 receive columns followed by 8 transmit columns
+            let received = fields.first()?.parse::<u64>().ok()?;
+            let sent = fields.get(8)?.parse::<u64>().ok()?;
+            Some((interface.to_owned(), (received, sent)))
+        })
+        .collect()
+}
+
+/// Parses `/proc/mounts` into the (device, mount point) of the filesystems on block devices
+fn parse_mounts(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = fields.next()?;
+            let mount_point = fields.next()?;
+            device
+                .starts_with("/dev/")
+                .then(|| (device.to_owned(), unescape_mount_field(mount_point)))
+        })
+        .collect()
+}
+
+/// Spaces, tabs, newlines and backslashes are escaped as octal in `/proc/mounts`
+fn unescape_mount_field(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+/// Reads the `temp*_input` files of every non gpu hardware monitor
+fn read_temperatures(hwmon_dir: &Path) -> Vec<Temperature> {
+    let Ok(hwmons) = fs::read_dir(hwmon_dir) else {
+        return vec![];
+    };
+    let mut temperatures: Vec<Temperature> = hwmons
+        .filter_map(Result::ok)
+        .flat_map(|hwmon| {
+            let path = hwmon.path();
+            let name = fs::read_to_string(path.join("name"))
+                .map(|name| name.trim().to_owned())
+                .unwrap_or_default();
+            if GPU_HWMONS.contains(&name.as_str()) {
+                return vec![];
+            }
+            let Ok(entries) = fs::read_dir(&path) else {
+                return vec![];
+            };
+            entries
+                .filter_map(Result::ok)
+                .filter_map(|entry| {
+                    let file_name = entry.file_name().into_string().ok()?;
+                    let sensor = file_name
+                        .strip_prefix("temp")?
+                        .strip_suffix("_input")?
+                        .to_owned();
+                    let millicelsius =
+                        fs::read_to_string(entry.path()).ok()?.trim().parse().ok()?;
+                    let label = fs::read_to_string(path.join(format!("temp{sensor}_label")))
+                        .map_or_else(|_| sensor, |label| label.trim().to_owned());
+                    Some(Temperature {
+                        sensor: format!("{name} {label}"),
+                        millicelsius,
+                    })
+                })
+                .collect()
+        })
+        .collect();
+    temperatures.sort_by(|first, second| first.sensor.cmp(&second.sensor));
+    temperatures
+}
+
+/// Latest metrics received from an agent, the oldest are dropped once `capacity` is reached
+#[derive(Debug)]
+pub struct MetricsHistory {
+    capacity: usize,
+    samples: VecDeque<Metrics>,
+}
+
+impl MetricsHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, metrics: Metrics) {
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        if self.capacity > 0 {
+            self.samples.push_back(metrics);
+        }
+    }
+
+    pub fn latest(&self) -> Option<&Metrics> {
+        self.samples.back()
+    }
+
+    /// Oldest first
+    pub fn samples(&self) -> Vec<Metrics> {
+        self.samples.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn fake_system(root: &Path, cpu: &str, read_sectors: u64, received: u64) {
+        write(root, "proc/stat", &format!("{cpu}\ncpu0 1 2 3 4\n"));
+        write(
+            root,
+            "proc/meminfo",
+            "MemTotal:       16000000 kB\nMemFree:         2000000 kB\nMemAvailable:    8000000 kB\nSwapTotal:       1000000 kB\nSwapFree:         500000 kB\n",
+        );
+        write(root, "proc/loadavg", "0.52 1.5 10.05 1/1234 5678\n");
+        write(root, "proc/uptime", "86400.42 123456.78\n");
+        write(
+            root,
+            "proc/diskstats",
+            &format!(
+                "   8       0 sda 10 0 {read_sectors} 0 10 0 100 0 0 0 0 0 0 0 0 0 0\n   8       1 sda1 10 0 {read_sectors} 0 10 0 100 0 0 0 0 0 0 0 0 0 0\n   7       0 loop0 1 0 8 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n"
+            ),
+        );
+        write(root, "sys/block/sda/size", "2000\n");
+        write(root, "sys/block/loop0/size", "8\n");
+        write(
+            root,
+            "proc/net/dev",
+            &format!(
+                "Inter-|   Receive                                                |  Transmit\n face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n    lo: 999 1 0 0 0 0 0 0 999 1 0 0 0 0 0 0\n  eth0: {received} 10 0 0 0 0 0 0 2000 10 0 0 0 0 0 0\n"
+            ),
+        );
+        write(
+            root,
+            "proc/mounts",
+            "/dev/sda1 / ext4 rw,relatime 0 0\nproc /proc proc rw 0 0\n/dev/sda1 /srv/bind\\040mount ext4 rw 0 0\n",
+        );
+        write(root, "sys/class/hwmon/hwmon0/name", "k10temp\n");
+        write(root, "sys/class/hwmon/hwmon0/temp1_input", "45500\n");
+        write(root, "sys/class/hwmon/hwmon0/temp1_label", "Tctl\n");
+        write(root, "sys/class/hwmon/hwmon1/name", "amdgpu\n");
+        write(root, "sys/class/hwmon/hwmon1/temp1_input", "60000\n");
+        write(root, "sys/class/hwmon/hwmon2/name", "nvme\n");
+        write(root, "sys/class/hwmon/hwmon2/temp1_input", "35000\n");
+    }
+
+    #[test]
+    fn test_collect() {
+        let dir = TempDir::new().unwrap();
+        let start = Instant::now();
+        let mut collector = MetricsCollector::new(dir.path().to_owned());
+
+        fake_system(dir.path(), "cpu  100 0 100 700 100 0 0 0 0 0", 1000, 1000);
+        let first = collector.collect(start);
+        assert_eq!(first.cpu_usage, None);
+        assert_eq!(
+            first.memory,
+            MemoryMetrics {
+                total_bytes: 16_000_000 * 1024,
+                available_bytes: 8_000_000 * 1024,
+                swap_total_bytes: 1_000_000 * 1024,
+                swap_free_bytes: 500_000 * 1024,
+            }
+        );
+        assert_eq!(first.load_average, [52, 150, 1005]);
+        assert_eq!(first.uptime_secs, 86400);
+        assert_eq!(
+            first.temperatures,
+            [
+                Temperature {
+                    sensor: "k10temp Tctl".to_owned(),
+                    millicelsius: 45500,
+                },
+                Temperature {
+                    sensor: "nvme 1".to_owned(),
+                    millicelsius: 35000,
+                },
+            ]
+        );
+        assert_eq!(
+            first.disks,
+            [DiskMetrics {
+                name: "sda".to_owned(),
+                size_bytes: 2000 * SECTOR_SIZE,
+                read_bytes_per_sec: None,
+                written_bytes_per_sec: None,
+            }]
+        );
+        let [filesystem] = first.filesystems.as_slice() else {
+            unreachable!("only sda1 is mounted: {:?}", first.filesystems);
+        };
+        assert_eq!(
+            (filesystem.device.as_str(), filesystem.mount_point.as_str()),
+            ("/dev/sda1", "/")
+        );
+        assert!(filesystem.total_bytes > 0);
+        assert!(filesystem.used_bytes + filesystem.available_bytes <= filesystem.total_bytes);
+        assert_eq!(
+            first.network,
+            [NetworkMetrics {
+                interface: "eth0".to_owned(),
+                received_bytes_per_sec: None,
+                sent_bytes_per_sec: None,
+            }]
+        );
+
+        fake_system(dir.path(), "cpu  200 0 200 1450 150 0 0 0 0 0", 3000, 11000);
+        let second = collector.collect(start + Duration::from_secs(10));
+        assert_eq!(second.cpu_usage, Some(20));
+        assert_eq!(second.disks[0].read_bytes_per_sec, Some(200 * SECTOR_SIZE));
+        assert_eq!(second.disks[0].written_bytes_per_sec, Some(0));
+        assert_eq!(second.network[0].received_bytes_per_sec, Some(1000));
+        assert_eq!(second.network[0].sent_bytes_per_sec, Some(0));
+    }
+
+    #[test]
+    fn test_collect_without_proc() {
+        let dir = TempDir::new().unwrap();
+        let metrics = MetricsCollector::new(dir.path().to_owned()).collect(Instant::now());
+        assert_eq!(metrics.cpu_usage, None);
+        assert_eq!(metrics.load_average, Vec::<u32>::new());
+        assert_eq!(metrics.disks, []);
+    }
+
+    #[test]
+    fn test_parse_mounts() {
+        assert_eq!(
+            parse_mounts("/dev/sda1 / ext4 rw 0 0\ntmpfs /tmp tmpfs rw 0 0\n/dev/sdb1 /mnt/my\\040disk ext4 rw 0 0\n"),
+            [
+                ("/dev/sda1".to_owned(), "/".to_owned()),
+                ("/dev/sdb1".to_owned(), "/mnt/my disk".to_owned()),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("0.52", Some(52))]
+    #[case("1.5", Some(150))]
+    #[case("12", Some(1200))]
+    #[case("0.123", Some(12))]
+    #[case("abc", None)]
+    fn test_parse_hundredths(#[case] value: &str, #[case] expected: Option<u32>) {
+        assert_eq!(parse_hundredths(value), expected);
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = TempDir::new().unwrap();
+        let mut collector = MetricsCollector::new(dir.path().to_owned());
+        let mut history = MetricsHistory::new(2);
+        assert_eq!(history.latest(), None);
+        for uptime in 1u8..=3u8 {
+            history.push(Metrics {
+                uptime_secs: uptime.into(),
+                ..collector.collect(Instant::now())
+            });
+        }
+        assert_eq!(
+            history
+                .samples()
+                .iter()
+                .map(|metrics| metrics.uptime_secs)
+                .collect::<Vec<_>>(),
+            [2, 3]
+        );
+        assert_eq!(history.latest().map(|metrics| metrics.uptime_secs), Some(3));
+    }
+}
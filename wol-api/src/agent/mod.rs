@@ -1 +1,7 @@
+pub mod backoff;
+pub mod exec;
+pub mod heartbeat;
 pub mod messages;
+pub mod metrics;
+pub mod pairing;
+pub mod tls;
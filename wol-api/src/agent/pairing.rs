@@ -0,0 +1,325 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use anyhow::Context as _;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use rand::RngCore as _;
+use serde::Serialize;
+use sha2::{Digest as _, Sha256};
+use utoipa::ToSchema;
+
+use super::messages::AgentCredentials;
+use crate::consts::{MAX_PENDING_PAIRINGS, PAIRING_REQUEST_TTL};
+
+pub const SECRETS_FILENAME: &str = "agent-secrets.json";
+
+/// An agent asking to be paired, waiting for an admin approval
+#[derive(Serialize, ToSchema, Clone, Debug, PartialEq, Eq)]
+pub struct PairingRequest {
+    /// Shown in the agent logs, so the admin can check it's the right agent
+    #[schema(example = "482-913")]
+    pub code: String,
+    /// Unix timestamp (in seconds) of the first request with this code
+    #[schema(example = 1_735_689_600)]
+    pub requested_at: u64,
+    /// Unix timestamp (in seconds) of the last request with this code
+    #[serde(skip)]
+    last_seen: u64,
+    #[serde(skip)]
+    approved: bool,
+}
+
+/// Outcome of a successful agent authentication
+#[derive(Debug, PartialEq, Eq)]
+pub enum Authentication {
+    /// The agent sent the secret of its machine
+    Authenticated,
+    /// An admin approved the agent, it must store this secret for its next connections
+    Paired(String),
+}
+
+/// Secrets of the paired agents, only their hashes are stored on disk
+#[derive(Debug)]
+pub struct Pairings {
+    path: PathBuf,
+    /// Machine name -> sha256 of its agent secret
+    secrets: BTreeMap<String, String>,
+    /// Machine name -> pairing code -> request, so an agent can't replace the request of another
+    pending: BTreeMap<String, BTreeMap<String, PairingRequest>>,
+}
+
+impl Pairings {
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let secrets = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Invalid agent secrets file at {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("Could not read agent secrets file at {}", path.display())
+                })
+            }
+        };
+        Ok(Self {
+            path,
+            secrets,
+            pending: BTreeMap::new(),
+        })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.secrets)?)
+            .with_context(|| format!("Could not write agent secrets to {}", self.path.display()))
+    }
+
+    pub fn is_paired(&self, name: &str) -> bool {
+        self.secrets.contains_key(name)
+    }
+
+    /// Pairing requests by machine name, the oldest first
+    pub fn pending(&self) -> BTreeMap<String, Vec<PairingRequest>> {
+        self.pending
+            .iter()
+            .map(|(name, requests)| {
+                let mut requests: Vec<PairingRequest> = requests.values().cloned().collect();
+                requests.sort_by_key(|request| request.requested_at);
+                (name.clone(), requests)
+            })
+            .collect()
+    }
+
+    /// Forgets the requests of the agents that stopped retrying
+    fn expire(&mut self, now: u64) {
+        for requests in self.pending.values_mut() {
+            requests
+                .retain(|_code, request| request.last_seen + PAIRING_REQUEST_TTL.as_secs() > now);
+        }
+        self.pending.retain(|_name, requests| !requests.is_empty());
+    }
+
+    /// Checks the credentials sent by the agent of `name` at `now` (unix timestamp in seconds),
+    /// returns the reason to show to the agent if it is rejected
+    pub fn authenticate(
+        &mut self,
+        name: &str,
+        credentials: Option<&AgentCredentials>,
+        now: u64,
+    ) -> Result<Authentication, String> {
+        match credentials {
+            None => Err(format!(
+                "The agent of `{name}` did not send any credentials, update it to pair it"
+            )),
+            Some(AgentCredentials::Secret(secret)) => {
+                if self.secrets.get(name) == Some(&hash(secret)) {
+                    Ok(Authentication::Authenticated)
+                } else {
+                    Err(format!(
+                        "Invalid secret for `{name}`, the agent must be paired again"
+                    ))
+                }
+            }
+            Some(AgentCredentials::PairingRequest { code }) => {
+                self.expire(now);
+                let requests = self.pending.entry(name.to_owned()).or_default();
+                let full = requests.len() >= MAX_PENDING_PAIRINGS;
+                match requests.get_mut(code) {
+                    Some(request) if request.approved => {
+                        let secret = generate_secret();
+                        self.secrets.insert(name.to_owned(), hash(&secret));
+                        self.save().map_err(|err| format!("{err:#}"))?;
+                        self.pending.remove(name);
+                        return Ok(Authentication::Paired(secret));
+                    }
+                    Some(request) => request.last_seen = now,
+                    None if full => {
+                        return Err(format!(
+                            "Too many agents of `{name}` are waiting to be paired, retry later"
+                        ));
+                    }
+                    None => {
+                        requests.insert(
+                            code.clone(),
+                            PairingRequest {
+                                code: code.clone(),
+                                requested_at: now,
+                                last_seen: now,
+                                approved: false,
+                            },
+                        );
+                    }
+                }
+                Err(format!(
+                    "Waiting for an admin to approve the agent of `{name}` with code {code}"
+                ))
+            }
+        }
+    }
+
+    /// Lets the agent of `name` that uses `code` pair on its next connection
+    pub fn approve(&mut self, name: &str, code: &str) -> Result<(), String> {
+        let request = self
+            .pending
+            .get_mut(name)
+            .and_then(|requests| requests.get_mut(code))
+            .ok_or_else(|| {
+                format!("No agent of `{name}` is waiting to be paired with code {code}")
+            })?;
+        request.approved = true;
+        Ok(())
+    }
+
+    /// Forgets the secret of `name`, returns false if it was not paired
+    pub fn unpair(&mut self, name: &str) -> anyhow::Result<bool> {
+        if self.secrets.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn hash(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const NOW: u64 = 1_735_689_600;
+
+    fn request(code: &str) -> AgentCredentials {
+        AgentCredentials::PairingRequest {
+            code: code.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_pairing() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(SECRETS_FILENAME);
+        let mut pairings = Pairings::load(path.clone()).unwrap();
+
+        pairings
+            .authenticate("machine1", None, NOW)
+            .expect_err("agents without credentials are rejected");
+        pairings
+            .authenticate("machine1", Some(&request("123-456")), NOW)
+            .expect_err("the pairing must be approved first");
+        assert_eq!(pairings.pending()["machine1"][0].code, "123-456");
+        pairings
+            .approve("machine1", "654-321")
+            .expect_err("the code must match");
+        pairings.approve("machine1", "123-456").unwrap();
+
+        let Ok(Authentication::Paired(secret)) =
+            pairings.authenticate("machine1", Some(&request("123-456")), NOW)
+        else {
+            unreachable!("the agent should be paired once approved");
+        };
+        assert!(pairings.pending().is_empty());
+
+        let mut pairings = Pairings::load(path).unwrap();
+        assert!(pairings.is_paired("machine1"), "secrets should be saved");
+        assert_eq!(
+            pairings.authenticate(
+                "machine1",
+                Some(&AgentCredentials::Secret(secret.clone())),
+                NOW
+            ),
+            Ok(Authentication::Authenticated)
+        );
+        pairings
+            .authenticate(
+                "machine2",
+                Some(&AgentCredentials::Secret(secret.clone())),
+                NOW,
+            )
+            .expect_err("secrets are per machine");
+        pairings
+            .authenticate(
+                "machine1",
+                Some(&AgentCredentials::Secret("wrong".to_owned())),
+                NOW,
+            )
+            .expect_err("wrong secret");
+
+        assert!(pairings.unpair("machine1").unwrap());
+        pairings
+            .authenticate("machine1", Some(&AgentCredentials::Secret(secret)), NOW)
+            .expect_err("unpaired agents are rejected");
+    }
+
+    #[test]
+    fn test_requests_are_kept_per_code() {
+        let dir = TempDir::new().unwrap();
+        let mut pairings = Pairings::load(dir.path().join(SECRETS_FILENAME)).unwrap();
+
+        pairings
+            .authenticate("machine1", Some(&request("123-456")), NOW)
+            .unwrap_err();
+        pairings.approve("machine1", "123-456").unwrap();
+        pairings
+            .authenticate("machine1", Some(&request("999-999")), NOW + 1)
+            .expect_err("another agent can't use the approval");
+        assert_eq!(
+            pairings.pending()["machine1"]
+                .iter()
+                .map(|request| request.code.as_str())
+                .collect::<Vec<_>>(),
+            ["123-456", "999-999"],
+            "a new request doesn't replace the pending ones"
+        );
+        assert!(matches!(
+            pairings.authenticate("machine1", Some(&request("123-456")), NOW + 2),
+            Ok(Authentication::Paired(_))
+        ));
+    }
+
+    #[test]
+    fn test_pending_requests_expire() {
+        let dir = TempDir::new().unwrap();
+        let mut pairings = Pairings::load(dir.path().join(SECRETS_FILENAME)).unwrap();
+        let ttl = PAIRING_REQUEST_TTL.as_secs();
+
+        pairings
+            .authenticate("machine1", Some(&request("123-456")), NOW)
+            .unwrap_err();
+        pairings
+            .authenticate("machine1", Some(&request("123-456")), NOW + ttl - 1)
+            .unwrap_err();
+        pairings
+            .authenticate("machine2", Some(&request("999-999")), NOW + 2 * ttl - 2)
+            .unwrap_err();
+        assert!(
+            pairings.pending().contains_key("machine1"),
+            "retrying keeps the request"
+        );
+        pairings
+            .authenticate("machine2", Some(&request("999-999")), NOW + 2 * ttl)
+            .unwrap_err();
+        assert!(!pairings.pending().contains_key("machine1"));
+
+        for code in 0..MAX_PENDING_PAIRINGS {
+            pairings
+                .authenticate("machine2", Some(&request(&code.to_string())), NOW + 2 * ttl)
+                .unwrap_err();
+        }
+        assert_eq!(pairings.pending()["machine2"].len(), MAX_PENDING_PAIRINGS);
+        assert!(
+            pairings.pending()["machine2"]
+                .iter()
+                .any(|request| request.code == "999-999"),
+            "older requests aren't evicted by new ones"
+        );
+    }
+}
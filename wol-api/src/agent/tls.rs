@@ -0,0 +1,208 @@
+use std::{fs::File, io::BufReader, path::PathBuf, sync::Arc, time::SystemTime};
+
+use anyhow::{bail, ensure, Context as _};
+use log::{error, warn};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, RootCertStore, ServerName,
+};
+use sha2::{Digest as _, Sha256};
+
+/// How the agent checks the certificate of the backend
+#[derive(Clone, Debug, Default)]
+pub struct TlsCfg {
+    /// PEM bundle of the certificate authorities to trust instead of the system ones
+    pub ca_file: Option<PathBuf>,
+    /// Sha256 fingerprint of the backend certificate, trusted even if self-signed
+    pub pinned_fingerprint: Option<String>,
+    /// Accept any certificate
+    pub insecure: bool,
+}
+
+impl TlsCfg {
+    pub fn client_config(&self) -> anyhow::Result<ClientConfig> {
+        ensure!(
+            [
+                self.ca_file.is_some(),
+                self.pinned_fingerprint.is_some(),
+                self.insecure
+            ]
+            .into_iter()
+            .filter(|set| *set)
+            .count()
+                <= 1,
+            "Only one of `ca_file`, `pinned_fingerprint` and `insecure` can be set"
+        );
+        let builder = ClientConfig::builder().with_safe_defaults();
+        Ok(if self.insecure {
+            error!("!!! TLS certificate verification is DISABLED (insecure: true), anyone on the network can impersonate the backend !!!");
+            builder
+                .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+                .with_no_client_auth()
+        } else if let Some(fingerprint) = &self.pinned_fingerprint {
+            builder
+                .with_custom_certificate_verifier(Arc::new(PinnedCertificate {
+                    fingerprint: parse_fingerprint(fingerprint)?,
+                }))
+                .with_no_client_auth()
+        } else if let Some(ca_file) = &self.ca_file {
+            builder
+                .with_root_certificates(load_ca_file(ca_file)?)
+                .with_no_client_auth()
+        } else {
+            builder
+                .with_root_certificates(load_system_roots()?)
+                .with_no_client_auth()
+        })
+    }
+}
+
+fn load_system_roots() -> anyhow::Result<RootCertStore> {
+    let certs = rustls_native_certs::load_native_certs()
+        .context("Could not load the system certificate authorities")?;
+    let mut roots = RootCertStore::empty();
+    let (_added, ignored) =
+        roots.add_parsable_certificates(&certs.into_iter().map(|cert| cert.0).collect::<Vec<_>>());
+    if ignored > 0 {
+        warn!("Ignored {ignored} invalid system certificate authorities");
+    }
+    ensure!(
+        !roots.is_empty(),
+        "No system certificate authority found, set `ca_file` or `pinned_fingerprint`"
+    );
+    Ok(roots)
+}
+
+fn load_ca_file(path: &PathBuf) -> anyhow::Result<RootCertStore> {
+    let file = File::open(path)
+        .with_context(|| format!("Could not open CA bundle at {}", path.display()))?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(file)) {
+        let cert = cert.with_context(|| format!("Invalid CA bundle at {}", path.display()))?;
+        roots
+            .add(&Certificate(cert.to_vec()))
+            .with_context(|| format!("Invalid certificate in {}", path.display()))?;
+    }
+    ensure!(
+        !roots.is_empty(),
+        "No certificate found in CA bundle at {}",
+        path.display()
+    );
+    Ok(roots)
+}
+
+/// Parses a sha256 fingerprint like `AB:CD:...` or `abcd...`
+pub fn parse_fingerprint(fingerprint: &str) -> anyhow::Result<[u8; 32]> {
+    let hex: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect();
+    if hex.len() != 64 || !hex.is_ascii() {
+        bail!("Invalid sha256 fingerprint `{fingerprint}`, expected 32 hex encoded bytes");
+    }
+    let mut bytes = [0; 32];
+    for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(digits)?, 16)
+            .with_context(|| format!("Invalid sha256 fingerprint `{fingerprint}`"))?;
+    }
+    Ok(bytes)
+}
+
+/// Only accepts the certificate with the given sha256 fingerprint
+struct PinnedCertificate {
+    fingerprint: [u8; 32],
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Sha256::digest(&end_entity.0).as_slice() == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "The backend certificate does not match the pinned fingerprint".to_owned(),
+            ))
+        }
+    }
+}
+
+struct SkipServerVerification;
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const FINGERPRINT: [u8; 32] = [
+        0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67,
+        0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45,
+        0x67, 0x89,
+    ];
+
+    #[rstest]
+    #[case("abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789")]
+    #[case("AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89")]
+    fn test_parse_fingerprint(#[case] fingerprint: &str) {
+        assert_eq!(parse_fingerprint(fingerprint).unwrap(), FINGERPRINT);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("abcdef")]
+    #[case("zzcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789")]
+    fn test_parse_invalid_fingerprint(#[case] fingerprint: &str) {
+        parse_fingerprint(fingerprint).unwrap_err();
+    }
+
+    #[test]
+    fn test_pinned_certificate() {
+        let cert = Certificate(b"not really a certificate".to_vec());
+        let verifier = PinnedCertificate {
+            fingerprint: Sha256::digest(&cert.0).into(),
+        };
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let verify = |cert: &Certificate| {
+            verifier.verify_server_cert(
+                cert,
+                &[],
+                &server_name,
+                &mut core::iter::empty(),
+                &[],
+                SystemTime::now(),
+            )
+        };
+        verify(&cert).unwrap();
+        verify(&Certificate(b"another certificate".to_vec())).unwrap_err();
+    }
+
+    #[test]
+    fn test_only_one_verification_mode() {
+        let tls = TlsCfg {
+            pinned_fingerprint: Some("ab".repeat(32)),
+            insecure: true,
+            ..TlsCfg::default()
+        };
+        tls.client_config().unwrap_err();
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Context as _, Error};
+use anyhow::{bail, Context as _, Error};
 use clap::Parser;
 use figment::{
     providers::{Format as _, Yaml},
@@ -6,33 +6,84 @@ use figment::{
 };
 use futures_util::{stream::SplitSink, SinkExt as _, StreamExt as _};
 use inotify::{Inotify, WatchMask};
+use itertools::Itertools as _;
 use log::{debug, error, info, warn};
+use rand::Rng as _;
 use rayon::prelude::*;
 use serde::Deserialize;
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs::{self, File},
-    io::Read as _,
+    io::{self, Read as _, Write as _},
+    os::unix::fs::OpenOptionsExt as _,
     path::PathBuf,
+    process::Stdio,
     sync::Arc,
-    thread::sleep,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt as _, AsyncRead, BufReader};
 use tokio::process::Command;
-use tokio::{net::TcpStream, sync::Mutex};
+use tokio::{net::TcpStream, sync::Mutex, time};
 use tokio_tungstenite::{
     client_async_tls_with_config,
     tungstenite::{client::IntoClientRequest, handshake::client::Response, protocol::Message},
     Connector, MaybeTlsStream, WebSocketStream,
 };
 use wol_relay_server::{
-    agent::messages::{AgentHello, AgentMessage, ServerMessage},
-    machine::application::{list_local_applications, Application, ApplicationInfo},
+    agent::{
+        backoff::Backoff,
+        heartbeat::HeartbeatCfg,
+        messages::{
+            self, AgentCredentials, AgentHello, AgentMessage, Capability, Decoded, OutputStream,
+            PowerAction, ServerMessage, WebtransportCertificateHash, CAPABILITIES,
+            PROTOCOL_VERSION,
+        },
+        metrics::MetricsCollector,
+        tls::TlsCfg,
+    },
+    consts::{ADMIN_LISTENING_ADDR, ICONS_PER_MESSAGE},
+    machine::application::{
+        application_dirs,
+        desktop_file::Locale,
+        exec, list_local_application_files, providers,
+        watch::{ApplicationWatches, WatchedDir},
+        Application, ApplicationInfo, IconData, RunningApplication,
+    },
+    misc::dirs,
+    utils::time::unix_timestamp,
 };
 
-const MAX_RETRIES: usize = 32;
-const RETRIES_INTERVAL: Duration = Duration::from_secs(1);
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Delay between two pairing attempts while waiting for an admin approval
+const REJECTED_RETRY_DELAY: Duration = Duration::from_secs(10);
+const SECRET_FILENAME: &str = "agent-secret";
+/// Time without changes before sending the application updates, installs touch many files
+const APPLICATIONS_DEBOUNCE: Duration = Duration::from_secs(2);
+/// Time left to the users to read the notification before a power action
+const POWER_ACTION_DELAY: Duration = Duration::from_secs(5);
+/// Time left to an application to exit after a SIGTERM before it's killed
+const CLOSE_APPLICATION_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the children left by an exited application are checked for
+const PROCESS_GROUP_POLL_INTERVAL: Duration = Duration::from_secs(5);
 
-type Socket = Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>;
+/// Sending half of the backend connection, `None` while disconnected.
+/// Replaced on every reconnect so running tasks (eg: the vdi) survive a disconnect
+type Socket = Arc<Mutex<Option<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>>;
+/// Certificate hash of the running vdi, sent again in the hello after a reconnect
+type VdiSession = Arc<Mutex<Option<WebtransportCertificateHash>>>;
+/// Installed applications by path of their desktop entry, kept up to date by inotify
+type Applications = Arc<Mutex<BTreeMap<PathBuf, ApplicationInfo>>>;
+type Capabilities = Arc<Mutex<Vec<Capability>>>;
+
+/// Applications opened by the backend that are still running, by pid
+#[derive(Clone)]
+struct Launched {
+    applications: Arc<Mutex<BTreeMap<u32, RunningApplication>>>,
+    socket: Socket,
+    backend_capabilities: Capabilities,
+}
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
@@ -51,6 +102,56 @@ struct Config {
     domain: String,
     /// Shell command to run to start the vdi
     start_vdi_cmd: String,
+    /// Where to store the secret received when the agent is paired with the backend
+    #[serde(default)]
+    secret_file: Option<PathBuf>,
+    /// PEM bundle of the certificate authorities to trust instead of the system ones
+    #[serde(default)]
+    ca_file: Option<PathBuf>,
+    /// Sha256 fingerprint of the backend certificate eg: <AB:CD:...:EF>, trusted even if self-signed
+    #[serde(default)]
+    pinned_fingerprint: Option<String>,
+    /// Accept any backend certificate, only use this for testing
+    #[serde(default)]
+    insecure: bool,
+    #[serde(default)]
+    heartbeat: HeartbeatCfg,
+    /// Seconds between two system metrics reports, 0 to disable them
+    #[serde(default = "default_metrics_interval_secs")]
+    metrics_interval_secs: u64,
+    /// Locale of the application names eg: <fr_FR.UTF-8>, defaults to `$LC_ALL`, `$LC_MESSAGES` or `$LANG`
+    #[serde(default)]
+    locale: Option<String>,
+}
+
+const fn default_metrics_interval_secs() -> u64 {
+    15
+}
+
+#[derive(Debug, Error)]
+#[error("The backend rejected the agent: {0}")]
+struct Rejected(String);
+
+struct Agent {
+    machine_name: String,
+    domain: String,
+    start_vdi_cmd: String,
+    applications: Applications,
+    socket: Socket,
+    vdi: VdiSession,
+    tls: Arc<rustls::ClientConfig>,
+    heartbeat: HeartbeatCfg,
+    secret_file: PathBuf,
+    /// `None` until the agent is paired
+    secret: Mutex<Option<String>>,
+    /// Shown to the admin approving the pairing
+    pairing_code: String,
+    /// Sent by the backend in its welcome, empty until then
+    backend_capabilities: Capabilities,
+    /// `None` if metrics reports are disabled
+    metrics_interval: Option<Duration>,
+    metrics: Arc<Mutex<MetricsCollector>>,
+    launched: Launched,
 }
 
 #[tokio::main]
@@ -64,6 +165,13 @@ async fn main() -> anyhow::Result<()> {
         machine_name,
         domain,
         start_vdi_cmd,
+        secret_file,
+        ca_file,
+        pinned_fingerprint,
+        insecure,
+        heartbeat,
+        metrics_interval_secs,
+        locale,
     } = Figment::new()
         .merge(Yaml::file(&config_path))
         .extract()
@@ -81,106 +189,790 @@ async fn main() -> anyhow::Result<()> {
     debug!("config: {config_path:?}");
 
     let domain = format!("{domain}/api/machine/agent");
+    let tls = TlsCfg {
+        ca_file,
+        pinned_fingerprint,
+        insecure,
+    }
+    .client_config()
+    .context("Invalid TLS configuration")?;
 
     info!("Listing applications...");
-    let applications = list_local_applications()
+    let locale = locale.map_or_else(Locale::from_env, |locale| Locale::parse(&locale));
+    let applications = list_local_application_files(&locale)
         .await
         .context("Could not list locally installed applications")?;
     info!("Reading applications icons...");
-    let applications: Vec<ApplicationInfo> = applications
-        .into_par_iter()
-        .map(Application::try_into)
-        .filter_map(|res: Result<ApplicationInfo, _>| match res {
-            Ok(app) => Some(app),
-            Err(err) => {
-                warn!("Error while listing local applications: {:#}", err);
-                None
+    let applications = read_applications(applications);
+
+    let secret_file = secret_file.unwrap_or_else(|| dirs.data_dir().join(SECRET_FILENAME));
+    let secret = match fs::read_to_string(&secret_file) {
+        Ok(secret) => Some(secret.trim().to_owned()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!("Could not read agent secret at {}", secret_file.display())
+            })
+        }
+    };
+
+    let socket: Socket = Arc::new(Mutex::new(None));
+    let backend_capabilities: Capabilities = Arc::new(Mutex::new(vec![]));
+    let agent = Agent {
+        machine_name,
+        domain,
+        start_vdi_cmd,
+        applications: Arc::new(Mutex::new(applications)),
+        socket: socket.clone(),
+        vdi: Arc::new(Mutex::new(None)),
+        tls: Arc::new(tls),
+        heartbeat,
+        secret_file,
+        secret: Mutex::new(secret),
+        pairing_code: {
+            let mut rng = rand::thread_rng();
+            format!(
+                "{:03}-{:03}",
+                rng.gen_range(0..1000u16),
+                rng.gen_range(0..1000u16)
+            )
+        },
+        backend_capabilities: backend_capabilities.clone(),
+        metrics_interval: (metrics_interval_secs > 0)
+            .then(|| Duration::from_secs(metrics_interval_secs)),
+        metrics: Arc::new(Mutex::new(MetricsCollector::default())),
+        launched: Launched {
+            applications: Arc::new(Mutex::new(BTreeMap::new())),
+            socket,
+            backend_capabilities,
+        },
+    };
+    tokio::spawn({
+        let socket = agent.socket.clone();
+        let applications = agent.applications.clone();
+        let backend_capabilities = agent.backend_capabilities.clone();
+        async move {
+            if let Err(err) =
+                watch_applications(&socket, &applications, &backend_capabilities, &locale).await
+            {
+                error!("Stopped watching the applications: {:#}", err);
             }
-        })
-        .collect();
+        }
+    });
+    agent.run().await;
+    // info!("Agent is done. Exiting");
+    // Ok(())
+}
 
-    let mut res = Err(anyhow!(
-        "unreachable? because MAX_RETRIES ({MAX_RETRIES}) > 0"
-    ));
+impl Agent {
+    /// Keeps the agent connected to the backend for the whole process lifetime
+    async fn run(&self) -> ! {
+        let mut backoff = Backoff::new(RECONNECT_MIN_DELAY, RECONNECT_MAX_DELAY);
+        loop {
+            info!("Connecting to backend at {}", &self.domain);
+            let delay = match self.serve(&mut backoff).await {
+                Ok(()) => {
+                    warn!("Backend closed the connection");
+                    backoff.next_delay()
+                }
+                Err(err) if err.is::<Rejected>() => {
+                    error!("{:#}", err);
+                    REJECTED_RETRY_DELAY
+                }
+                Err(err) => {
+                    warn!("{:#}", err);
+                    backoff.next_delay()
+                }
+            };
+            *self.socket.lock().await = None;
+            info!("Reconnecting in {:.1}s", delay.as_secs_f32());
+            time::sleep(delay).await;
+        }
+    }
 
-    info!("Connecting to backend at {}", &domain);
-    for i in 0..MAX_RETRIES {
-        debug!("Try #{}/{}", i, MAX_RETRIES);
-        match connect(&domain)
+    /// Connects to the backend and handles its messages until the connection is lost
+    async fn serve(&self, backoff: &mut Backoff) -> anyhow::Result<()> {
+        let (socket, response) = connect(&self.domain, self.tls.clone())
             .await
-            .with_context(|| format!("Could not connect to backend server at {domain}"))
-        {
-            Ok(ok) => {
-                res = Ok(ok);
+            .with_context(|| format!("Could not connect to backend server at {}", self.domain))?;
+        let (sock_send, mut sock_recv) = socket.split();
+        *self.socket.lock().await = Some(sock_send);
+        self.backend_capabilities.lock().await.clear();
+
+        info!("Connected to the server");
+        debug!("Response HTTP code: {}", response.status());
+
+        let secret = self.secret.lock().await.clone();
+        let credentials = secret.map_or_else(
+            || {
+                warn!(
+                    "This agent is not paired yet, approve it from the backend host with `curl -X POST 'http://{ADMIN_LISTENING_ADDR}/agent/pairings/{}/approve?code={}'`",
+                    self.machine_name, self.pairing_code
+                );
+                AgentCredentials::PairingRequest {
+                    code: self.pairing_code.clone(),
+                }
+            },
+            AgentCredentials::Secret,
+        );
+        let hello = AgentMessage::Hello(AgentHello {
+            machine_name: self.machine_name.clone(),
+            applications: self.applications.lock().await.values().cloned().collect(),
+            vdi_certificate_hash: self.vdi.lock().await.clone(),
+            credentials: Some(credentials),
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.to_vec(),
+        });
+        send_message(&self.socket, &hello).await?;
+
+        let mut pings = time::interval(self.heartbeat.interval());
+        let mut last_seen = Instant::now();
+        // the interval is never polled if metrics reports are disabled
+        let mut metrics_reports = time::interval(
+            self.metrics_interval
+                .unwrap_or_else(|| self.heartbeat.interval()),
+        );
+        loop {
+            let msg = tokio::select! {
+                msg = sock_recv.next() => msg,
+                _ = metrics_reports.tick(), if self.metrics_interval.is_some() => {
+                    self.send_metrics().await?;
+                    continue;
+                }
+                _ = pings.tick() => {
+                    if self.heartbeat.is_expired(last_seen, Instant::now()) {
+                        bail!("Backend missed {} heartbeats", self.heartbeat.missed_beats);
+                    }
+                    self.socket
+                        .lock()
+                        .await
+                        .as_mut()
+                        .context("Not connected to the backend")?
+                        .send(Message::Ping(vec![]))
+                        .await
+                        .context("Could not ping the backend")?;
+                    continue;
+                }
+            };
+            let Some(msg) = msg else {
                 break;
-            }
-            Err(err) => {
-                warn!("#{}: {:#}", i, err);
-                res = Err(err);
-                sleep(RETRIES_INTERVAL);
+            };
+            let msg = msg.context("Failed to read message from backend socket")?;
+            last_seen = Instant::now();
+            let Message::Text(msg) = msg else {
+                continue;
+            };
+            match messages::decode(&msg) {
+                Ok(Decoded::Known(msg)) => self.handle_message(msg, backoff).await?,
+                Ok(Decoded::Unknown(kind)) => warn!(
+                    "Ignoring unknown message `{kind}` from the backend, it may be newer than the agent"
+                ),
+                Err(err) => error!("Expected server to send correct json messages: {:#}", err),
             }
         }
+        Ok(())
     }
-    let (socket, response) = res?;
-    let (sock_send, sock_recv) = socket.split();
-    let sock_send = Arc::new(Mutex::new(sock_send));
-    let sock_recv = Arc::new(Mutex::new(sock_recv));
 
-    info!("Connected to the server");
-    debug!("Response HTTP code: {}", response.status());
-
-    let hello = AgentMessage::Hello(AgentHello {
-        machine_name,
-        applications,
-    });
-    send_message(&sock_send, &hello).await?;
+    /// Sends the system metrics, if the backend is able to understand them
+    async fn send_metrics(&self) -> anyhow::Result<()> {
+        if !self
+            .backend_capabilities
+            .lock()
+            .await
+            .contains(&Capability::Metrics)
+        {
+            return Ok(());
+        }
+        let mut collector = Arc::clone(&self.metrics).lock_owned().await;
+        let metrics = tokio::task::spawn_blocking(move || collector.collect(Instant::now()))
+            .await
+            .context("Failed to collect the metrics")?;
+        send_message(&self.socket, &AgentMessage::Metrics(metrics)).await
+    }
 
-    loop {
-        let mut lock = sock_recv.lock().await;
-        let msg = lock
-            .next()
+    /// Sends the icons with these `hashes`, in several messages to keep them small
+    async fn send_icons(&self, hashes: &[String]) -> anyhow::Result<()> {
+        let icons: Vec<IconData> = self
+            .applications
+            .lock()
             .await
-            .context("Failed to read message from backend socket")?
-            .context("Failed to read message from backend socket")?;
-        drop(lock);
-        let msg: ServerMessage = serde_json::from_str(msg.into_text().unwrap().as_str())
-            .context("Expected server to send correct json messages")?;
+            .values()
+            .filter(|app| {
+                app.icon
+                    .as_ref()
+                    .is_some_and(|icon| hashes.contains(&icon.hash))
+            })
+            .unique_by(|app| app.icon.clone())
+            .filter_map(ApplicationInfo::icon_data)
+            .collect();
+        debug!(
+            "Sending {} icon(s) out of the {} requested",
+            icons.len(),
+            hashes.len()
+        );
+        for chunk in icons.chunks(ICONS_PER_MESSAGE) {
+            send_message(&self.socket, &AgentMessage::Icons(chunk.to_vec())).await?;
+        }
+        Ok(())
+    }
+
+    async fn save_secret(&self, secret: String) -> anyhow::Result<()> {
+        info!("The agent is now paired with the backend");
+        if let Some(parent) = self.secret_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.secret_file)
+            .and_then(|mut file| file.write_all(secret.as_bytes()))
+            .with_context(|| {
+                format!(
+                    "Could not save agent secret to {}",
+                    self.secret_file.display()
+                )
+            })?;
+        *self.secret.lock().await = Some(secret);
+        Ok(())
+    }
+
+    fn remove_secret_file(&self) {
+        match fs::remove_file(&self.secret_file) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => error!(
+                "Could not remove the agent secret at {}: {err}",
+                self.secret_file.display()
+            ),
+        }
+    }
+
+    /// Runs `action` in the background, its result is sent to the backend if it gave an `id`
+    fn power(&self, id: Option<u64>, action: PowerAction) {
+        let socket = self.socket.clone();
+        tokio::spawn(async move {
+            let res = power_action(action).await;
+            match id {
+                Some(id) => {
+                    report_exit(
+                        &socket,
+                        id,
+                        &[format!("{action:?}")],
+                        res.map(|()| Some(0i32)),
+                    )
+                    .await;
+                }
+                None => {
+                    if let Err(err) = res {
+                        error!("Failed to {action:?} the machine: {:#}", err);
+                    }
+                }
+            }
+        });
+    }
+
+    async fn handle_message(
+        &self,
+        msg: ServerMessage,
+        backoff: &mut Backoff,
+    ) -> anyhow::Result<()> {
         match msg {
+            ServerMessage::Welcome {
+                protocol_version,
+                capabilities,
+            } => {
+                info!("Accepted by the backend (protocol version {protocol_version}, agent: {PROTOCOL_VERSION})");
+                debug!("Backend capabilities: {capabilities:?}");
+                *self.backend_capabilities.lock().await = capabilities;
+                backoff.reset();
+                // they may have changed while we were disconnected
+                self.launched.report().await;
+            }
+            ServerMessage::Paired { secret } => self.save_secret(secret).await?,
+            ServerMessage::Rejected {
+                reason,
+                pairing_required,
+            } => {
+                if pairing_required && self.secret.lock().await.take().is_some() {
+                    warn!("The backend does not know our secret, asking to be paired again");
+                    // it would be sent again after a restart of the agent
+                    self.remove_secret_file();
+                }
+                return Err(Rejected(reason).into());
+            }
             // fun fact: we don't even check that the vdi is not already opened
             // we trust the backend to know this for us otherwise we would open
             // multiple sanzu server
             ServerMessage::OpenVdi => {
-                let start_vdi_cmd = start_vdi_cmd.clone();
-                let socket = sock_send.clone();
+                let start_vdi_cmd = self.start_vdi_cmd.clone();
+                let socket = self.socket.clone();
+                let vdi = self.vdi.clone();
                 tokio::spawn(async move {
-                    if let Err(err) = open_vdi(&socket, &start_vdi_cmd)
+                    if let Err(err) = open_vdi(&socket, &vdi, &start_vdi_cmd)
                         .await
                         .with_context(|| format!("Failed to open vdi (cmd = {:#})", &start_vdi_cmd))
                     {
                         error!("TODO: report vdi error to backend: {:#}", err);
                     }
+                    *vdi.lock().await = None;
                     let res = send_message(&socket, &AgentMessage::VdiClosed).await;
                     if let Err(err) = res {
                         error!("Couldn't send vdi closed to backend: {:#}", err);
                     }
                 });
             }
+            ServerMessage::ShutdownWarning {
+                in_secs,
+                cancellable,
+            } => {
+                let socket = self.socket.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = warn_shutdown(&socket, in_secs, cancellable).await {
+                        error!("Failed to warn the user about the shutdown: {:#}", err);
+                    }
+                });
+            }
+            ServerMessage::Exec {
+                id,
+                argv,
+                env,
+                cwd,
+                detach,
+                name,
+                terminal,
+            } => {
+                let socket = self.socket.clone();
+                let launched = self.launched.clone();
+                tokio::spawn(async move {
+                    let res = match command(&argv, env, cwd, terminal) {
+                        Ok(cmd) if detach => launched.launch(cmd, name).await.map(|()| Some(0i32)),
+                        Ok(cmd) => exec(&socket, id, cmd).await,
+                        Err(err) => Err(err),
+                    };
+                    report_exit(&socket, id, &argv, res).await;
+                });
+            }
+            ServerMessage::PowerAction(action) => self.power(None, action),
+            ServerMessage::Power { id, action } => self.power(Some(id), action),
+            ServerMessage::RequestIcons(hashes) => self.send_icons(&hashes).await?,
+            ServerMessage::CloseApplication { pid } => {
+                let launched = self.launched.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = launched.close(pid).await {
+                        error!("Failed to close the application {pid}: {:#}", err);
+                    }
+                });
+            }
+            ServerMessage::ShutdownCancelled => {
+                tokio::spawn(async move {
+                    if let Err(err) = Command::new("notify-send")
+                        .args(["--app-name=wol-agent", "Shutdown cancelled"])
+                        .status()
+                        .await
+                    {
+                        error!("Failed to notify the user: {:#}", err);
+                    }
+                });
+            }
         }
+        Ok(())
     }
-    // info!("Agent is done. Exiting");
-    // Ok(())
 }
 
 async fn send_message(ws: &Socket, msg: &AgentMessage) -> anyhow::Result<()> {
     debug!("Sending msg to backend");
     ws.lock()
         .await
+        .as_mut()
+        .context("Not connected to the backend")?
         .send(Message::Text(serde_json::to_string(msg)?))
         .await
         .context("Could not send message to backend")
 }
 
-async fn open_vdi(socket: &Socket, start_vdi_cmd: &str) -> anyhow::Result<()> {
+/// Shows a desktop notification, letting the user postpone or veto the shutdown if it's `cancellable`
+async fn warn_shutdown(socket: &Socket, in_secs: u64, cancellable: bool) -> anyhow::Result<()> {
+    let mut cmd = Command::new("notify-send");
+    cmd.arg("--app-name=wol-agent")
+        .arg("--urgency=critical")
+        .arg(format!("--expire-time={}", in_secs * 1000));
+    if cancellable {
+        cmd.args(["--action=postpone=Postpone", "--action=veto=Keep on"]);
+    }
+    let output = cmd
+        .arg("Shutdown")
+        .arg(format!(
+            "This machine will shut down in {} minutes",
+            in_secs.div_ceil(60)
+        ))
+        .output()
+        .await
+        .context("Failed to run notify-send")?;
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "postpone" => send_message(socket, &AgentMessage::PostponeShutdown).await,
+        "veto" => send_message(socket, &AgentMessage::VetoShutdown).await,
+        _ => Ok(()),
+    }
+}
+
+/// Reads the icons of the applications, skipping the invalid ones
+fn read_applications(
+    applications: Vec<(PathBuf, Application)>,
+) -> BTreeMap<PathBuf, ApplicationInfo> {
+    applications
+        .into_par_iter()
+        .filter_map(
+            |(path, application)| match TryInto::<ApplicationInfo>::try_into(application) {
+                Ok(app) => Some((path, app)),
+                Err(err) => {
+                    warn!("Error while listing local applications: {:#}", err);
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+/// Sends the applications installed or removed while the agent runs
+async fn watch_applications(
+    socket: &Socket,
+    applications: &Applications,
+    backend_capabilities: &Capabilities,
+    locale: &Locale,
+) -> anyhow::Result<()> {
+    let inotify = Inotify::init().context("Failed to initialize inotify")?;
+    // the games of the launchers are in their libraries, next to folders with their files
+    let provider_dirs = providers::Roots::from_env()
+        .map(|roots| providers::watched_dirs(&roots))
+        .unwrap_or_default();
+    let roots = application_dirs()?
+        .into_iter()
+        .map(|path| WatchedDir {
+            path,
+            recursive: true,
+        })
+        .chain(provider_dirs.into_iter().map(|path| WatchedDir {
+            path,
+            recursive: false,
+        }))
+        .collect();
+    let mut watched = ApplicationWatches::new(inotify.watches(), roots);
+    let mut buffer = [0; 4096];
+    let mut events = inotify
+        .into_event_stream(&mut buffer)
+        .context("Failed to watch the applications")?;
+    loop {
+        let mut changed = BTreeSet::new();
+        let mut next = events.next().await;
+        // wait for the changes to settle down
+        loop {
+            let event = next
+                .context("The inotify stream ended")?
+                .context("Failed to watch the applications")?;
+            changed.extend(watched.handle(&event));
+            match time::timeout(APPLICATIONS_DEBOUNCE, events.next()).await {
+                Ok(event) => next = event,
+                Err(_elapsed) => break,
+            }
+        }
+        update_applications(socket, applications, backend_capabilities, locale, &changed).await;
+    }
+}
+
+/// Lists the applications again, an entry may shadow or reveal another one.
+/// Only the `changed` and new desktop entries are read, loading the icons is slow
+async fn update_applications(
+    socket: &Socket,
+    applications: &Applications,
+    backend_capabilities: &Capabilities,
+    locale: &Locale,
+    changed: &BTreeSet<PathBuf>,
+) {
+    let listed = match list_local_application_files(locale).await {
+        Ok(listed) => listed,
+        Err(err) => {
+            warn!("Could not list the applications: {:#}", err);
+            return;
+        }
+    };
+    let previous = applications.lock().await.clone();
+    let (unchanged, to_read): (Vec<_>, Vec<_>) = listed
+        .into_iter()
+        .partition(|(path, _application)| previous.contains_key(path) && !changed.contains(path));
+    let added = tokio::task::spawn_blocking(move || read_applications(to_read))
+        .await
+        .unwrap_or_default();
+    let mut all_applications: BTreeMap<PathBuf, ApplicationInfo> = unchanged
+        .into_iter()
+        .filter_map(|(path, _application)| Some((path.clone(), previous.get(&path)?.clone())))
+        .collect();
+    all_applications.extend(added.clone());
+    // another desktop entry may still provide it
+    let removed: Vec<String> = previous
+        .values()
+        .filter(|app| !all_applications.values().any(|new| new.name == app.name))
+        .map(|app| app.name.clone())
+        .unique()
+        .collect();
+    *applications.lock().await = all_applications;
+    let added: Vec<ApplicationInfo> = added.into_values().collect();
+    info!(
+        "{} application(s) added or changed, {} removed",
+        added.len(),
+        removed.len()
+    );
+    if !backend_capabilities
+        .lock()
+        .await
+        .contains(&Capability::ApplicationUpdates)
+    {
+        // the backend will get them in our next hello
+        return;
+    }
+    if !added.is_empty() {
+        if let Err(err) = send_message(socket, &AgentMessage::ApplicationsAdded(added)).await {
+            warn!("Couldn't send the new applications to backend: {:#}", err);
+        }
+    }
+    if !removed.is_empty() {
+        if let Err(err) = send_message(socket, &AgentMessage::ApplicationsRemoved(removed)).await {
+            warn!(
+                "Couldn't send the removed applications to backend: {:#}",
+                err
+            );
+        }
+    }
+}
+
+/// Notifies the desktop users then asks logind to do `action`, falls back to `systemctl`
+async fn power_action(action: PowerAction) -> anyhow::Result<()> {
+    let (title, logind_method, systemctl_verb) = match action {
+        PowerAction::Shutdown => ("Shutting down", "PowerOff", "poweroff"),
+        PowerAction::Reboot => ("Rebooting", "Reboot", "reboot"),
+        PowerAction::Suspend => ("Suspending", "Suspend", "suspend"),
+    };
+    info!("{title} the machine as asked by the backend");
+    if let Err(err) = Command::new("notify-send")
+        .args(["--app-name=wol-agent", "--urgency=critical", title])
+        .arg(format!(
+            "Asked from the wol panel, in {} seconds",
+            POWER_ACTION_DELAY.as_secs()
+        ))
+        .status()
+        .await
+    {
+        warn!("Failed to notify the user: {:#}", err);
+    }
+    time::sleep(POWER_ACTION_DELAY).await;
+
+    let logind = Command::new("busctl")
+        .args([
+            "call",
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+            logind_method,
+            "b",
+            // not interactive, we can't answer a polkit prompt
+            "false",
+        ])
+        .status()
+        .await;
+    match logind {
+        Ok(status) if status.success() => return Ok(()),
+        Ok(status) => warn!("logind refused to {logind_method} ({status}), trying systemctl"),
+        Err(err) => warn!("Could not call logind ({err:#}), trying systemctl"),
+    }
+    let status = Command::new("systemctl")
+        .arg(systemctl_verb)
+        .status()
+        .await
+        .context("Failed to run systemctl")?;
+    if !status.success() {
+        bail!("systemctl {systemctl_verb} failed with {status}");
+    }
+    Ok(())
+}
+
+/// Sends the `ExecExit` of a command
+async fn report_exit(socket: &Socket, id: u64, argv: &[String], res: anyhow::Result<Option<i32>>) {
+    let (code, error) = match res {
+        Ok(code) => (code, None),
+        Err(err) => {
+            warn!("Failed to run {argv:?} for the backend: {:#}", err);
+            (None, Some(format!("{err:#}")))
+        }
+    };
+    let res = send_message(socket, &AgentMessage::ExecExit { id, code, error }).await;
+    if let Err(err) = res {
+        error!(
+            "Couldn't send the exit code of {argv:?} to backend: {:#}",
+            err
+        );
+    }
+}
+
+fn command(
+    argv: &[String],
+    env: BTreeMap<String, String>,
+    cwd: Option<PathBuf>,
+    terminal: bool,
+) -> anyhow::Result<Command> {
+    let argv = if terminal {
+        exec::in_terminal(argv.to_vec()).context("No terminal emulator installed")?
+    } else {
+        argv.to_vec()
+    };
+    let (program, arguments) = argv.split_first().context("Empty command")?;
+    let mut cmd = Command::new(program);
+    cmd.args(arguments).envs(env).stdin(Stdio::null());
+    if let Some(cwd) = cwd {
+        cmd.current_dir(cwd);
+    }
+    Ok(cmd)
+}
+
+/// Runs a command, forwarding its output to the backend, and returns its exit code
+async fn exec(socket: &Socket, id: u64, mut cmd: Command) -> anyhow::Result<Option<i32>> {
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn")?;
+    let stdout = child.stdout.take().context("stdout to be piped")?;
+    let stderr = child.stderr.take().context("stderr to be piped")?;
+    tokio::join!(
+        forward_output(socket, id, OutputStream::Stdout, stdout),
+        forward_output(socket, id, OutputStream::Stderr, stderr)
+    );
+    let status = child.wait().await.context("Failed to wait")?;
+    Ok(status.code())
+}
+
+impl Launched {
+    /// Starts a detached command, it is reported as running until every process of its group
+    /// exited if it has a `name`. The launchers that fork and exit, like `sh -c 'app &'`, are
+    /// followed this way, but not the applications that leave the group with `setsid`
+    async fn launch(&self, mut cmd: Command, name: Option<String>) -> anyhow::Result<()> {
+        // in its own process group to close its children too
+        let mut child = cmd
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .spawn()
+            .context("Failed to spawn")?;
+        let (Some(name), Some(pid)) = (name, child.id()) else {
+            return Ok(());
+        };
+        let application = RunningApplication {
+            pid,
+            name,
+            started_at: unix_timestamp(SystemTime::now()),
+        };
+        self.applications.lock().await.insert(pid, application);
+        self.report().await;
+        let launched = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = child.wait().await {
+                warn!("Failed to wait for the application {pid}: {err}");
+            }
+            // its children may still run, they keep its pid as their process group
+            while signal_group(pid, "0").await.is_ok() {
+                time::sleep(PROCESS_GROUP_POLL_INTERVAL).await;
+            }
+            launched.applications.lock().await.remove(&pid);
+            launched.report().await;
+        });
+        Ok(())
+    }
+
+    /// Sends the running applications, if the backend is able to understand them
+    async fn report(&self) {
+        if !self
+            .backend_capabilities
+            .lock()
+            .await
+            .contains(&Capability::RunningApplications)
+        {
+            return;
+        }
+        let applications = self.applications.lock().await.values().cloned().collect();
+        let msg = AgentMessage::RunningApplications(applications);
+        if let Err(err) = send_message(&self.socket, &msg).await {
+            warn!(
+                "Couldn't send the running applications to backend: {:#}",
+                err
+            );
+        }
+    }
+
+    /// Terminates an application and its children, kills them if they don't exit in time
+    async fn close(&self, pid: u32) -> anyhow::Result<()> {
+        if !self.applications.lock().await.contains_key(&pid) {
+            bail!("It was not opened by the backend or it exited already");
+        }
+        signal_group(pid, "TERM").await?;
+        let deadline = Instant::now() + CLOSE_APPLICATION_TIMEOUT;
+        while Instant::now() < deadline {
+            time::sleep(Duration::from_millis(500)).await;
+            if !self.applications.lock().await.contains_key(&pid) {
+                return Ok(());
+            }
+        }
+        warn!("The application {pid} is still running, killing it");
+        signal_group(pid, "KILL").await
+    }
+}
+
+/// Sends `signal` to every process of the group `pgid`
+async fn signal_group(pgid: u32, signal: &str) -> anyhow::Result<()> {
+    let status = Command::new("kill")
+        .arg(format!("-{signal}"))
+        .arg("--")
+        .arg(format!("-{pgid}"))
+        // the group being gone is expected, the status is enough
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .context("Failed to run kill")?;
+    if !status.success() {
+        bail!("kill -{signal} failed with {status}");
+    }
+    Ok(())
+}
+
+/// Sends the output of a command line by line, the command keeps running if we are disconnected
+async fn forward_output<R>(socket: &Socket, id: u64, stream: OutputStream, output: R)
+where
+    R: AsyncRead + Unpin,
+{
+    let mut output = BufReader::new(output);
+    let mut line = vec![];
+    loop {
+        line.clear();
+        match output.read_until(b'\n', &mut line).await {
+            Ok(0) => break,
+            Ok(_len) => {
+                let msg = AgentMessage::ExecOutput {
+                    id,
+                    stream,
+                    data: String::from_utf8_lossy(&line).into_owned(),
+                };
+                if let Err(err) = send_message(socket, &msg).await {
+                    debug!("Lost the output of command {id}: {:#}", err);
+                }
+            }
+            Err(err) => {
+                warn!("Could not read the output of command {id}: {:#}", err);
+                break;
+            }
+        }
+    }
+}
+
+async fn open_vdi(socket: &Socket, vdi: &VdiSession, start_vdi_cmd: &str) -> anyhow::Result<()> {
     let certificate_hash_path: PathBuf = "/tmp/sanzu/webtransport-cert-hash.txt".into();
     fs::remove_file(&certificate_hash_path).with_context(|| {
         format!(
@@ -212,9 +1004,15 @@ async fn open_vdi(socket: &Socket, start_vdi_cmd: &str) -> anyhow::Result<()> {
         let _ev = stream.next();
         if let Some(hash) = fs::read_to_string(&certificate_hash_path)
             .ok()
-            .and_then(|hash_str| serde_json::from_str(&hash_str).ok())
+            .and_then(|hash_str| {
+                serde_json::from_str::<WebtransportCertificateHash>(&hash_str).ok()
+            })
         {
-            send_message(socket, &AgentMessage::VdiCertificateHash(hash)).await?;
+            *vdi.lock().await = Some(hash.clone());
+            // if we are disconnected, the hash will be sent in the next hello
+            if let Err(err) = send_message(socket, &AgentMessage::VdiCertificateHash(hash)).await {
+                warn!("Couldn't send vdi certificate hash to backend: {:#}", err);
+            }
             break;
         }
     }
@@ -226,6 +1024,7 @@ async fn open_vdi(socket: &Socket, start_vdi_cmd: &str) -> anyhow::Result<()> {
 
 async fn connect<R>(
     request: R,
+    tls: Arc<rustls::ClientConfig>,
 ) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, Response), Error>
 where
     R: IntoClientRequest + Unpin,
@@ -242,38 +1041,10 @@ where
         })
         .context("Unexpected url scheme")?;
 
-    let config = rustls::ClientConfig::builder()
-        .with_safe_defaults()
-        .with_custom_certificate_verifier(SkipServerVerification::new())
-        .with_no_client_auth();
-    let ccc = Some(Connector::Rustls(std::sync::Arc::new(config)));
-
     let addr = format!("{domain}:{port}");
     let socket = TcpStream::connect(addr).await?;
 
-    client_async_tls_with_config(request, socket, None, ccc)
+    client_async_tls_with_config(request, socket, None, Some(Connector::Rustls(tls)))
         .await
         .context("Could not upgrade to websocket")
 }
-
-struct SkipServerVerification;
-
-impl SkipServerVerification {
-    fn new() -> std::sync::Arc<Self> {
-        std::sync::Arc::new(Self)
-    }
-}
-
-impl rustls::client::ServerCertVerifier for SkipServerVerification {
-    fn verify_server_cert(
-        &self,
-        _end_entity: &rustls::Certificate,
-        _intermediates: &[rustls::Certificate],
-        _server_name: &rustls::ServerName,
-        _scts: &mut dyn Iterator<Item = &[u8]>,
-        _ocsp_response: &[u8],
-        _now: std::time::SystemTime,
-    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
-        Ok(rustls::client::ServerCertVerified::assertion())
-    }
-}
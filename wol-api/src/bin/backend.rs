@@ -1,4 +1,5 @@
 use std::{
+    mem,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
     sync::Arc,
@@ -11,8 +12,10 @@ use warp::{reply, Filter as _};
 use wol_relay_server::{
     cache::{self, cache_images_from_web},
     config::{self},
-    consts::{API_PATH, CONFIG_AUTO_RELOAD},
+    consts::{ADMIN_LISTENING_ADDR, API_PATH, CONFIG_AUTO_RELOAD},
     machine::{self, service::StoreInner},
+    misc::dirs,
+    monitoring::{self, MONITORING},
 };
 
 use clap::Parser;
@@ -96,7 +99,12 @@ async fn main() -> anyhow::Result<()> {
 
     let listening_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 3030);
     println!("Listening on http://{listening_addr}");
-    let store = Arc::new(sync::Mutex::new(StoreInner::new(&config.lock().unwrap())?));
+    let store = Arc::new(sync::Mutex::new(StoreInner::new(
+        &config.lock().unwrap(),
+        dirs.data_dir(),
+    )?));
+    println!("Listening for admin requests on http://{ADMIN_LISTENING_ADDR}");
+    tokio::spawn(warp::serve(machine::api::admin::handlers(&store)).run(ADMIN_LISTENING_ADDR));
 
     // TODO: this doesn't need to be a select anymore, just spawn each tasks
     loop {
@@ -109,7 +117,12 @@ async fn main() -> anyhow::Result<()> {
             .or(machine_api)
             .or(image_cache.clone())
             .with(&cors);
-        let routes = warp::path(API_PATH.strip_prefix("/").unwrap()).and(routes);
+        let routes = warp::path(API_PATH.strip_prefix("/").unwrap())
+            .and(routes)
+            .or(monitoring::handler(store.clone()))
+            .with(warp::log::custom(|info| {
+                MONITORING.record_http_request(info.method(), info.status());
+            }));
         tokio::select! {
             biased;
 
@@ -119,8 +132,12 @@ async fn main() -> anyhow::Result<()> {
                     Ok(cached_config) => *config.lock().unwrap() = cached_config,
                     Err(e) => log::error!("{}", e.context("Failed to cache images")),
                 };
-                let new_store = StoreInner::new(&config.lock().unwrap())?;
-                *store.lock().await = new_store;
+                let mut new_store = StoreInner::new(&config.lock().unwrap(), dirs.data_dir())?;
+                let mut current = store.lock().await;
+                // the pending pairing requests are only kept in memory
+                mem::swap(&mut new_store.pairings, &mut current.pairings);
+                *current = new_store;
+                drop(current);
                 v.unwrap();
             },
             _ = warp::serve(routes).run(listening_addr) => {},
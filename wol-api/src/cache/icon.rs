@@ -1,18 +1,24 @@
-use anyhow::Context as _;
+use anyhow::{bail, Context as _};
 use image::{imageops::FilterType, DynamicImage};
 use itertools::Itertools as _;
 use log::debug;
+use std::{ffi::OsStr, path::Path};
 use tokio::fs;
 
 use crate::{
     cache::{download_image, searxng_api, IMAGE_SIZE},
+    config::IconProviderCfg,
     misc::dirs,
     utils::comparable_floats::ComparableFloats,
 };
 
 use super::{searxng_api::SearchResult, url_to_filename, CACHE_SUBFOLDER};
 
-pub async fn cache_find_icon(application_name: &str) -> anyhow::Result<String> {
+/// Url of the icon of `application_name` found by `provider`, `None` if it is disabled
+pub async fn cache_find_icon(
+    application_name: &str,
+    provider: &IconProviderCfg,
+) -> anyhow::Result<Option<String>> {
     let cache_dir = dirs.cache_dir().join(CACHE_SUBFOLDER);
     fs::create_dir_all(&cache_dir).await?;
     let key = url_to_filename(application_name);
@@ -20,17 +26,20 @@ pub async fn cache_find_icon(application_name: &str) -> anyhow::Result<String> {
     let resized_filename = cache_dir.join(&resized_filename_key);
 
     if !resized_filename.exists() {
-        let image = find_icon(application_name)
+        let Some(image) = find_icon(application_name, provider)
             .await
             .with_context(|| {
                 format!("Failed to find an icon for application `{application_name}`")
             })?
-            .resize(IMAGE_SIZE, IMAGE_SIZE, FilterType::CatmullRom);
+        else {
+            return Ok(None);
+        };
         image
+            .resize(IMAGE_SIZE, IMAGE_SIZE, FilterType::CatmullRom)
             .save(&resized_filename)
             .context("Failed to write the resized image")?;
     }
-    Ok(format!("/api/cache/images/{resized_filename_key}"))
+    Ok(Some(format!("/api/cache/images/{resized_filename_key}")))
 }
 
 struct IconMetadata {
@@ -134,8 +143,21 @@ fn calculate_icon_score(search_result: &SearchResult) -> f32 {
     squariness_score + format_score + search_engine_score + resolution_matching_score
 }
 
-async fn find_icon(application_name: &str) -> anyhow::Result<DynamicImage> {
-    // 1. send request to searxng api eg https://search.eldolfin.top/search?q=!images+satisfactory+logo+square&category_images=
+async fn find_icon(
+    application_name: &str,
+    provider: &IconProviderCfg,
+) -> anyhow::Result<Option<DynamicImage>> {
+    match provider {
+        IconProviderCfg::Disabled => Ok(None),
+        IconProviderCfg::Searxng { url } => search_icon(url, application_name).await.map(Some),
+        IconProviderCfg::Directory { path } => {
+            find_icon_in_dir(path, application_name).await.map(Some)
+        }
+    }
+}
+
+async fn search_icon(searxng_url: &str, application_name: &str) -> anyhow::Result<DynamicImage> {
+    // 1. send request to searxng api eg https://searx.example.com/search?q=!images+satisfactory+logo+square&category_images=
     // 2. rank result by (from the most important to the less important)
     //   - resolution
     //     - it should be close enough to the expected icon size (but larger)
@@ -145,36 +167,156 @@ async fn find_icon(application_name: &str) -> anyhow::Result<DynamicImage> {
     //   - search engine rank
 
     let search_query = format!("{application_name} logo");
-    let response = searxng_api::query_image(search_query).await?;
+    let response = searxng_api::query_image(searxng_url, &search_query).await?;
     let best_icon = response
         .results
         .into_iter()
         .map(IconMetadata::from)
         .max_by_key(|icon| ComparableFloats::from(icon.score))
-        .expect("Searxng to return some results");
+        .with_context(|| format!("Searxng found no image for `{search_query}`"))?;
     debug!("Found best icon with score {}", best_icon.score);
     // debug!("Icon: {:#?}", best_icon.search_result);
     let image = download_image(&best_icon.search_result.img_src).await?;
     Ok(image)
 }
 
+/// Lowercase alphanumeric characters of `name`, so `Visual Studio Code` matches
+/// `visual-studio-code.png`
+fn icon_key(name: &str) -> String {
+    name.chars()
+        .filter(|character| character.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The image of `dir` named like the application, ignoring the case and the punctuation
+async fn find_icon_in_dir(dir: &Path, application_name: &str) -> anyhow::Result<DynamicImage> {
+    let key = icon_key(application_name);
+    let mut entries = fs::read_dir(dir)
+        .await
+        .with_context(|| format!("Failed to read the icon directory `{}`", dir.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .is_some_and(|stem| icon_key(stem) == key)
+        {
+            return image::open(&path)
+                .with_context(|| format!("Failed to load the icon `{}`", path.display()));
+        }
+    }
+    bail!("No icon named `{application_name}` in `{}`", dir.display())
+}
+
 #[cfg(test)]
 mod test {
+    use std::io::Cursor;
+
+    use image::ImageFormat;
     use rstest::rstest;
+    use warp::{reply, Filter as _};
 
-    use crate::cache::icon::find_icon;
+    use super::*;
+    use crate::cache::searxng_api::SearchResponse;
     use crate::test::logfxt;
 
+    fn result(host: &str, filename: &str, resolution: Option<&str>, score: f32) -> SearchResult {
+        SearchResult {
+            img_src: format!("http://{host}/{filename}"),
+            resolution: resolution.map(ToOwned::to_owned),
+            img_format: None,
+            score,
+            category: "images".to_owned(),
+        }
+    }
+
+    fn png(size: u32) -> Vec<u8> {
+        let mut png = Vec::new();
+        DynamicImage::new_rgba8(size, size)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    /// Local searxng instance answering `results` to every search, only `icon.png` exists
+    fn mock_searxng(results: fn(&str) -> Vec<SearchResult>) -> String {
+        let search =
+            warp::path::end()
+                .and(warp::header::<String>("host"))
+                .map(move |host: String| {
+                    reply::json(&SearchResponse {
+                        query: "firefox logo".to_owned(),
+                        results: results(&host),
+                    })
+                });
+        let icon = warp::path!("icon.png").map(|| png(256));
+        let (addr, server) = warp::serve(search.or(icon)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{addr}")
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_search_icon(#[expect(unused_variables, reason = "fixture")] logfxt: ()) {
+        let url = mock_searxng(|host| {
+            vec![
+                result(host, "small.png", Some("16x16"), 1.0),
+                result(host, "unknown.png", None, 1.0),
+                result(host, "wide.png", Some("1024 x 128"), 0.5),
+                result(host, "icon.png", Some("256 x 256"), 0.5),
+            ]
+        });
+        let icon = find_icon("firefox", &IconProviderCfg::Searxng { url })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((icon.width(), icon.height()), (IMAGE_SIZE, IMAGE_SIZE));
+    }
+
     #[rstest]
-    #[case("Discord")]
-    #[case("firefox")]
-    #[case("firefox")]
+    #[case(|_host: &str| vec![], "found no image")]
+    #[case(|host: &str| vec![result(host, "missing.png", Some("256x256"), 1.0)], "Failed to fetch image")]
     #[tokio::test]
-    async fn test_find_icon(
+    async fn test_search_icon_error(
         #[expect(unused_variables, reason = "fixture")] logfxt: (),
-        #[case] appname: String,
-    ) -> anyhow::Result<()> {
-        find_icon(&appname).await?;
-        Ok(())
+        #[case] results: fn(&str) -> Vec<SearchResult>,
+        #[case] expected: &str,
+    ) {
+        let url = mock_searxng(results);
+        let err = find_icon("firefox", &IconProviderCfg::Searxng { url })
+            .await
+            .expect_err("expected the search to fail");
+        assert!(
+            format!("{err:#}").contains(expected),
+            "unexpected error: {err:#}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_icon_in_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("visual-studio-code.png"), png(64)).unwrap();
+        std::fs::write(dir.path().join("firefox.svg"), "<svg/>").unwrap();
+        let provider = IconProviderCfg::Directory {
+            path: dir.path().to_owned(),
+        };
+
+        let icon = find_icon("Visual Studio Code", &provider).await.unwrap();
+        assert_eq!(icon.map(|icon| icon.width()), Some(64));
+        find_icon("firefox", &provider)
+            .await
+            .expect_err("svg icons can't be decoded");
+        find_icon("Discord", &provider)
+            .await
+            .expect_err("there is no icon for discord");
+    }
+
+    #[tokio::test]
+    async fn test_find_icon_disabled() {
+        assert!(find_icon("firefox", &IconProviderCfg::Disabled)
+            .await
+            .unwrap()
+            .is_none());
     }
 }
@@ -1,8 +1,9 @@
 pub mod icon;
 pub mod searxng_api;
 use crate::config;
+use crate::machine::application::IconData;
 use crate::misc::dirs;
-use anyhow::Context as _;
+use anyhow::{bail, Context as _};
 use image::imageops::FilterType;
 use regex::Regex;
 use sha2::{Digest as _, Sha256};
@@ -83,20 +84,32 @@ async fn download_image(url: &str) -> anyhow::Result<image::DynamicImage> {
     Ok(image)
 }
 
-pub fn cache_image(name: &str, icon: image::DynamicImage) -> anyhow::Result<String> {
-    let cache_dir = dirs.cache_dir().join(CACHE_SUBFOLDER);
-    fs::create_dir_all(&cache_dir)?;
-    let key = url_to_filename(name);
-    let resized_filename_key = format!("{key}_resize{IMAGE_SIZE}x{IMAGE_SIZE}.png");
-    let resized_filename = cache_dir.join(&resized_filename_key);
+fn icon_filename(hash: &str) -> String {
+    format!("{hash}.png")
+}
 
-    if !resized_filename.exists() {
-        let image = icon.resize(IMAGE_SIZE, IMAGE_SIZE, FilterType::CatmullRom);
-        image
-            .save(&resized_filename)
-            .context("Failed to write the resized image")?;
+/// Where the icon sent by an agent is served, once it's cached
+pub fn icon_url(hash: &str) -> String {
+    format!("/api/cache/images/{}", icon_filename(hash))
+}
+
+pub fn is_icon_cached(hash: &str) -> bool {
+    dirs.cache_dir()
+        .join(CACHE_SUBFOLDER)
+        .join(icon_filename(hash))
+        .exists()
+}
+
+/// Checks and stores an icon received from an agent
+pub fn cache_icon(icon: &IconData) -> anyhow::Result<()> {
+    if !icon.icon.is_valid() {
+        bail!("Invalid icon hash {:?}", icon.icon.hash);
     }
-    Ok(format!("/api/cache/images/{resized_filename_key}"))
+    let png = icon.decode()?;
+    let cache_dir = dirs.cache_dir().join(CACHE_SUBFOLDER);
+    fs::create_dir_all(&cache_dir)?;
+    fs::write(cache_dir.join(icon_filename(&icon.icon.hash)), png)
+        .context("Failed to write the icon")
 }
 
 #[expect(
@@ -2,8 +2,6 @@ use anyhow::Context as _;
 use serde::Deserialize;
 use serde::Serialize;
 
-const SEARXNG_BASEURL: &str = "https://search.eldolfin.top";
-
 // thanks https://transform.tools/json-to-rust-serde
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -37,18 +35,20 @@ pub struct SearchResult {
     // pub author: Option<String>,
 }
 
-pub async fn query_image(search_query: String) -> anyhow::Result<SearchResponse> {
+/// Searches images on the `SearXNG` instance at `base_url`
+pub async fn query_image(base_url: &str, search_query: &str) -> anyhow::Result<SearchResponse> {
     let resp = reqwest::Client::default()
-        .get(SEARXNG_BASEURL)
+        .get(base_url)
         .query(&[
             ("search", ""),
             ("category_images", ""),
             ("format", "json"),
-            ("q", &search_query),
+            ("q", search_query),
         ])
         .send()
         .await
-        .with_context(|| format!("Failed to query searxng instance `{SEARXNG_BASEURL}`"))?;
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to query searxng instance `{base_url}`"))?;
     // debug!("searxng query response: {:#?}", &resp);
     resp.json()
         .await
@@ -1,14 +1,15 @@
-use anyhow::Context as _;
+use anyhow::{bail, ensure, Context as _};
 use figment::{
     providers::{Format as _, Yaml},
     Figment,
 };
 use futures_util::StreamExt as _;
 use inotify::{Inotify, WatchMask};
+use itertools::Itertools as _;
 use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::File,
     io::Read as _,
     path::PathBuf,
@@ -17,6 +18,8 @@ use std::{
 use tokio::sync::{self, mpsc::Receiver};
 use utoipa::ToSchema;
 
+use crate::agent::heartbeat::HeartbeatCfg;
+
 #[derive(Serialize, Deserialize, Clone, ToSchema, Debug, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 #[serde(deny_unknown_fields)]
@@ -27,6 +30,104 @@ pub struct MachineCfg {
     pub mac: String,
     #[serde(default)]
     pub tasks: Vec<TaskCfg>,
+    /// Groups the machine belongs to, used for bulk actions
+    #[serde(default)]
+    #[schema(example = json!(["gaming", "living-room"]))]
+    pub tags: Vec<String>,
+    /// Machines that have to be on before this one is woken up
+    #[serde(default)]
+    #[schema(example = json!(["nas"]))]
+    pub depends_on: Vec<String>,
+    /// Automatically shut the machine down when nobody is using it
+    #[serde(default)]
+    pub idle_shutdown: Option<IdleShutdownCfg>,
+    /// How the applications of the agent are shown in the panel
+    #[serde(default)]
+    pub applications: ApplicationsCfg,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct ApplicationsCfg {
+    /// Pinned in a first group, they are still shown in their usual group
+    #[serde(default)]
+    #[schema(example = json!(["Firefox", "Satisfactory"]))]
+    pub favourites: Vec<String>,
+    /// Shown before the groups of the categories, the applications listed are taken out of them
+    #[serde(default)]
+    pub groups: Vec<CustomGroupCfg>,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct CustomGroupCfg {
+    #[schema(example = "Work")]
+    pub name: String,
+    /// Names of the applications
+    #[schema(example = json!(["Slack", "Thunderbird"]))]
+    pub applications: Vec<String>,
+}
+
+/// Group of the applications having one of `categories`, see
+/// <https://specifications.freedesktop.org/menu-spec/latest/category-registry.html>
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CategoryGroupCfg {
+    pub name: String,
+    pub categories: Vec<String>,
+}
+
+/// The main categories, the first group matching one of the categories of an application wins
+/// so the specific ones come first, eg: `Settings;System;` is in "Settings"
+fn default_application_groups() -> Vec<CategoryGroupCfg> {
+    [
+        ("Games", &["Game"][..]),
+        ("Development", &["Development"]),
+        ("Graphics", &["Graphics"]),
+        ("Multimedia", &["AudioVideo", "Audio", "Video"]),
+        ("Office", &["Office"]),
+        ("Internet", &["Network"]),
+        ("Education", &["Education", "Science"]),
+        ("Settings", &["Settings"]),
+        ("System", &["System"]),
+        ("Utilities", &["Utility"]),
+    ]
+    .into_iter()
+    .map(|(name, categories)| CategoryGroupCfg {
+        name: name.to_owned(),
+        categories: categories
+            .iter()
+            .map(|&category| category.to_owned())
+            .collect(),
+    })
+    .collect()
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct IdleShutdownCfg {
+    /// Minutes the machine has to stay idle before being shut down
+    #[schema(example = 30)]
+    pub after_minutes: u64,
+    /// Cpu usage (in percent) under which the machine is considered idle
+    #[serde(default = "default_idle_cpu_threshold")]
+    #[schema(example = 5)]
+    pub cpu_threshold: u8,
+    /// Seconds between the warning sent to the desktop session and the actual shutdown
+    #[serde(default = "default_idle_grace_period")]
+    #[schema(example = 300)]
+    pub grace_period_secs: u64,
+}
+
+const fn default_idle_cpu_threshold() -> u8 {
+    5
+}
+
+const fn default_idle_grace_period() -> u64 {
+    300
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
@@ -40,6 +141,83 @@ pub struct Ssh {
 pub struct Config {
     pub machines: HashMap<String, MachineCfg>,
     pub ssh: Ssh,
+    #[serde(default)]
+    pub agent_heartbeat: HeartbeatCfg,
+    /// Groups of the applications by category, in the order they are shown
+    #[serde(default = "default_application_groups")]
+    pub application_groups: Vec<CategoryGroupCfg>,
+    /// Where the icons the agents couldn't send are searched
+    #[serde(default)]
+    pub icon_provider: IconProviderCfg,
+}
+
+/// Source of the icons of the applications without one, eg: `icon_provider: disabled` or
+/// `icon_provider: { searxng: { url: "https://searx.example.com" } }`
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum IconProviderCfg {
+    /// The applications are shown without an icon
+    #[default]
+    Disabled,
+    /// Image search of a `SearXNG` instance, its json format must be enabled
+    Searxng { url: String },
+    /// Images named after the applications, eg: `firefox.png` or `visual-studio-code.jpg`
+    Directory { path: PathBuf },
+}
+
+impl Config {
+    /// Checks that machines only depend on existing machines, without cycles, and that their
+    /// application groups have unique names
+    pub fn validate(&self) -> anyhow::Result<()> {
+        fn visit<'config>(
+            config: &'config Config,
+            name: &'config str,
+            path: &mut Vec<&'config str>,
+            checked: &mut HashSet<&'config str>,
+        ) -> anyhow::Result<()> {
+            if checked.contains(name) {
+                return Ok(());
+            }
+            if let Some(start) = path.iter().position(|visited| *visited == name) {
+                bail!(
+                    "Dependency cycle between machines: {} -> {name}",
+                    path[start..].join(" -> ")
+                );
+            }
+            path.push(name);
+            for dependency in &config.machines[name].depends_on {
+                visit(config, dependency, path, checked)?;
+            }
+            path.pop();
+            checked.insert(name);
+            Ok(())
+        }
+
+        for (name, machine) in &self.machines {
+            if let Some(group) = machine
+                .applications
+                .groups
+                .iter()
+                .map(|group| &group.name)
+                .duplicates()
+                .next()
+            {
+                bail!("Machine `{name}` has several application groups named `{group}`");
+            }
+            for dependency in &machine.depends_on {
+                ensure!(
+                    self.machines.contains_key(dependency),
+                    "Machine `{name}` depends on unknown machine `{dependency}`"
+                );
+            }
+        }
+        let mut checked = HashSet::new();
+        for name in self.machines.keys() {
+            visit(self, name, &mut Vec::new(), &mut checked)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
@@ -61,7 +239,7 @@ pub fn open(
     auto_reload: bool,
 ) -> anyhow::Result<(Arc<Mutex<Config>>, Receiver<()>)> {
     fn load_config(path: &PathBuf) -> Result<Config, anyhow::Error> {
-        let config = Figment::new()
+        let config: Config = Figment::new()
             .merge(Yaml::file(path))
             .extract()
             .with_context(|| {
@@ -72,6 +250,9 @@ pub fn open(
                 });
                 format!("Failed to parse config file at {}", path.display())
             })?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file at {}", path.display()))?;
         debug!("config: {config:?}");
         Ok(config)
     }
@@ -1,7 +1,40 @@
-use std::time::Duration;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
 
 pub const API_PATH: &str = "/api";
+/// Only reachable from the backend host, the pairing of agents is done there
+pub const ADMIN_LISTENING_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 3031);
 pub const MACHINE_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
 pub const TIME_BEFORE_ASSUMING_WOL_FAILED: Duration = Duration::from_secs(60);
+/// The agent waits a few seconds before a power action, so users can save their work
+pub const POWER_ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+pub const TIME_BEFORE_ASSUMING_POWER_ACTION_FAILED: Duration = Duration::from_secs(90);
+/// Commands run by the agent, eg: tasks, are failed if they don't exit in time
+#[expect(
+    clippy::duration_suboptimal_units,
+    reason = "`Duration::from_mins` is too recent for the rust of the nix build"
+)]
+pub const AGENT_EXEC_TIMEOUT: Duration = Duration::from_secs(15 * 60);
 pub const CONFIG_AUTO_RELOAD: bool = true;
 pub const SEND_STATE_INTERVAL: Duration = Duration::from_millis(100);
+pub const IDLE_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+/// Metrics samples kept per machine, one hour with the default agent interval
+pub const METRICS_HISTORY_LEN: usize = 240;
+/// Icons per `AgentMessage::Icons`, a 128x128 PNG is at most ~90KB in base64
+pub const ICONS_PER_MESSAGE: usize = 32;
+/// A launch of an application weighs half as much in the search ranking after a week
+#[expect(
+    clippy::duration_suboptimal_units,
+    reason = "`Duration::from_hours` is too recent for the rust of the nix build"
+)]
+pub const FRECENCY_HALF_LIFE: Duration = Duration::from_secs(7 * 24 * 3600);
+/// A pairing request is forgotten if its agent doesn't retry for this long
+#[expect(
+    clippy::duration_suboptimal_units,
+    reason = "`Duration::from_mins` is too recent for the rust of the nix build"
+)]
+pub const PAIRING_REQUEST_TTL: Duration = Duration::from_secs(10 * 60);
+/// Pairing requests kept per machine, the new ones are rejected past it
+pub const MAX_PENDING_PAIRINGS: usize = 32;
@@ -3,6 +3,7 @@ pub mod cache;
 pub mod config;
 pub mod consts;
 pub mod machine;
+pub mod monitoring;
 pub mod utils;
 
 pub mod misc {
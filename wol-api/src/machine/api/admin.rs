@@ -0,0 +1,148 @@
+//! Admin routes, served on [`crate::consts::ADMIN_LISTENING_ADDR`] instead of the public api.
+//!
+//! Anyone on the network can reach the public api, so the agents would be able to approve their
+//! own pairing request with it.
+
+use core::convert::Infallible;
+
+use http::StatusCode;
+use log::info;
+use serde::Deserialize;
+use utoipa::{IntoParams, OpenApi};
+use warp::{
+    http,
+    reject::Rejection,
+    reply::{self, Reply},
+    Filter,
+};
+
+use super::responses::PairingRequestsResponse;
+use crate::machine::service::Store;
+
+#[derive(OpenApi)]
+#[openapi(paths(list_pairings, approve_pairing, unpair_agent))]
+pub struct AdminApi;
+
+#[utoipa::path(
+    get,
+    path = "/agent/pairings",
+    responses(
+        (status = 200, description = "List agents waiting to be paired", body = PairingRequestsResponse)
+    )
+)]
+pub async fn list_pairings(store: Store) -> Result<impl Reply, Infallible> {
+    let requests = store.lock().await.pairings.pending();
+    Ok(reply::json(&PairingRequestsResponse { requests }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PairingQuery {
+    /// Code shown in the logs of the agent
+    #[param(example = "482-913")]
+    code: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/agent/pairings/{name}/approve",
+    responses(
+        (status = 200, description = "The agent will be paired on its next connection"),
+        (status = 404, description = "No agent with this code is waiting to be paired")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine of the agent"),
+        PairingQuery
+    ),
+)]
+pub async fn approve_pairing(
+    store: Store,
+    name: String,
+    query: PairingQuery,
+) -> Result<impl Reply, Infallible> {
+    let res = store.lock().await.pairings.approve(&name, &query.code);
+    match res {
+        Ok(()) => {
+            info!("Approved the pairing of the agent of `{name}`");
+            Ok(reply::with_status(
+                format!("The agent of `{name}` will be paired on its next connection"),
+                StatusCode::OK,
+            ))
+        }
+        Err(msg) => Ok(reply::with_status(msg, StatusCode::NOT_FOUND)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/{name}/agent/unpair",
+    responses(
+        (status = 200, description = "Forgot the agent secret and disconnected it"),
+        (status = 404, description = "Machine does not exist or its agent is not paired"),
+        (status = 500, description = "Could not save the agent secrets")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine")
+    ),
+)]
+#[expect(clippy::significant_drop_tightening, reason = "todo fix mais flemme")]
+pub async fn unpair_agent(store: Store, name: String) -> Result<impl Reply, Infallible> {
+    let mut lock = store.lock().await;
+    if lock.by_name(&name).is_none() {
+        return Ok(reply::with_status(
+            "Machine does not exist".to_owned(),
+            http::StatusCode::NOT_FOUND,
+        ));
+    }
+    match lock.pairings.unpair(&name) {
+        Ok(true) => {
+            if let Some(machine) = lock.by_name_mut(&name) {
+                machine.disconnect_agent();
+            }
+            info!("Unpaired the agent of `{name}`");
+            Ok(reply::with_status(
+                format!("Unpaired the agent of `{name}`"),
+                StatusCode::OK,
+            ))
+        }
+        Ok(false) => Ok(reply::with_status(
+            format!("The agent of `{name}` is not paired"),
+            StatusCode::NOT_FOUND,
+        )),
+        Err(err) => Ok(reply::with_status(
+            format!("{err:#}"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+    }
+}
+
+/// Routes to pair and unpair agents, they must not be reachable by the agents
+pub fn handlers(store: &Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let api_doc = warp::path!("api-doc.json")
+        .and(warp::get())
+        .map(|| reply::json(&AdminApi::openapi()));
+    let list_pairings = {
+        let store = store.clone();
+        warp::path!("agent" / "pairings")
+            .and(warp::get())
+            .and_then(move || list_pairings(store.clone()))
+    };
+    let approve_pairing = {
+        let store = store.clone();
+        warp::path!("agent" / "pairings" / String / "approve")
+            .and(warp::post())
+            .and(warp::query())
+            .and_then(move |name: String, query| approve_pairing(store.clone(), name, query))
+    };
+    let unpair_agent = {
+        let store = store.clone();
+        warp::path!(String / "agent" / "unpair")
+            .and(warp::post())
+            .and_then(move |name: String| unpair_agent(store.clone(), name))
+    };
+
+    api_doc
+        .or(list_pairings)
+        .or(approve_pairing)
+        .or(unpair_agent)
+}
@@ -1,25 +1,44 @@
+pub mod admin;
 pub mod responses;
-use super::service::{recv_agent_msg, State, Store, Task};
+use super::service::{self, recv_agent_msg, GroupTask, Machine, OpenApplicationError, Store, Task};
 use crate::{
-    agent::messages::AgentMessage,
+    agent::{
+        messages::{
+            AgentHello, AgentMessage, PowerAction, ServerMessage, CAPABILITIES,
+            MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+        },
+        pairing::Authentication,
+    },
     config::Config,
-    consts::{MACHINE_REFRESH_INTERVAL, SEND_STATE_INTERVAL, TIME_BEFORE_ASSUMING_WOL_FAILED},
+    consts::{MACHINE_REFRESH_INTERVAL, SEND_STATE_INTERVAL},
     machine::ssh,
+    utils::time::unix_timestamp,
+};
+use responses::{
+    CloseApplicationError, GroupActionResponse, ListMachineResponse, MetricsResponse, OpenVdiError,
+    RunningApplicationsResponse, SearchApplicationsResponse, SessionsResponse,
 };
-use responses::{ListMachineResponse, OpenVdiError};
 use urlencoding;
 
+use anyhow::Context as _;
 use core::convert::Infallible;
 use futures_util::{SinkExt as _, StreamExt as _};
 use http::status::StatusCode;
-use log::{debug, error};
-use std::{future::Future, pin::Pin};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    future::Future,
+    pin::Pin,
+    time::{Duration, SystemTime},
+};
 use tokio::time;
-use utoipa::OpenApi;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 use warp::{
     body::json,
     filters::ws::{Message, WebSocket},
     http,
+    hyper::body::Bytes,
     reject::Rejection,
     reply::{self, Reply},
     ws, Filter,
@@ -27,7 +46,30 @@ use warp::{
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(list, wake, shutdown, open_vdi, task, list_ws, agent, open_application),
+    paths(
+        list,
+        wake,
+        shutdown,
+        cancel_shutdown,
+        reboot,
+        suspend,
+        open_vdi,
+        task,
+        list_ws,
+        agent,
+        open_application,
+        open_application_action,
+        running_applications,
+        search_applications,
+        graphical_sessions,
+        close_application,
+        postpone_idle_shutdown,
+        veto_idle_shutdown,
+        group_wake,
+        group_shutdown,
+        group_task,
+        machine_metrics
+    ),
     nest(
         (path = "/ssh", api = ssh::api::Api)
     ),
@@ -76,6 +118,17 @@ pub async fn list_ws(store: Store, websocket: WebSocket) {
     }
 }
 
+fn supported_protocol(hello: &AgentHello) -> bool {
+    let supported = hello.protocol_version >= MIN_PROTOCOL_VERSION;
+    if !supported {
+        warn!(
+            "Refused the agent of `{}`: its protocol version {} is older than {MIN_PROTOCOL_VERSION}, update it",
+            hello.machine_name, hello.protocol_version
+        );
+    }
+    supported
+}
+
 #[utoipa::path(
     get,
     path = "/agent",
@@ -102,10 +155,77 @@ pub async fn agent(store: Store, mut websocket: WebSocket) {
         }
     };
 
+    let name = agent_hello.machine_name.clone();
+    // it would not understand a `Rejected` either
+    if !supported_protocol(&agent_hello) {
+        return;
+    }
     let mut lock = store.lock().await;
-    if let Some(machine) = lock.by_name_mut(&agent_hello.machine_name) {
+    let known = lock.by_name(&name).is_some();
+    let authentication = if known {
+        lock.pairings.authenticate(
+            &name,
+            agent_hello.credentials.as_ref(),
+            unix_timestamp(SystemTime::now()),
+        )
+    } else {
+        Err(format!(
+            "Unknown machine `{name}`, add it to the backend config first"
+        ))
+    };
+    drop(lock);
+    match authentication {
+        Ok(Authentication::Authenticated) => {}
+        Ok(Authentication::Paired(secret)) => {
+            info!("The agent of `{name}` is now paired");
+            if let Err(err) =
+                send_server_msg(&mut websocket, &ServerMessage::Paired { secret }).await
+            {
+                error!("Could not send its secret to the agent of `{name}`: {err:#}");
+                return;
+            }
+        }
+        Err(reason) => {
+            warn!("Rejected the agent of `{name}`: {reason}");
+            let rejected = ServerMessage::Rejected {
+                reason,
+                pairing_required: known,
+            };
+            if let Err(err) = send_server_msg(&mut websocket, &rejected).await {
+                debug!("Could not tell the agent of `{name}` it was rejected: {err:#}");
+            }
+            let _res = websocket.close().await;
+            return;
+        }
+    }
+
+    let welcome = ServerMessage::Welcome {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: CAPABILITIES.to_vec(),
+    };
+    if let Err(err) = send_server_msg(&mut websocket, &welcome).await {
+        error!("Could not welcome the agent of `{name}`: {err:#}");
+        return;
+    }
+    info!(
+        "Agent of `{name}` connected with protocol version {} (backend: {PROTOCOL_VERSION})",
+        agent_hello.protocol_version
+    );
+
+    let mut lock = store.lock().await;
+    let heartbeat = lock.heartbeat;
+    if let Some(machine) = lock.by_name_mut(&name) {
+        machine.set_connection(websocket, agent_hello.capabilities, heartbeat);
+        // once connected, to request the missing icons
         machine.set_applications(agent_hello.applications).await;
-        machine.set_connection(websocket);
+        if let Some(hash) = agent_hello.vdi_certificate_hash {
+            debug!(
+                "Agent of `{}` reconnected with a vdi opened",
+                machine.infos.name
+            );
+            machine.infos.vdi_opened = true;
+            machine.infos.vdi_cert_hash = Some(hash);
+        }
         debug!(
             "Machine `{}` successfully sent its list of applications",
             machine.infos.name
@@ -118,33 +238,220 @@ pub async fn agent(store: Store, mut websocket: WebSocket) {
     }
 }
 
+async fn send_server_msg(websocket: &mut WebSocket, msg: &ServerMessage) -> anyhow::Result<()> {
+    websocket
+        .send(Message::text(serde_json::to_string(msg)?))
+        .await
+        .context("Could not send message to agent")
+}
+
+#[utoipa::path(
+    get,
+    path = "/{name}/metrics",
+    responses(
+        (status = 200, description = "Latest metrics sent by the agent of the machine", body = MetricsResponse),
+        (status = 404, description = "Machine does not exist")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine")
+    ),
+)]
+pub async fn machine_metrics(store: Store, name: String) -> Result<Box<dyn Reply>, Infallible> {
+    let Some(samples) = store.lock().await.by_name(&name).map(Machine::metrics) else {
+        return Ok(Box::new(reply::with_status(
+            "Machine does not exist",
+            http::StatusCode::NOT_FOUND,
+        )));
+    };
+    Ok(Box::new(reply::json(&MetricsResponse { samples })))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ShutdownQuery {
+    /// Seconds to wait before shutting down, logged in users are warned in the meantime
+    #[param(example = 300)]
+    delay: Option<u64>,
+    /// Shutdown even if running machines depend on this one
+    #[serde(default)]
+    force: bool,
+}
+
+impl ShutdownQuery {
+    async fn apply(&self, machine: &mut Machine, dry_run: bool) -> String {
+        match self.delay {
+            Some(delay) if delay > 0 => {
+                machine
+                    .schedule_shutdown(Duration::from_secs(delay), false, dry_run)
+                    .await
+            }
+            _ => machine.shutdown(dry_run).await,
+        }
+    }
+}
+
+fn dependents_error(name: &str, dependents: &[String]) -> String {
+    format!(
+        "Machine {name} is needed by running machines: {} (use force=true to shut it down anyway)",
+        dependents.join(", ")
+    )
+}
+
 #[utoipa::path(
     post,
     path = "/{name}/shutdown",
     responses(
         (status = 200, description = "Shutdown the machine successfully"),
-        (status = 404, description = "Machine does not exist")
+        (status = 404, description = "Machine does not exist"),
+        (status = 409, description = "Running machines depend on this one")
     ),
     params(
-        ("name" = String, Path, description = "Name of the machine to shutdown")
+        ("name" = String, Path, description = "Name of the machine to shutdown"),
+        ShutdownQuery
     ),
 )]
 #[expect(clippy::significant_drop_tightening, reason = "todo fix mais flemme")]
-pub async fn shutdown(store: Store, name: String, dry_run: bool) -> Result<impl Reply, Infallible> {
+pub async fn shutdown(
+    store: Store,
+    name: String,
+    dry_run: bool,
+    query: ShutdownQuery,
+) -> Result<impl Reply, Infallible> {
     let mut lock = store.lock().await;
+    let dependents = lock.running_dependents(&name);
     let Some(machine) = lock.by_name_mut(&name) else {
         return Ok(reply::with_status(
             "Machine does not exist".to_owned(),
             http::StatusCode::NOT_FOUND,
         ));
     };
+    if !query.force && !dependents.is_empty() {
+        return Ok(reply::with_status(
+            dependents_error(&name, &dependents),
+            StatusCode::CONFLICT,
+        ));
+    }
 
     Ok(reply::with_status(
-        machine.shutdown(dry_run).await,
+        query.apply(machine, dry_run).await,
         StatusCode::OK,
     ))
 }
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PowerQuery {
+    /// Go on even if running machines depend on this one
+    #[serde(default)]
+    force: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/{name}/reboot",
+    responses(
+        (status = 200, description = "Rebooting the machine"),
+        (status = 404, description = "Machine does not exist"),
+        (status = 409, description = "Running machines depend on this one")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine to reboot"),
+        PowerQuery
+    ),
+)]
+pub async fn reboot(
+    store: Store,
+    name: String,
+    dry_run: bool,
+    query: PowerQuery,
+) -> Result<impl Reply, Infallible> {
+    power(store, name, PowerAction::Reboot, dry_run, query).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/{name}/suspend",
+    responses(
+        (status = 200, description = "Suspending the machine"),
+        (status = 404, description = "Machine does not exist"),
+        (status = 409, description = "Running machines depend on this one")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine to suspend"),
+        PowerQuery
+    ),
+)]
+pub async fn suspend(
+    store: Store,
+    name: String,
+    dry_run: bool,
+    query: PowerQuery,
+) -> Result<impl Reply, Infallible> {
+    power(store, name, PowerAction::Suspend, dry_run, query).await
+}
+
+#[expect(
+    clippy::significant_drop_tightening,
+    reason = "the machine is used until the end"
+)]
+async fn power(
+    store: Store,
+    name: String,
+    action: PowerAction,
+    dry_run: bool,
+    query: PowerQuery,
+) -> Result<reply::WithStatus<String>, Infallible> {
+    let mut lock = store.lock().await;
+    let dependents = lock.running_dependents(&name);
+    let Some(machine) = lock.by_name_mut(&name) else {
+        return Ok(reply::with_status(
+            "Machine does not exist".to_owned(),
+            http::StatusCode::NOT_FOUND,
+        ));
+    };
+    if !query.force && !dependents.is_empty() {
+        return Ok(reply::with_status(
+            dependents_error(&name, &dependents),
+            StatusCode::CONFLICT,
+        ));
+    }
+    Ok(reply::with_status(
+        machine.power(action, dry_run).await,
+        StatusCode::OK,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{name}/shutdown/cancel",
+    responses(
+        (status = 200, description = "Cancelled the pending shutdown successfully"),
+        (status = 404, description = "Machine does not exist"),
+        (status = 500, description = "The machine has no pending shutdown")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine")
+    ),
+)]
+#[expect(clippy::significant_drop_tightening, reason = "todo fix mais flemme")]
+pub async fn cancel_shutdown(
+    store: Store,
+    name: String,
+    dry_run: bool,
+) -> Result<impl Reply, Infallible> {
+    let mut lock = store.lock().await;
+    let Some(machine) = lock.by_name_mut(&name) else {
+        return Ok(reply::with_status(
+            "Machine does not exist".to_owned(),
+            http::StatusCode::NOT_FOUND,
+        ));
+    };
+    match machine.cancel_shutdown(dry_run).await {
+        Ok(msg) => Ok(reply::with_status(msg, StatusCode::OK)),
+        Err(msg) => Ok(reply::with_status(msg, StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
 #[utoipa::path(
     post,
     path = "/{name}/open_vdi",
@@ -211,38 +518,290 @@ pub async fn task(
     }
 }
 
+#[derive(Deserialize, ToSchema, Default)]
+pub struct OpenApplicationBody {
+    /// Files or urls to open, passed to the `%f`, `%F`, `%u` or `%U` of the `Exec` key
+    #[serde(default)]
+    #[schema(example = json!(["https://example.com"]))]
+    arguments: Vec<String>,
+    /// Id of the graphical session to open the application in, from `/{name}/sessions`.
+    /// Needed if several sessions are active, the agent's session is used when omitted
+    #[serde(default)]
+    #[schema(example = "2")]
+    session: Option<String>,
+}
+
 #[utoipa::path(
     post,
     path = "/{name}/open_application/{application_name}",
+    request_body(content = Option<OpenApplicationBody>, description = "Optional, to open files or urls"),
     responses(
         (status = 200, description = "Application opened successfully"),
+        (status = 400, description = "Invalid body or url encoding"),
+        (status = 404, description = "Machine or application does not exist"),
     ),
     params(
         ("name" = String, Path, description = "Name of the machine"),
         ("application_name" = String, Path, description = "Name of the application")
     ),
 )]
-#[expect(clippy::significant_drop_tightening, reason = "todo fix mais flemme")]
 pub async fn open_application(
     store: Store,
     name: String,
     application_name: String,
     dry_run: bool,
+    body: Bytes,
 ) -> Result<impl Reply, Infallible> {
-    let application_name = urlencoding::decode(&application_name).unwrap();
+    Ok(open(store, name, application_name, None, dry_run, body).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/{name}/open_application/{application_name}/action/{action}",
+    request_body(content = Option<OpenApplicationBody>, description = "Optional, to open files or urls"),
+    responses(
+        (status = 200, description = "Application action opened successfully"),
+        (status = 400, description = "Invalid body or url encoding"),
+        (status = 404, description = "Machine, application or action does not exist"),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine"),
+        ("application_name" = String, Path, description = "Name of the application"),
+        ("action" = String, Path, description = "Id of the desktop action")
+    ),
+)]
+pub async fn open_application_action(
+    store: Store,
+    name: String,
+    application_name: String,
+    action: String,
+    dry_run: bool,
+    body: Bytes,
+) -> Result<impl Reply, Infallible> {
+    Ok(open(store, name, application_name, Some(action), dry_run, body).await)
+}
+
+async fn open(
+    store: Store,
+    name: String,
+    application_name: String,
+    action: Option<String>,
+    dry_run: bool,
+    body: Bytes,
+) -> reply::WithStatus<String> {
+    let decoded = urlencoding::decode(&application_name).and_then(|application_name| {
+        let action = action
+            .map(|action| urlencoding::decode(&action).map(Cow::into_owned))
+            .transpose()?;
+        Ok((application_name, action))
+    });
+    let (application_name, action) = match decoded {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            return reply::with_status(
+                format!("Invalid url encoding: {err}"),
+                StatusCode::BAD_REQUEST,
+            )
+        }
+    };
+    let body = if body.is_empty() {
+        OpenApplicationBody::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(body) => body,
+            Err(err) => {
+                return reply::with_status(format!("Invalid body: {err}"), StatusCode::BAD_REQUEST)
+            }
+        }
+    };
     let mut lock = store.lock().await;
     let Some(machine) = lock.by_name_mut(&name) else {
+        return reply::with_status(
+            "Machine does not exist".to_owned(),
+            http::StatusCode::NOT_FOUND,
+        );
+    };
+    let launch = match machine
+        .open_app(
+            &application_name,
+            action.as_deref(),
+            &body.arguments,
+            body.session.as_deref(),
+            dry_run,
+        )
+        .await
+    {
+        Ok(launch) => launch,
+        Err(OpenApplicationError::Failed(msg)) => {
+            return reply::with_status(format!("{msg:#}"), StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        // the application or its action does not exist
+        Err(err) => return reply::with_status(err.to_string(), StatusCode::NOT_FOUND),
+    };
+    if let Some(launch) = launch {
+        drop(lock);
+        if let Err(msg) = launch.run().await {
+            return reply::with_status(format!("{msg:#}"), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        lock = store.lock().await;
+    }
+    if !dry_run {
+        let now = unix_timestamp(SystemTime::now());
+        if let Err(err) = lock.launches.record(&name, &application_name, now) {
+            warn!("Could not record the launch of {application_name}: {err:#}");
+        }
+    }
+    reply::with_status("Success".to_owned(), StatusCode::OK)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Fuzzy matched on the name, generic name, keywords and program of the applications
+    #[param(example = "firefox")]
+    #[serde(default)]
+    q: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/{name}/applications",
+    responses(
+        (status = 200, description = "Applications matching the query the best first, the ones opened often and recently are ranked higher. Every application if the query is empty", body = SearchApplicationsResponse),
+        (status = 404, description = "Machine does not exist")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine"),
+        SearchQuery
+    ),
+)]
+pub async fn search_applications(
+    store: Store,
+    name: String,
+    query: SearchQuery,
+) -> Result<Box<dyn Reply>, Infallible> {
+    let lock = store.lock().await;
+    let now = unix_timestamp(SystemTime::now());
+    let Some(applications) = lock
+        .by_name(&name)
+        .map(|machine| machine.search_applications(&query.q, &lock.launches, now))
+    else {
+        return Ok(Box::new(reply::with_status(
+            "Machine does not exist",
+            http::StatusCode::NOT_FOUND,
+        )));
+    };
+    drop(lock);
+    Ok(Box::new(reply::json(&SearchApplicationsResponse {
+        applications,
+    })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{name}/sessions",
+    responses(
+        (status = 200, description = "Graphical sessions of the ssh user on the machine, to pick the one applications are opened in", body = SessionsResponse),
+        (status = 404, description = "Machine does not exist"),
+        (status = 500, description = "Could not list the sessions of the machine")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine")
+    ),
+)]
+pub async fn graphical_sessions(store: Store, name: String) -> Result<Box<dyn Reply>, Infallible> {
+    let Some(addr) = store
+        .lock()
+        .await
+        .by_name(&name)
+        .map(|machine| machine.addr)
+    else {
+        return Ok(Box::new(reply::with_status(
+            "Machine does not exist".to_owned(),
+            http::StatusCode::NOT_FOUND,
+        )));
+    };
+    match service::graphical_sessions(addr).await {
+        Ok(sessions) => Ok(Box::new(reply::json(&SessionsResponse { sessions }))),
+        Err(err) => Ok(Box::new(reply::with_status(
+            format!("{err:#}"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/{name}/applications/running",
+    responses(
+        (status = 200, description = "Applications opened from the panel that are still running", body = RunningApplicationsResponse),
+        (status = 404, description = "Machine does not exist")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine")
+    ),
+)]
+pub async fn running_applications(
+    store: Store,
+    name: String,
+) -> Result<Box<dyn Reply>, Infallible> {
+    let Some(applications) = store
+        .lock()
+        .await
+        .by_name(&name)
+        .map(|machine| machine.infos.running_applications.clone())
+    else {
+        return Ok(Box::new(reply::with_status(
+            "Machine does not exist",
+            http::StatusCode::NOT_FOUND,
+        )));
+    };
+    Ok(Box::new(reply::json(&RunningApplicationsResponse {
+        applications,
+    })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{name}/close_application/{pid}",
+    responses(
+        (status = 200, description = "Asked the application to close, it is killed if it doesn't"),
+        (status = 404, description = "Machine does not exist, or no application opened from the panel has this pid", body = CloseApplicationError),
+        (status = 500, description = "The agent is unreachable", body = CloseApplicationError)
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine"),
+        ("pid" = u32, Path, description = "Pid of the running application")
+    ),
+)]
+#[expect(clippy::significant_drop_tightening, reason = "todo fix mais flemme")]
+pub async fn close_application(
+    store: Store,
+    name: String,
+    pid: u32,
+    dry_run: bool,
+) -> Result<impl Reply, Infallible> {
+    let lock = store.lock().await;
+    let Some(machine) = lock.by_name(&name) else {
         return Ok(reply::with_status(
             "Machine does not exist".to_owned(),
             http::StatusCode::NOT_FOUND,
         ));
     };
-    match machine.open_app(&application_name, dry_run).await {
+    match machine.close_application(pid, dry_run).await {
         Ok(()) => Ok(reply::with_status("Success".to_owned(), StatusCode::OK)),
-        Err(msg) => Ok(reply::with_status(
-            format!("{msg:#}"),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        )),
+        Err(err) => {
+            let status = match err {
+                CloseApplicationError::NotRunning => StatusCode::NOT_FOUND,
+                CloseApplicationError::AgentComunicationError(_) => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            };
+            Ok(reply::with_status(
+                serde_json::to_string(&err).unwrap(),
+                status,
+            ))
+        }
     }
 }
 
@@ -266,24 +825,296 @@ pub async fn wake(store: Store, name: String, dry_run: bool) -> Result<Box<dyn R
             http::StatusCode::NOT_FOUND,
         )));
     };
-    {
-        let store = store.clone();
-        tokio::spawn(async move {
-            time::sleep(TIME_BEFORE_ASSUMING_WOL_FAILED).await;
-            let mut lock = store.lock().await;
-            let machine = lock.by_name_mut(&name).unwrap();
-            if machine.infos.state == State::PendingOn {
-                machine.infos.state = State::Off;
-            }
-        });
-    }
-
     Ok(Box::new(match machine.wake(dry_run) {
         Ok(msg) => reply::with_status(msg, StatusCode::OK),
         Err(msg) => reply::with_status(msg, StatusCode::INTERNAL_SERVER_ERROR),
     }))
 }
 
+fn group_reply(response: &GroupActionResponse) -> Box<dyn Reply> {
+    if response.is_empty() {
+        return Box::new(reply::with_status(
+            "No machine has this tag",
+            http::StatusCode::NOT_FOUND,
+        ));
+    }
+    Box::new(reply::json(response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/group/{tag}/wake",
+    responses(
+        (status = 200, description = "Result of the wake on lan for each machine", body = GroupActionResponse),
+        (status = 404, description = "No machine has this tag")
+    ),
+    params(
+        ("tag" = String, Path, description = "Tag of the machines to wake")
+    ),
+)]
+pub async fn group_wake(
+    store: Store,
+    tag: String,
+    dry_run: bool,
+) -> Result<Box<dyn Reply>, Infallible> {
+    let mut lock = store.lock().await;
+    let mut response = GroupActionResponse::default();
+    for machine in lock.by_tag_mut(&tag) {
+        let res = machine.wake(dry_run);
+        response.push(&machine.infos.name, res);
+    }
+    drop(lock);
+    Ok(group_reply(&response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/group/{tag}/shutdown",
+    responses(
+        (status = 200, description = "Result of the shutdown for each machine", body = GroupActionResponse),
+        (status = 404, description = "No machine has this tag")
+    ),
+    params(
+        ("tag" = String, Path, description = "Tag of the machines to shutdown"),
+        ShutdownQuery
+    ),
+)]
+pub async fn group_shutdown(
+    store: Store,
+    tag: String,
+    dry_run: bool,
+    query: ShutdownQuery,
+) -> Result<Box<dyn Reply>, Infallible> {
+    let mut lock = store.lock().await;
+    let mut response = GroupActionResponse::default();
+    let members: Vec<_> = lock
+        .by_tag_mut(&tag)
+        .map(|machine| machine.infos.name.clone())
+        .collect();
+    for name in &members {
+        // machines of the group can be shut down together
+        let dependents: Vec<_> = lock
+            .running_dependents(name)
+            .into_iter()
+            .filter(|dependent| !members.contains(dependent))
+            .collect();
+        let machine = lock.by_name_mut(name).expect("machine to exist");
+        if !query.force && !dependents.is_empty() {
+            response.push(name, Err(dependents_error(name, &dependents)));
+            continue;
+        }
+        response.push(name, Ok(query.apply(machine, dry_run).await));
+    }
+    drop(lock);
+    Ok(group_reply(&response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/group/{tag}/task",
+    responses(
+        (status = 200, description = "Result of pushing the task for each machine", body = GroupActionResponse),
+        (status = 404, description = "No machine has this tag")
+    ),
+    request_body = GroupTask,
+    params(
+        ("tag" = String, Path, description = "Tag of the machines to run the task on")
+    ),
+)]
+pub async fn group_task(
+    store: Store,
+    tag: String,
+    dry_run: bool,
+    task: GroupTask,
+) -> Result<Box<dyn Reply>, Infallible> {
+    let mut lock = store.lock().await;
+    let mut response = GroupActionResponse::default();
+    for machine in lock.by_tag_mut(&tag) {
+        let res = machine.push_group_task(&task, dry_run);
+        response.push(&machine.infos.name, res);
+    }
+    drop(lock);
+    Ok(group_reply(&response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{name}/idle_shutdown/postpone",
+    responses(
+        (status = 200, description = "Postponed the idle shutdown successfully"),
+        (status = 404, description = "Machine does not exist"),
+        (status = 500, description = "The machine has no idle shutdown configured")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine")
+    ),
+)]
+#[expect(clippy::significant_drop_tightening, reason = "todo fix mais flemme")]
+pub async fn postpone_idle_shutdown(
+    store: Store,
+    name: String,
+    dry_run: bool,
+) -> Result<impl Reply, Infallible> {
+    let mut lock = store.lock().await;
+    let Some(machine) = lock.by_name_mut(&name) else {
+        return Ok(reply::with_status(
+            "Machine does not exist".to_owned(),
+            http::StatusCode::NOT_FOUND,
+        ));
+    };
+    match machine.postpone_idle_shutdown(dry_run).await {
+        Ok(msg) => Ok(reply::with_status(msg, StatusCode::OK)),
+        Err(msg) => Ok(reply::with_status(msg, StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/{name}/idle_shutdown/veto",
+    responses(
+        (status = 200, description = "Disabled the idle shutdown until the machine is turned off"),
+        (status = 404, description = "Machine does not exist"),
+        (status = 500, description = "The machine has no idle shutdown configured")
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the machine")
+    ),
+)]
+#[expect(clippy::significant_drop_tightening, reason = "todo fix mais flemme")]
+pub async fn veto_idle_shutdown(
+    store: Store,
+    name: String,
+    dry_run: bool,
+) -> Result<impl Reply, Infallible> {
+    let mut lock = store.lock().await;
+    let Some(machine) = lock.by_name_mut(&name) else {
+        return Ok(reply::with_status(
+            "Machine does not exist".to_owned(),
+            http::StatusCode::NOT_FOUND,
+        ));
+    };
+    match machine.veto_idle_shutdown(dry_run).await {
+        Ok(msg) => Ok(reply::with_status(msg, StatusCode::OK)),
+        Err(msg) => Ok(reply::with_status(msg, StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+fn power_handlers(
+    store: &Store,
+    dry_run: bool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let reboot = {
+        let store = store.clone();
+        warp::path!(String / "reboot")
+            .and(warp::query())
+            .and_then(move |name: String, query| reboot(store.clone(), name, dry_run, query))
+    };
+    let suspend = {
+        let store = store.clone();
+        warp::path!(String / "suspend")
+            .and(warp::query())
+            .and_then(move |name: String, query| suspend(store.clone(), name, dry_run, query))
+    };
+
+    reboot.or(suspend)
+}
+
+fn group_handlers(
+    store: &Store,
+    dry_run: bool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let group_wake = {
+        let store = store.clone();
+        warp::path!("group" / String / "wake")
+            .and_then(move |tag: String| group_wake(store.clone(), tag, dry_run))
+    };
+    let group_shutdown = {
+        let store = store.clone();
+        warp::path!("group" / String / "shutdown")
+            .and(warp::query())
+            .and_then(move |tag: String, query| group_shutdown(store.clone(), tag, dry_run, query))
+    };
+    let group_task = {
+        let store = store.clone();
+        warp::path!("group" / String / "task").and(json()).and_then(
+            move |tag: String, body: GroupTask| group_task(store.clone(), tag, dry_run, body),
+        )
+    };
+
+    group_wake.or(group_shutdown).or(group_task)
+}
+
+fn metrics_handlers(
+    store: &Store,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let store = store.clone();
+    warp::path!(String / "metrics")
+        .and(warp::get())
+        .and_then(move |name: String| machine_metrics(store.clone(), name))
+}
+
+fn application_handlers(
+    store: &Store,
+    dry_run: bool,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let open_application = {
+        let store = store.clone();
+        warp::path!(String / "open_application" / String)
+            .and(warp::body::bytes())
+            .and_then(move |name, application_name, body| {
+                open_application(store.clone(), name, application_name, dry_run, body)
+            })
+    };
+    let open_application_action = {
+        let store = store.clone();
+        warp::path!(String / "open_application" / String / "action" / String)
+            .and(warp::body::bytes())
+            .and_then(move |name, application_name, action, body| {
+                open_application_action(
+                    store.clone(),
+                    name,
+                    application_name,
+                    action,
+                    dry_run,
+                    body,
+                )
+            })
+    };
+    let search_applications = {
+        let store = store.clone();
+        warp::path!(String / "applications")
+            .and(warp::get())
+            .and(warp::query::<SearchQuery>())
+            .and_then(move |name: String, query| search_applications(store.clone(), name, query))
+    };
+    let graphical_sessions = {
+        let store = store.clone();
+        warp::path!(String / "sessions")
+            .and(warp::get())
+            .and_then(move |name: String| graphical_sessions(store.clone(), name))
+    };
+    let running_applications = {
+        let store = store.clone();
+        warp::path!(String / "applications" / "running")
+            .and(warp::get())
+            .and_then(move |name: String| running_applications(store.clone(), name))
+    };
+    let close_application = {
+        let store = store.clone();
+        warp::path!(String / "close_application" / u32)
+            .and(warp::post())
+            .and_then(move |name: String, pid: u32| {
+                close_application(store.clone(), name, pid, dry_run)
+            })
+    };
+    open_application
+        .or(open_application_action)
+        .or(search_applications)
+        .or(graphical_sessions)
+        .or(running_applications)
+        .or(close_application)
+}
+
 #[expect(clippy::type_complexity, reason = "aie aie aie")]
 pub fn handlers(
     config: &Config,
@@ -315,8 +1146,9 @@ pub fn handlers(
         let store = store.clone();
         warp::path!("agent").and(ws()).map(move |ws: ws::Ws| {
             let store = store.clone();
-            ws.max_message_size(1024 << 20) // 1GB
-                .max_frame_size(1024 << 20) // 1GB
+            // icons are sent in batches of `ICONS_PER_MESSAGE`
+            ws.max_message_size(16 << 20) // 16MB
+                .max_frame_size(16 << 20) // 16MB
                 .on_upgrade(move |websocket| {
                     let store = store.clone();
                     async move {
@@ -334,7 +1166,13 @@ pub fn handlers(
     let shutdown = {
         let store = store.clone();
         warp::path!(String / "shutdown")
-            .and_then(move |name: String| shutdown(store.clone(), name, dry_run))
+            .and(warp::query())
+            .and_then(move |name: String, query| shutdown(store.clone(), name, dry_run, query))
+    };
+    let cancel_shutdown = {
+        let store = store.clone();
+        warp::path!(String / "shutdown" / "cancel")
+            .and_then(move |name: String| cancel_shutdown(store.clone(), name, dry_run))
     };
     let open_vdi = {
         let store = store.clone();
@@ -346,18 +1184,22 @@ pub fn handlers(
             .and(json())
             .and_then(move |name, body: Task| task(store.clone(), name, dry_run, body))
     };
-    let open_application = {
+    let postpone_idle_shutdown = {
         let store = store.clone();
-        warp::path!(String / "open_application" / String).and_then(move |name, application_name| {
-            open_application(store.clone(), name, application_name, dry_run)
-        })
+        warp::path!(String / "idle_shutdown" / "postpone")
+            .and_then(move |name: String| postpone_idle_shutdown(store.clone(), name, dry_run))
+    };
+    let veto_idle_shutdown = {
+        let store = store.clone();
+        warp::path!(String / "idle_shutdown" / "veto")
+            .and_then(move |name: String| veto_idle_shutdown(store.clone(), name, dry_run))
     };
 
     let check_state_thread = {
         let store = store.clone();
         Box::pin(async move {
             loop {
-                store.lock().await.refresh_machine_state().await;
+                store.lock().await.refresh_machine_state(dry_run).await;
                 time::sleep(MACHINE_REFRESH_INTERVAL).await;
             }
         })
@@ -368,12 +1210,18 @@ pub fn handlers(
     let routes = list
         .or(wake)
         .or(shutdown)
+        .or(cancel_shutdown)
+        .or(power_handlers(&store, dry_run))
         .or(open_vdi)
         .or(task)
         .or(list_ws)
         .or(ssh_handlers)
         .or(agent)
-        .or(open_application);
+        .or(application_handlers(&store, dry_run))
+        .or(postpone_idle_shutdown)
+        .or(veto_idle_shutdown)
+        .or(group_handlers(&store, dry_run))
+        .or(metrics_handlers(&store));
 
     Ok((routes, check_state_thread))
 }
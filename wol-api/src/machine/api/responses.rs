@@ -1,25 +1,84 @@
+use std::collections::BTreeMap;
+
+use itertools::Itertools as _;
 use serde::Serialize;
 use utoipa::ToSchema;
 
-use crate::machine::service::{Machine, MachineInfos};
+use crate::{
+    agent::{metrics::Metrics, pairing::PairingRequest},
+    machine::{
+        application::{ApplicationDisplay, RunningApplication},
+        service::{Machine, MachineInfos},
+        session::GraphicalSession,
+    },
+};
 
 #[derive(Serialize, ToSchema, PartialEq, Eq)]
 pub struct ListMachineResponse {
     machines: Vec<MachineInfos>,
+    /// Every tag used by at least one machine
+    #[schema(example = json!(["gaming", "living-room"]))]
+    tags: Vec<String>,
 }
 
 impl From<&Vec<Machine>> for ListMachineResponse {
     fn from(value: &Vec<Machine>) -> Self {
         Self {
             machines: value.iter().map(|machine| machine.infos.clone()).collect(),
+            tags: value
+                .iter()
+                .flat_map(|machine| machine.infos.config.tags.iter().cloned())
+                .sorted()
+                .dedup()
+                .collect(),
         }
     }
 }
 
 #[derive(Serialize, ToSchema, PartialEq, Eq)]
+pub struct ActionResult {
+    success: bool,
+    #[schema(example = "Sent wake on lan successfully")]
+    message: String,
+}
+
+impl From<Result<String, String>> for ActionResult {
+    fn from(value: Result<String, String>) -> Self {
+        match value {
+            Ok(message) => Self {
+                success: true,
+                message,
+            },
+            Err(message) => Self {
+                success: false,
+                message,
+            },
+        }
+    }
+}
+
+/// Result of an action on each machine of a group
+#[derive(Serialize, ToSchema, PartialEq, Eq, Default)]
+pub struct GroupActionResponse {
+    results: BTreeMap<String, ActionResult>,
+}
+
+impl GroupActionResponse {
+    pub fn push(&mut self, machine: &str, result: impl Into<ActionResult>) {
+        self.results.insert(machine.to_owned(), result.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+}
+
+#[derive(Serialize, ToSchema, PartialEq, Eq, Debug)]
 pub enum AgentComunicationError {
     NotConnected,
     SendFailed(String),
+    /// The agent is too old to understand this message
+    Unsupported,
 }
 
 #[derive(Serialize, ToSchema, PartialEq, Eq)]
@@ -27,3 +86,42 @@ pub enum OpenVdiError {
     AgentComunicationError(AgentComunicationError),
     AlreadyOpened,
 }
+
+#[derive(Serialize, ToSchema, PartialEq, Eq, Debug)]
+pub enum CloseApplicationError {
+    AgentComunicationError(AgentComunicationError),
+    /// No application opened from the panel has this pid, it may have exited already
+    NotRunning,
+}
+
+/// Agents waiting for an admin to approve their pairing
+#[derive(Serialize, ToSchema, PartialEq, Eq)]
+pub struct PairingRequestsResponse {
+    /// Pairing requests by machine name, the oldest first
+    pub requests: BTreeMap<String, Vec<PairingRequest>>,
+}
+
+/// Latest metrics sent by the agent of a machine
+#[derive(Serialize, ToSchema, PartialEq, Eq)]
+pub struct MetricsResponse {
+    /// Oldest first
+    pub samples: Vec<Metrics>,
+}
+
+/// Applications opened from the panel that are still running
+#[derive(Serialize, ToSchema, PartialEq, Eq)]
+pub struct RunningApplicationsResponse {
+    pub applications: Vec<RunningApplication>,
+}
+
+/// Graphical sessions opened on a machine
+#[derive(Serialize, ToSchema, PartialEq, Eq)]
+pub struct SessionsResponse {
+    pub sessions: Vec<GraphicalSession>,
+}
+
+/// Applications of a machine matching a search, the best first
+#[derive(Serialize, ToSchema, PartialEq, Eq)]
+pub struct SearchApplicationsResponse {
+    pub applications: Vec<ApplicationDisplay>,
+}
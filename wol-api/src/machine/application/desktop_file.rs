@@ -0,0 +1,206 @@
+//! Key files of the desktop entries, see
+//! <https://specifications.freedesktop.org/desktop-entry-spec/latest/basic-format.html>
+//!
+//! xdgkit ignores the localized keys without `$LANG` and the `[Desktop Action ...]` groups
+
+use std::{collections::HashMap, env};
+
+pub const DESKTOP_ENTRY: &str = "Desktop Entry";
+pub const DESKTOP_ACTION_PREFIX: &str = "Desktop Action ";
+
+/// `lang_COUNTRY.ENCODING@MODIFIER`, used to pick the localized keys eg: `Name[fr]`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Locale {
+    lang: Option<String>,
+    country: Option<String>,
+    modifier: Option<String>,
+}
+
+impl Locale {
+    pub fn parse(locale: &str) -> Self {
+        let (locale, modifier) = locale
+            .split_once('@')
+            .map_or((locale, None), |(locale, modifier)| {
+                (locale, Some(modifier))
+            });
+        let locale = locale
+            .split_once('.')
+            .map_or(locale, |(locale, _encoding)| locale);
+        let (lang, country) = locale
+            .split_once('_')
+            .map_or((locale, None), |(lang, country)| (lang, Some(country)));
+        if lang.is_empty() || lang == "C" || lang == "POSIX" {
+            return Self::default();
+        }
+        Self {
+            lang: Some(lang.to_owned()),
+            country: country.map(ToOwned::to_owned),
+            modifier: modifier.map(ToOwned::to_owned),
+        }
+    }
+
+    /// Locale of the messages of this process
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .find_map(|var| env::var(var).ok().filter(|value| !value.is_empty()))
+            .map_or_else(Self::default, |locale| Self::parse(&locale))
+    }
+
+    /// Suffixes of the localized keys to try, most specific first
+    fn candidates(&self) -> Vec<String> {
+        let Some(lang) = &self.lang else {
+            return vec![];
+        };
+        let mut candidates = vec![];
+        if let (Some(country), Some(modifier)) = (&self.country, &self.modifier) {
+            candidates.push(format!("{lang}_{country}@{modifier}"));
+        }
+        if let Some(country) = &self.country {
+            candidates.push(format!("{lang}_{country}"));
+        }
+        if let Some(modifier) = &self.modifier {
+            candidates.push(format!("{lang}@{modifier}"));
+        }
+        candidates.push(lang.clone());
+        candidates
+    }
+}
+
+/// Groups of key-value pairs, in order
+#[derive(Debug, Clone, Default)]
+pub struct KeyFile {
+    groups: Vec<(String, HashMap<String, String>)>,
+}
+
+impl KeyFile {
+    /// Lenient, invalid lines are skipped
+    pub fn parse(content: &str) -> Self {
+        let mut groups: Vec<(String, HashMap<String, String>)> = vec![];
+        for line in content.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some(group) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                groups.push((group.to_owned(), HashMap::new()));
+                continue;
+            }
+            let (Some((_group, keys)), Some((key, value))) =
+                (groups.last_mut(), line.split_once('='))
+            else {
+                continue;
+            };
+            // the first occurrence wins
+            keys.entry(key.trim().to_owned())
+                .or_insert_with(|| value.trim().to_owned());
+        }
+        Self { groups }
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = &str> {
+        self.groups.iter().map(|(group, _keys)| group.as_str())
+    }
+
+    pub fn get(&self, group: &str, key: &str) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|(name, _keys)| name == group)?
+            .1
+            .get(key)
+            .map(String::as_str)
+    }
+
+    /// `key[locale]` for the best matching locale, or `key`
+    pub fn localized(&self, group: &str, key: &str, locale: &Locale) -> Option<&str> {
+        locale
+            .candidates()
+            .iter()
+            .find_map(|suffix| self.get(group, &format!("{key}[{suffix}]")))
+            .or_else(|| self.get(group, key))
+    }
+
+    pub fn boolean(&self, group: &str, key: &str) -> bool {
+        self.get(group, key) == Some("true")
+    }
+
+    /// `;` separated values
+    pub fn list(&self, group: &str, key: &str) -> Option<Vec<&str>> {
+        self.get(group, key).map(split_list)
+    }
+
+    /// Like [`KeyFile::list`] for localized keys eg: `Keywords[fr]`
+    pub fn localized_list(&self, group: &str, key: &str, locale: &Locale) -> Option<Vec<&str>> {
+        self.localized(group, key, locale).map(split_list)
+    }
+}
+
+fn split_list(value: &str) -> Vec<&str> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const ENTRY: &str = "# comment
+[Desktop Entry]
+Name=Files
+Name[fr]=Fichiers
+Name[sr@latin]=Datoteke
+Name[pt_BR]=Arquivos
+Name=Ignored duplicate
+NoDisplay=true
+OnlyShowIn=GNOME;Unity;
+Keywords=folder;manager;
+Keywords[fr]=dossier;gestionnaire;
+
+[Desktop Action new-window]
+Name=New Window
+";
+
+    #[rstest]
+    #[case("", "Files")]
+    #[case("C.UTF-8", "Files")]
+    #[case("fr_FR.UTF-8", "Fichiers")]
+    #[case("pt_BR.UTF-8", "Arquivos")]
+    #[case("pt_PT.UTF-8", "Files")]
+    #[case("sr_RS.UTF-8@latin", "Datoteke")]
+    fn test_localized(#[case] locale: &str, #[case] expected: &str) {
+        let keys = KeyFile::parse(ENTRY);
+        assert_eq!(
+            keys.localized(DESKTOP_ENTRY, "Name", &Locale::parse(locale)),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn test_key_file() {
+        let keys = KeyFile::parse(ENTRY);
+        assert_eq!(
+            keys.groups().collect::<Vec<_>>(),
+            ["Desktop Entry", "Desktop Action new-window"]
+        );
+        assert!(keys.boolean(DESKTOP_ENTRY, "NoDisplay"));
+        assert!(!keys.boolean(DESKTOP_ENTRY, "Hidden"));
+        assert_eq!(
+            keys.list(DESKTOP_ENTRY, "OnlyShowIn"),
+            Some(vec!["GNOME", "Unity"])
+        );
+        assert_eq!(
+            keys.localized_list(DESKTOP_ENTRY, "Keywords", &Locale::parse("fr_FR")),
+            Some(vec!["dossier", "gestionnaire"])
+        );
+        assert_eq!(
+            keys.get("Desktop Action new-window", "Name"),
+            Some("New Window")
+        );
+    }
+}
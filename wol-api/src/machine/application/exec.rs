@@ -0,0 +1,297 @@
+//! `Exec` key of the desktop entries, see
+//! <https://specifications.freedesktop.org/desktop-entry-spec/latest/exec-variables.html>
+
+use std::{
+    env, iter,
+    os::unix::fs::PermissionsExt as _,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+/// Terminal emulators tried in order when `$TERMINAL` is not set, with the flag to run a command
+const TERMINALS: &[(&str, Option<&str>)] = &[
+    ("xdg-terminal-exec", None),
+    ("x-terminal-emulator", Some("-e")),
+    ("kgx", Some("--")),
+    ("gnome-terminal", Some("--")),
+    ("konsole", Some("-e")),
+    ("xfce4-terminal", Some("-x")),
+    ("alacritty", Some("-e")),
+    ("kitty", None),
+    ("foot", None),
+    ("xterm", Some("-e")),
+];
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ExecError {
+    #[error("Empty command")]
+    Empty,
+    #[error("Unterminated quoted argument")]
+    UnterminatedQuote,
+    #[error("Invalid field code %{0}")]
+    InvalidFieldCode(char),
+}
+
+/// What the field codes are replaced with
+#[derive(Debug, Default)]
+pub struct FieldCodes<'entry> {
+    /// `%c`
+    pub name: &'entry str,
+    /// `%i`, the `Icon` key
+    pub icon: Option<&'entry str>,
+    /// `%k`
+    pub desktop_file: Option<&'entry Path>,
+    /// Files or urls for `%f`, `%F`, `%u` and `%U`
+    pub arguments: &'entry [String],
+}
+
+/// Splits an `Exec` key in arguments, the field codes are kept
+pub fn parse(exec: &str) -> Result<Vec<String>, ExecError> {
+    let exec = unescape(exec);
+    let mut arguments = vec![];
+    let mut current: Option<String> = None;
+    let mut chars = exec.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '"' => {
+                let argument = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or(ExecError::UnterminatedQuote)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or(ExecError::UnterminatedQuote)? {
+                            escaped @ ('"' | '`' | '$' | '\\') => argument.push(escaped),
+                            other => {
+                                argument.push('\\');
+                                argument.push(other);
+                            }
+                        },
+                        other => argument.push(other),
+                    }
+                }
+            }
+            ' ' | '\t' | '\n' => arguments.extend(current.take()),
+            other => current.get_or_insert_with(String::new).push(other),
+        }
+    }
+    arguments.extend(current);
+    if arguments.is_empty() {
+        return Err(ExecError::Empty);
+    }
+    Ok(arguments)
+}
+
+/// The escape sequences of the string values, applied before the quoting rules
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            unescaped.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            // a trailing backslash is kept as is
+            Some('\\') | None => unescaped.push('\\'),
+            // left for the quoting rules
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+        }
+    }
+    unescaped
+}
+
+/// Replaces the field codes of parsed arguments.
+/// Only the first file or url is passed to `%f` and `%u`
+pub fn expand(arguments: Vec<String>, codes: &FieldCodes<'_>) -> Result<Vec<String>, ExecError> {
+    let mut expanded = vec![];
+    for argument in arguments {
+        match argument.as_str() {
+            "%F" => expanded.extend(codes.arguments.iter().map(|file| to_path(file))),
+            "%U" => expanded.extend(codes.arguments.iter().cloned()),
+            "%i" => {
+                if let Some(icon) = codes.icon {
+                    expanded.extend(["--icon".to_owned(), icon.to_owned()]);
+                }
+            }
+            // a lone field code without value is removed, not passed as an empty argument
+            "%f" | "%u" | "%k" | "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => {
+                let value = expand_argument(&argument, codes)?;
+                if !value.is_empty() {
+                    expanded.push(value);
+                }
+            }
+            _ => expanded.push(expand_argument(&argument, codes)?),
+        }
+    }
+    if expanded.is_empty() {
+        return Err(ExecError::Empty);
+    }
+    Ok(expanded)
+}
+
+/// Expands the field codes that can be part of a larger argument
+fn expand_argument(argument: &str, codes: &FieldCodes<'_>) -> Result<String, ExecError> {
+    let mut expanded = String::with_capacity(argument.len());
+    let mut chars = argument.chars();
+    while let Some(char) = chars.next() {
+        if char != '%' {
+            expanded.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('f') => expanded.extend(codes.arguments.first().map(|file| to_path(file))),
+            Some('u') => expanded.extend(codes.arguments.first().cloned()),
+            Some('c') => expanded.push_str(codes.name),
+            Some('k') => expanded.extend(codes.desktop_file.map(|path| path.display().to_string())),
+            // deprecated
+            Some('d' | 'D' | 'n' | 'N' | 'v' | 'm') => {}
+            Some(other) => return Err(ExecError::InvalidFieldCode(other)),
+            None => return Err(ExecError::InvalidFieldCode(' ')),
+        }
+    }
+    Ok(expanded)
+}
+
+/// `%f` and `%F` expect local paths
+fn to_path(file: &str) -> String {
+    file.strip_prefix("file://").map_or_else(
+        || file.to_owned(),
+        |path| {
+            urlencoding::decode(path).map_or_else(|_| path.to_owned(), std::borrow::Cow::into_owned)
+        },
+    )
+}
+
+/// Quotes the arguments for a posix shell, nothing is interpreted by it
+pub fn shell_join(arguments: &[String]) -> String {
+    arguments
+        .iter()
+        .map(|argument| format!("'{}'", argument.replace('\'', r"'\''")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Finds an executable like the shell would, `program` may be a path
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let is_executable = |path: &Path| {
+        path.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    };
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return is_executable(&path).then_some(path);
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+/// Wraps the arguments to run them in `$TERMINAL` or the first terminal emulator installed
+pub fn in_terminal(arguments: Vec<String>) -> Option<Vec<String>> {
+    let (terminal, flag) = env::var("TERMINAL")
+        .ok()
+        .filter(|terminal| find_program(terminal).is_some())
+        .map(|terminal| (terminal, Some("-e")))
+        .or_else(|| {
+            TERMINALS
+                .iter()
+                .find(|(terminal, _flag)| find_program(terminal).is_some())
+                .map(|(terminal, flag)| ((*terminal).to_owned(), *flag))
+        })?;
+    Some(
+        iter::once(terminal)
+            .chain(flag.map(ToOwned::to_owned))
+            .chain(arguments)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn strings(arguments: &[&str]) -> Vec<String> {
+        arguments
+            .iter()
+            .map(|&argument| argument.to_owned())
+            .collect()
+    }
+
+    #[rstest]
+    #[case("firefox %u", &["firefox", "%u"])]
+    #[case("  spaced   out  ", &["spaced", "out"])]
+    #[case(r#""/opt/My App/app" --flag"#, &["/opt/My App/app", "--flag"])]
+    #[case(r#"sh -c "echo \\"hi\\" \\$HOME""#, &["sh", "-c", r#"echo "hi" $HOME"#])]
+    #[case(r#"app """#, &["app", ""])]
+    #[case(r#""My\sApp" --opt"#, &["My App", "--opt"])]
+    #[case(r#"env "A=1"B"#, &["env", "A=1B"])]
+    fn test_parse(#[case] exec: &str, #[case] expected: &[&str]) {
+        assert_eq!(parse(exec).unwrap(), strings(expected));
+    }
+
+    #[rstest]
+    #[case("", ExecError::Empty)]
+    #[case(r#"app "unterminated"#, ExecError::UnterminatedQuote)]
+    fn test_parse_errors(#[case] exec: &str, #[case] expected: ExecError) {
+        assert_eq!(parse(exec).unwrap_err(), expected);
+    }
+
+    #[rstest]
+    #[case("firefox %u", &[], &["firefox"])]
+    #[case("firefox %U", &["a", "b"], &["firefox", "a", "b"])]
+    #[case("gimp %F", &["file:///tmp/a%20b.png", "/tmp/c.png"], &["gimp", "/tmp/a b.png", "/tmp/c.png"])]
+    #[case("vlc --started-from-file %f", &["/tmp/a.mkv", "/tmp/b.mkv"], &["vlc", "--started-from-file", "/tmp/a.mkv"])]
+    #[case("app %i --name=%c %k", &[], &["app", "--icon", "app-icon", "--name=App", "/usr/share/applications/app.desktop"])]
+    #[case("printf 100%%", &[], &["printf", "100%"])]
+    #[case("old %d %D %n %N %v %m", &[], &["old"])]
+    fn test_expand(#[case] exec: &str, #[case] arguments: &[&str], #[case] expected: &[&str]) {
+        let arguments = strings(arguments);
+        let codes = FieldCodes {
+            name: "App",
+            icon: Some("app-icon"),
+            desktop_file: Some(Path::new("/usr/share/applications/app.desktop")),
+            arguments: &arguments,
+        };
+        assert_eq!(
+            expand(parse(exec).unwrap(), &codes).unwrap(),
+            strings(expected)
+        );
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let codes = FieldCodes::default();
+        assert_eq!(
+            expand(strings(&["app", "%z"]), &codes).unwrap_err(),
+            ExecError::InvalidFieldCode('z')
+        );
+        assert_eq!(
+            expand(strings(&["%f"]), &codes).unwrap_err(),
+            ExecError::Empty
+        );
+    }
+
+    #[test]
+    fn test_shell_join() {
+        assert_eq!(
+            shell_join(&strings(&["echo", "it's", "$HOME"])),
+            r"'echo' 'it'\''s' '$HOME'"
+        );
+    }
+
+    #[test]
+    fn test_find_program() {
+        assert!(find_program("sh").is_some());
+        assert!(find_program("/bin/sh").is_some());
+        assert!(find_program("surely-not-an-installed-program").is_none());
+    }
+}
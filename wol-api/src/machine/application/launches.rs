@@ -0,0 +1,142 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::consts::FRECENCY_HALF_LIFE;
+
+pub const LAUNCHES_FILENAME: &str = "application-launches.json";
+
+/// How often and how recently an application was opened from the panel
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LaunchStats {
+    pub count: u64,
+    /// Unix timestamp (in seconds)
+    pub last_launch: u64,
+    /// At `last_launch`, it decays with [`FRECENCY_HALF_LIFE`]
+    frecency: f32,
+}
+
+impl LaunchStats {
+    #[expect(
+        clippy::cast_precision_loss,
+        clippy::float_arithmetic,
+        reason = "it's only used to rank the applications"
+    )]
+    fn frecency(&self, now: u64) -> f32 {
+        let elapsed = now.saturating_sub(self.last_launch) as f32;
+        self.frecency * 0.5f32.powf(elapsed / FRECENCY_HALF_LIFE.as_secs() as f32)
+    }
+}
+
+/// Launches of the applications of each machine, kept across restarts
+#[derive(Debug)]
+pub struct Launches {
+    path: PathBuf,
+    /// Machine name -> application name -> stats
+    stats: BTreeMap<String, BTreeMap<String, LaunchStats>>,
+}
+
+impl Launches {
+    /// Starts without any launch if the file can't be read, it only ranks the search results
+    pub fn load(path: PathBuf) -> Self {
+        let stats = match Self::read(&path) {
+            Ok(stats) => stats,
+            Err(err) => {
+                warn!("Forgetting the launches of the applications: {err:#}");
+                BTreeMap::new()
+            }
+        };
+        Self { path, stats }
+    }
+
+    fn read(path: &Path) -> anyhow::Result<BTreeMap<String, BTreeMap<String, LaunchStats>>> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Invalid launches file at {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(err) => Err(err)
+                .with_context(|| format!("Could not read launches file at {}", path.display())),
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.stats)?)
+            .with_context(|| format!("Could not write launches to {}", self.path.display()))
+    }
+
+    /// Counts a launch of `application` on `machine` at `now` (unix timestamp in seconds)
+    #[expect(
+        clippy::float_arithmetic,
+        reason = "it's only used to rank the applications"
+    )]
+    pub fn record(&mut self, machine: &str, application: &str, now: u64) -> anyhow::Result<()> {
+        let stats = self
+            .stats
+            .entry(machine.to_owned())
+            .or_default()
+            .entry(application.to_owned())
+            .or_insert(LaunchStats {
+                count: 0,
+                last_launch: now,
+                frecency: 0.0,
+            });
+        stats.frecency = stats.frecency(now) + 1.0;
+        stats.count += 1;
+        stats.last_launch = now;
+        self.save()
+    }
+
+    pub fn stats(&self, machine: &str, application: &str) -> Option<&LaunchStats> {
+        self.stats.get(machine)?.get(application)
+    }
+
+    /// 1 for each launch, halved every [`FRECENCY_HALF_LIFE`]
+    pub fn frecency(&self, machine: &str, application: &str, now: u64) -> f32 {
+        self.stats(machine, application)
+            .map_or(0.0, |stats| stats.frecency(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_launches() {
+        const DAY: u64 = 24 * 3600;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LAUNCHES_FILENAME);
+        let mut launches = Launches::load(path.clone());
+        assert!(launches.frecency("machine1", "Firefox", 0).abs() < f32::EPSILON);
+
+        launches.record("machine1", "Firefox", 0).unwrap();
+        launches.record("machine1", "Firefox", 7 * DAY).unwrap();
+        launches.record("machine1", "Steam", 7 * DAY).unwrap();
+        let launches = Launches::load(path.clone());
+        let firefox = launches.stats("machine1", "Firefox").unwrap();
+        assert_eq!((firefox.count, firefox.last_launch), (2, 7 * DAY));
+        assert!((launches.frecency("machine1", "Firefox", 7 * DAY) - 1.5).abs() < 1e-3);
+        assert!((launches.frecency("machine1", "Firefox", 14 * DAY) - 0.75).abs() < 1e-3);
+        assert!(
+            launches.frecency("machine1", "Firefox", 7 * DAY)
+                > launches.frecency("machine1", "Steam", 7 * DAY)
+        );
+        assert!(launches.stats("machine2", "Firefox").is_none());
+
+        fs::write(&path, "{").unwrap();
+        let launches = Launches::load(path);
+        assert!(
+            launches.stats("machine1", "Firefox").is_none(),
+            "a corrupt file is ignored"
+        );
+    }
+}
@@ -1,14 +1,17 @@
-use anyhow::Context as _;
+use anyhow::{bail, Context as _};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
 use futures_util::future::join_all;
-use image::{DynamicImage, GenericImageView as _, ImageBuffer, ImageReader, Pixel as _, Rgba};
+use image::{imageops::FilterType, DynamicImage, ImageFormat, ImageReader};
 use itertools::Itertools as _;
 use log::warn;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
 use std::{
     collections::HashMap,
+    env,
     ffi::OsStr,
     fs,
-    io::Error,
+    io::{Cursor, Error},
     iter,
     path::{Path, PathBuf},
     str::FromStr as _,
@@ -19,17 +22,48 @@ use tokio::{fs::File, io::AsyncReadExt as _};
 use utoipa::ToSchema;
 use xdgkit::{
     basedir,
-    categories::Categories,
     desktop_entry::DesktopEntry,
     icon_finder::{generate_dir_list, multiple_find_icon, user_theme, DirList},
     icon_theme::IconTheme,
 };
 
-use crate::cache;
+use crate::{
+    cache,
+    config::{ApplicationsCfg, CategoryGroupCfg, IconProviderCfg},
+};
+
+pub mod desktop_file;
+pub mod exec;
+pub mod launches;
+pub mod providers;
+pub mod search;
+pub mod watch;
+
+use desktop_file::{KeyFile, Locale, DESKTOP_ACTION_PREFIX, DESKTOP_ENTRY};
 
 #[derive(Debug, Clone)]
 pub struct Application {
     entry: DesktopEntry,
+    keys: KeyFile,
+    /// In the locale it was parsed with, like `generic_name` and `keywords`
+    name: Option<String>,
+    generic_name: Option<String>,
+    keywords: Vec<String>,
+    /// The desktop entry, or what the entry was generated from by a [`providers`]
+    path: PathBuf,
+    /// Not passed to `%k` since it's not a desktop entry
+    generated: bool,
+    actions: Vec<ApplicationAction>,
+}
+
+/// `[Desktop Action ...]` of a desktop entry, eg: "New Private Window"
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[expect(clippy::module_name_repetitions, reason = "more clear")]
+pub struct ApplicationAction {
+    pub id: String,
+    pub name: String,
+    /// Unparsed, with the same field codes as the application
+    exec: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
@@ -37,28 +71,144 @@ pub struct Application {
 /// Serializable application
 pub struct ApplicationInfo {
     pub name: String,
-    icon_bytes: Option<Vec<u8>>,
+    /// The pixels are only sent when the backend asks for them
+    #[serde(default)]
+    pub icon: Option<IconRef>,
+    /// Only read by older backends
     icon_name: String,
+    /// Unparsed, see [`ApplicationInfo::argv`]
     pub exec: String,
+    /// First of `categories`, only read by older backends
     category: String,
+    /// `Categories` key, eg: `["Game", "ActionGame"]`
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// eg: "Web Browser", used by the search
+    #[serde(default)]
+    pub generic_name: Option<String>,
+    /// Other words to find the application with
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Runs in a terminal emulator
+    #[serde(default)]
+    pub terminal: bool,
+    /// Working directory
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Value of the `Icon` key, passed to `%i`
+    #[serde(default)]
+    icon_key: Option<String>,
+    /// Passed to `%k`
+    #[serde(default)]
+    desktop_file: Option<PathBuf>,
+    #[serde(default)]
+    pub actions: Vec<ApplicationAction>,
+    /// Encoded `icon`, kept by the agent until the backend asks for it
+    #[serde(skip)]
+    icon_png: Option<Vec<u8>>,
 }
 impl ApplicationInfo {
-    fn icon(&self) -> Option<image::DynamicImage> {
-        self.icon_bytes.as_ref().map(|icon_bytes| {
-            #[expect(
-                clippy::cast_possible_truncation,
-                clippy::cast_precision_loss,
-                reason = "It won't be truncated because it's < 2^52 or something"
-            )]
-            let size = ((icon_bytes.len() / 4) as f64).sqrt() as u32;
-            let mut buf: ImageBuffer<Rgba<u8>, Vec<_>> = ImageBuffer::new(size, size);
-            buf.copy_from_slice(icon_bytes);
-            DynamicImage::from(buf)
+    /// The command to run the application or one of its actions, with the files or urls to
+    /// open in `arguments`
+    pub fn argv(&self, action: Option<&str>, arguments: &[String]) -> anyhow::Result<Vec<String>> {
+        let command = match action {
+            Some(id) => {
+                &self
+                    .actions
+                    .iter()
+                    .find(|action| action.id == id)
+                    .with_context(|| format!("{} has no action {id}", self.name))?
+                    .exec
+            }
+            None => &self.exec,
+        };
+        let codes = exec::FieldCodes {
+            name: &self.name,
+            icon: self.icon_key.as_deref(),
+            desktop_file: self.desktop_file.as_deref(),
+            arguments,
+        };
+        exec::expand(exec::parse(command)?, &codes)
+            .with_context(|| format!("Invalid command {command}"))
+    }
+
+    /// Older agents only send the first category
+    fn all_categories(&self) -> Vec<&str> {
+        if self.categories.is_empty() {
+            return vec![self.category.as_str()];
+        }
+        self.categories.iter().map(String::as_str).collect()
+    }
+
+    /// The icon to send to a backend that doesn't have it cached
+    pub fn icon_data(&self) -> Option<IconData> {
+        Some(IconData {
+            icon: self.icon.clone()?,
+            png: STANDARD.encode(self.icon_png.as_ref()?),
         })
     }
+}
+
+/// Icon of an application, content-addressed by the sha256 of its PNG
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct IconRef {
+    pub hash: String,
+    pub width: u32,
+    pub height: u32,
+}
 
-    fn icon_name(&self) -> &str {
-        &self.icon_name
+impl IconRef {
+    /// Hashes are used as filenames, make sure a peer didn't send a path
+    pub fn is_valid(&self) -> bool {
+        self.hash.len() == 64 && self.hash.bytes().all(|byte| byte.is_ascii_hexdigit())
+    }
+}
+
+/// Icon sent by the agent in `AgentMessage::Icons`
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct IconData {
+    pub icon: IconRef,
+    /// Base64 of the PNG
+    pub png: String,
+}
+
+impl IconData {
+    /// Shrinks `image` to fit in [`cache::IMAGE_SIZE`] and encodes it as a PNG
+    pub fn encode(image: &DynamicImage) -> image::ImageResult<(IconRef, Vec<u8>)> {
+        let image = if image.width() > cache::IMAGE_SIZE || image.height() > cache::IMAGE_SIZE {
+            image.resize(cache::IMAGE_SIZE, cache::IMAGE_SIZE, FilterType::CatmullRom)
+        } else {
+            image.clone()
+        };
+        let mut png = vec![];
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        let icon = IconRef {
+            hash: format!("{:x}", Sha256::digest(&png)),
+            width: image.width(),
+            height: image.height(),
+        };
+        Ok((icon, png))
+    }
+
+    /// The PNG, checked against the hash and dimensions it was announced with
+    pub fn decode(&self) -> anyhow::Result<Vec<u8>> {
+        let png = STANDARD.decode(&self.png).context("Invalid base64")?;
+        let hash = format!("{:x}", Sha256::digest(&png));
+        if hash != self.icon.hash {
+            bail!("Expected an icon with hash {}, got {hash}", self.icon.hash);
+        }
+        let image =
+            image::load_from_memory_with_format(&png, ImageFormat::Png).context("Invalid PNG")?;
+        if (image.width(), image.height()) != (self.icon.width, self.icon.height) {
+            bail!(
+                "Expected a {}x{} icon, got {}x{}",
+                self.icon.width,
+                self.icon.height,
+                image.width(),
+                image.height()
+            );
+        }
+        Ok(png)
     }
 }
 
@@ -68,14 +218,114 @@ impl ApplicationInfo {
 pub struct ApplicationDisplay {
     #[schema(example = "Satisfactory")]
     name: String,
+    /// `None` if the agent didn't send one and the icon provider found nothing
     #[schema(example = "/api/cache/images/steam_icon_526870.png")]
-    icon: String,
+    #[serde(default)]
+    icon: Option<String>,
+    #[serde(default)]
+    actions: Vec<ApplicationActionDisplay>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, ToSchema)]
 #[expect(clippy::module_name_repetitions, reason = "more clear")]
+/// Action of an application, opened with `open_application/{application}/action/{id}`
+pub struct ApplicationActionDisplay {
+    #[schema(example = "new-private-window")]
+    id: String,
+    #[schema(example = "New Private Window")]
+    name: String,
+}
+
+/// Application opened from the panel that is still running on the machine
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, ToSchema)]
+#[expect(clippy::module_name_repetitions, reason = "more clear")]
+pub struct RunningApplication {
+    pub pid: u32,
+    #[schema(example = "Satisfactory")]
+    pub name: String,
+    /// Unix timestamp (in seconds)
+    #[schema(example = 1_735_689_600)]
+    pub started_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, ToSchema, Default)]
+#[expect(clippy::module_name_repetitions, reason = "more clear")]
 pub struct GroupedApplication {
     groups: HashMap<String, Vec<ApplicationDisplay>>,
+    /// Names of the `groups`, in the order they should be shown
+    #[serde(default)]
+    #[schema(example = json!(["Favourites", "Games", "Internet", "Misc"]))]
+    order: Vec<String>,
+}
+
+/// Group of the applications pinned with `applications.favourites`
+pub const FAVOURITES_GROUP: &str = "Favourites";
+/// Group of the applications without any category of `application_groups`
+pub const MISC_GROUP: &str = "Misc";
+
+/// Which groups an application is shown in, from the config
+#[derive(Debug, Clone, Default)]
+#[expect(clippy::module_name_repetitions, reason = "more clear")]
+pub struct ApplicationGrouping {
+    categories: Vec<CategoryGroupCfg>,
+    applications: ApplicationsCfg,
+}
+
+impl ApplicationGrouping {
+    pub fn new(categories: &[CategoryGroupCfg], applications: &ApplicationsCfg) -> Self {
+        Self {
+            categories: categories.to_vec(),
+            applications: applications.clone(),
+        }
+    }
+
+    /// A favourite is also shown in its custom groups, or else in the group of its categories
+    fn groups_of(&self, application: &ApplicationInfo) -> Vec<String> {
+        let favourite = self
+            .applications
+            .favourites
+            .contains(&application.name)
+            .then(|| FAVOURITES_GROUP.to_owned());
+        let custom = self
+            .applications
+            .groups
+            .iter()
+            .filter(|group| group.applications.contains(&application.name))
+            .map(|group| group.name.clone())
+            .collect_vec();
+        let by_category = custom.is_empty().then(|| self.category_group(application));
+        favourite
+            .into_iter()
+            .chain(custom)
+            .chain(by_category)
+            .collect()
+    }
+
+    fn category_group(&self, application: &ApplicationInfo) -> String {
+        let categories = application.all_categories();
+        self.categories
+            .iter()
+            .find(|group| {
+                group
+                    .categories
+                    .iter()
+                    .any(|category| categories.contains(&category.as_str()))
+            })
+            .map_or_else(|| MISC_GROUP.to_owned(), |group| group.name.clone())
+    }
+
+    /// Every group, in the order they should be shown
+    fn order(&self) -> impl Iterator<Item = &str> {
+        iter::once(FAVOURITES_GROUP)
+            .chain(
+                self.applications
+                    .groups
+                    .iter()
+                    .map(|group| group.name.as_str()),
+            )
+            .chain(self.categories.iter().map(|group| group.name.as_str()))
+            .chain(iter::once(MISC_GROUP))
+    }
 }
 
 static DIR_LIST: LazyLock<Vec<DirList>> = LazyLock::new(generate_dir_list);
@@ -83,16 +333,70 @@ static THEME: LazyLock<IconTheme> =
     LazyLock::new(|| user_theme(DIR_LIST.clone()).unwrap_or_else(IconTheme::empty));
 
 impl Application {
+    /// Parses a desktop entry with the names in the locale of this process
     pub async fn parse(path: impl AsRef<Path> + Send + Sync) -> anyhow::Result<Self> {
+        Self::parse_localized(path, &Locale::from_env()).await
+    }
+
+    pub async fn parse_localized(
+        path: impl AsRef<Path> + Send + Sync,
+        locale: &Locale,
+    ) -> anyhow::Result<Self> {
         let mut buf = String::new();
         File::open(&path).await?.read_to_string(&mut buf).await?;
+        Ok(Self::from_entry(buf, path.as_ref().to_owned(), locale))
+    }
 
+    fn from_entry(buf: String, path: PathBuf, locale: &Locale) -> Self {
+        let keys = KeyFile::parse(&buf);
+        let name = keys
+            .localized(DESKTOP_ENTRY, "Name", locale)
+            .map(ToOwned::to_owned);
+        let generic_name = keys
+            .localized(DESKTOP_ENTRY, "GenericName", locale)
+            .map(ToOwned::to_owned);
+        let keywords = keys
+            .localized_list(DESKTOP_ENTRY, "Keywords", locale)
+            .unwrap_or_default()
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        let actions = parse_actions(&keys, locale);
         let entry = DesktopEntry::read(buf);
-        Ok(Self { entry })
+        Self {
+            entry,
+            keys,
+            name,
+            generic_name,
+            keywords,
+            path,
+            generated: false,
+            actions,
+        }
+    }
+
+    pub const fn name(&self) -> &Option<String> {
+        &self.name
+    }
+
+    /// `Hidden` entries are considered deleted, they also hide the entries they shadow
+    pub fn is_hidden(&self) -> bool {
+        self.keys.boolean(DESKTOP_ENTRY, "Hidden")
     }
 
-    pub fn name(&self) -> &Option<String> {
-        &self.entry.name
+    /// Whether it should be shown in the menus of one of the `desktops`,
+    /// eg: `["KDE"]` from `$XDG_CURRENT_DESKTOP`
+    pub fn is_displayed(&self, desktops: &[String]) -> bool {
+        let in_desktops = |key| {
+            self.keys.list(DESKTOP_ENTRY, key).map(|listed| {
+                listed
+                    .iter()
+                    .any(|desktop| desktops.iter().any(|current| current == desktop))
+            })
+        };
+        !self.keys.boolean(DESKTOP_ENTRY, "NoDisplay")
+            && in_desktops("OnlyShowIn").unwrap_or(true)
+            && !in_desktops("NotShowIn").unwrap_or(false)
     }
 
     pub fn icon(&self) -> Option<PathBuf> {
@@ -103,37 +407,152 @@ impl Application {
         }
 
         multiple_find_icon(
-            path_str,
+            path_str.clone(),
             cache::IMAGE_SIZE.try_into().unwrap(),
             1,
             DIR_LIST.to_owned(),
             THEME.to_owned(),
         )
+        // the icons of flatpak apps are only in the theme if its exports are in `$XDG_DATA_DIRS`
+        .or_else(|| providers::packages::find_exported_icon(&path_str))
         // .or(icon_finder::find_icon( "dialog-question".to_owned(), 48, 1, ))
     }
 
     pub fn exec(&self) -> &Option<String> {
         &self.entry.exec
     }
-    pub fn categories(&self) -> Vec<Categories> {
-        self.entry.categories.clone().unwrap_or_default()
+    pub fn categories(&self) -> Vec<&str> {
+        self.keys
+            .list(DESKTOP_ENTRY, "Categories")
+            .unwrap_or_default()
     }
 }
 
-pub async fn list_local_applications() -> anyhow::Result<Vec<Application>> {
-    let applications = basedir::applications()?;
-    let futures = applications
+/// The `[Desktop Action ...]` groups listed in the `Actions` key, with a name and a command
+fn parse_actions(keys: &KeyFile, locale: &Locale) -> Vec<ApplicationAction> {
+    keys.list(DESKTOP_ENTRY, "Actions")
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|id| {
+            let group = format!("{DESKTOP_ACTION_PREFIX}{id}");
+            Some(ApplicationAction {
+                id: id.to_owned(),
+                name: keys.localized(&group, "Name", locale)?.to_owned(),
+                exec: keys.get(&group, "Exec")?.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// XDG directories containing the desktop entries, by decreasing priority.
+/// The flatpak and snap exports come last, they may be missing from `$XDG_DATA_DIRS`
+pub fn application_dirs() -> anyhow::Result<Vec<PathBuf>> {
+    let data_home = PathBuf::from(basedir::data_home()?);
+    Ok(iter::once(data_home.clone())
+        .chain(
+            basedir::data_dirs()?
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+        )
+        .map(|dir| dir.join("applications"))
+        .chain(providers::packages::export_dirs(&data_home))
+        .unique()
+        .collect())
+}
+
+/// Desktops of the session, used by `OnlyShowIn` and `NotShowIn`
+pub fn current_desktops() -> Vec<String> {
+    env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
         .split(':')
-        .flat_map(fs::read_dir)
-        .flat_map(iter::IntoIterator::into_iter)
+        .filter(|desktop| !desktop.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+pub fn is_desktop_entry(path: &Path) -> bool {
+    path.is_file() && path.extension() == Some(OsStr::new("desktop"))
+}
+
+/// Desktop entries of an application dir by desktop file id, eg: `kde4/app.desktop` is
+/// `kde4-app.desktop`
+fn desktop_files(dir: &Path, prefix: &str) -> Vec<(String, PathBuf)> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
         .filter_map(|res| res.ok().map(|entry| entry.path()))
-        .filter(|path| path.is_file() && path.extension() == Some(OsStr::new("desktop")))
-        .map(Application::parse);
-    Ok(join_all(futures)
+        .flat_map(|path| {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if path.is_dir() {
+                desktop_files(&path, &format!("{prefix}{name}-"))
+            } else if is_desktop_entry(&path) {
+                vec![(format!("{prefix}{name}"), path)]
+            } else {
+                vec![]
+            }
+        })
+        .collect()
+}
+
+pub async fn list_local_applications() -> anyhow::Result<Vec<Application>> {
+    Ok(list_local_application_files(&Locale::from_env())
+        .await?
+        .into_iter()
+        .map(|(_path, application)| application)
+        .collect())
+}
+
+/// Like [`list_local_applications`], with the path of their desktop entry or of what it was
+/// generated from
+pub async fn list_local_application_files(
+    locale: &Locale,
+) -> anyhow::Result<Vec<(PathBuf, Application)>> {
+    let mut applications =
+        list_applications_in(&application_dirs()?, locale, &current_desktops()).await;
+    let provided = tokio::task::spawn_blocking(|| providers::list(&providers::Roots::from_env()?))
+        .await?
+        .inspect_err(|err| warn!("Could not list the games of the launchers: {err:#}"))
+        .unwrap_or_default();
+    // the desktop shortcut of a game is preferred, it may have been customized
+    let provided = provided
+        .into_iter()
+        .filter(|(_path, provided)| {
+            !applications
+                .iter()
+                .any(|(_path, application)| application.name == provided.name)
+        })
+        .collect_vec();
+    applications.extend(provided);
+    Ok(applications)
+}
+
+/// The applications to show in the menus of `desktops`, an entry of a dir shadows the entries
+/// with the same desktop file id in the next `dirs`
+pub async fn list_applications_in(
+    dirs: &[PathBuf],
+    locale: &Locale,
+    desktops: &[String],
+) -> Vec<(PathBuf, Application)> {
+    let futures = dirs
+        .iter()
+        .flat_map(|dir| desktop_files(dir, ""))
+        .unique_by(|(id, _path)| id.clone())
+        .map(|(_id, path)| async {
+            let application = Application::parse_localized(&path, locale).await?;
+            anyhow::Ok((path, application))
+        });
+    join_all(futures)
         .await
         .into_iter()
         .filter_map(Result::ok)
-        .collect())
+        .filter(|(_path, application)| {
+            !application.is_hidden() && application.is_displayed(desktops)
+        })
+        .collect()
 }
 
 #[expect(clippy::module_name_repetitions, reason = "more clear")]
@@ -143,6 +562,8 @@ pub enum ApplicationInfoErrorKind {
     NoName,
     #[error("Missing the exec field")]
     NoExec,
+    #[error("{0} is not installed")]
+    TryExecNotFound(String),
     #[error("Missing an icon")]
     NoIcon,
     #[error("Failed to read icon {0}")]
@@ -175,23 +596,23 @@ impl TryInto<ApplicationInfo> for Application {
                 kind: ApplicationInfoErrorKind::NoExec,
             });
         };
-        let category = self.categories().first().copied().unwrap_or_default();
-        let category = category.to_string();
-        let category = category.strip_prefix('"').unwrap_or(&category);
-        let category = category.strip_suffix('"').unwrap_or(category);
-        let category = if category.is_empty() {
-            "Misc"
-        } else {
-            category
-        };
-        let category = category.to_owned();
+        if let Some(try_exec) = &self.entry.try_exec {
+            if exec::find_program(try_exec).is_none() {
+                let try_exec = try_exec.clone();
+                return Err(ApplicationInfoError {
+                    application: self,
+                    kind: ApplicationInfoErrorKind::TryExecNotFound(try_exec),
+                });
+            }
+        }
+        let categories = self.categories();
+        let category = categories.first().copied().unwrap_or(MISC_GROUP).to_owned();
+        let categories = categories.into_iter().map(ToOwned::to_owned).collect();
 
         // nice 😐️
-        let icon_bytes = (|| ImageReader::open(self.icon()?).ok()?.decode().ok())().map(|icon| {
-            icon.pixels()
-                .flat_map(|(_, _, rgba)| rgba.channels().to_vec())
-                .collect_vec()
-        });
+        let encoded =
+            (|| IconData::encode(&ImageReader::open(self.icon()?).ok()?.decode().ok()?).ok())();
+        let (icon, icon_png) = encoded.map_or((None, None), |(icon, png)| (Some(icon), Some(png)));
 
         #[expect(clippy::map_unwrap_or, reason = "unreachable :)")]
         let icon_name = self
@@ -203,51 +624,392 @@ impl TryInto<ApplicationInfo> for Application {
             name: name.to_owned(),
             exec: exec.to_owned(),
             category,
-            icon_bytes,
+            categories,
+            generic_name: self.generic_name.clone(),
+            keywords: self.keywords.clone(),
+            icon,
             icon_name,
+            terminal: self.entry.terminal.unwrap_or_default(),
+            path: self
+                .entry
+                .path
+                .clone()
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+            icon_key: self.entry.icon.clone(),
+            desktop_file: (!self.generated).then(|| self.path.clone()),
+            actions: self.actions.clone(),
+            icon_png,
         })
     }
 }
 
 impl ApplicationDisplay {
-    async fn try_from(value: ApplicationInfo) -> anyhow::Result<Self> {
-        let icon = if let Some(icon) = value.icon() {
-            cache::cache_image(value.icon_name(), icon).with_context(|| {
-                format!("Error while trying to cache icon {}", value.icon_name())
-            })?
+    async fn new(value: ApplicationInfo, icon_provider: &IconProviderCfg) -> Self {
+        let icon = if let Some(icon) = value.icon.as_ref().filter(|icon| icon.is_valid()) {
+            // it may not be received yet, the agent is asked for the missing ones
+            Some(cache::icon_url(&icon.hash))
         } else {
-            cache::icon::cache_find_icon(&value.name)
+            cache::icon::cache_find_icon(&value.name, icon_provider)
                 .await
-                .with_context(|| {
-                    format!(
-                        "Failed to search an icon on the web for application `{}`",
+                .inspect_err(|err| {
+                    warn!(
+                        "Failed to find an icon for application `{}`: {err:#}",
                         &value.name
-                    )
-                })?
+                    );
+                })
+                .ok()
+                .flatten()
         };
-        Ok(Self {
+        Self {
             name: value.name,
             icon,
-        })
+            actions: value
+                .actions
+                .into_iter()
+                .map(|action| ApplicationActionDisplay {
+                    id: action.id,
+                    name: action.name,
+                })
+                .collect(),
+        }
     }
 }
 
 impl GroupedApplication {
-    pub async fn from_list(value: Vec<ApplicationInfo>) -> Self {
+    pub async fn from_list(
+        value: Vec<ApplicationInfo>,
+        grouping: &ApplicationGrouping,
+        icon_provider: &IconProviderCfg,
+    ) -> Self {
         let groups = value.into_iter().map(|info| async {
-            Some((
-                info.category.clone(),
-                ApplicationDisplay::try_from(info)
-                    .await
-                    .inspect_err(|err| warn!("while processing applications: {:#}", err))
-                    .ok()?,
-            ))
+            let groups = grouping.groups_of(&info);
+            let display = ApplicationDisplay::new(info, icon_provider).await;
+            groups
+                .into_iter()
+                .map(move |group| (group, display.clone()))
         });
         let groups = join_all(groups)
             .await
             .into_iter()
             .flatten()
             .into_group_map();
-        Self { groups }
+        let mut grouped = Self {
+            groups,
+            order: vec![],
+        };
+        grouped.sort(grouping);
+        grouped
+    }
+
+    /// Adds or replaces applications, only their icons are processed
+    pub async fn insert(
+        &mut self,
+        applications: Vec<ApplicationInfo>,
+        grouping: &ApplicationGrouping,
+        icon_provider: &IconProviderCfg,
+    ) {
+        let names: Vec<String> = applications.iter().map(|app| app.name.clone()).collect();
+        self.remove(&names);
+        for (group, displays) in Self::from_list(applications, grouping, icon_provider)
+            .await
+            .groups
+        {
+            self.groups.entry(group).or_default().extend(displays);
+        }
+        self.sort(grouping);
+    }
+
+    /// How the application named `name` is shown
+    pub fn display(&self, name: &str) -> Option<&ApplicationDisplay> {
+        self.groups
+            .values()
+            .flatten()
+            .find(|display| display.name == name)
+    }
+
+    /// Removes the applications named `names`, and the groups left empty
+    pub fn remove(&mut self, names: &[String]) {
+        for displays in self.groups.values_mut() {
+            displays.retain(|display| !names.contains(&display.name));
+        }
+        self.groups.retain(|_group, displays| !displays.is_empty());
+        self.order.retain(|group| self.groups.contains_key(group));
+    }
+
+    /// Orders the groups like the config, and the favourites like `favourites`
+    fn sort(&mut self, grouping: &ApplicationGrouping) {
+        self.order = grouping
+            .order()
+            .filter(|group| self.groups.contains_key(*group))
+            .map(ToOwned::to_owned)
+            .unique()
+            .collect();
+        if let Some(favourites) = self.groups.get_mut(FAVOURITES_GROUP) {
+            favourites.sort_by_key(|display| {
+                grouping
+                    .applications
+                    .favourites
+                    .iter()
+                    .position(|name| *name == display.name)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, CustomGroupCfg};
+    use figment::{
+        providers::{Format as _, Yaml},
+        Figment,
+    };
+    use rstest::rstest;
+
+    fn display(name: &str) -> ApplicationDisplay {
+        ApplicationDisplay {
+            name: name.to_owned(),
+            icon: Some(format!("/api/cache/images/{name}.png")),
+            actions: vec![],
+        }
+    }
+
+    #[test]
+    fn test_icon_data() {
+        let image = DynamicImage::new_rgba8(512, 256);
+        let (icon, png) = IconData::encode(&image).unwrap();
+        assert_eq!((icon.width, icon.height), (128, 64));
+        assert!(icon.is_valid());
+        let app = ApplicationInfo {
+            name: "Firefox".to_owned(),
+            icon: Some(icon.clone()),
+            icon_name: "firefox.png".to_owned(),
+            exec: "firefox".to_owned(),
+            category: "Network".to_owned(),
+            categories: vec![],
+            generic_name: None,
+            keywords: vec![],
+            terminal: false,
+            path: None,
+            icon_key: Some("firefox".to_owned()),
+            desktop_file: None,
+            actions: vec![],
+            icon_png: Some(png.clone()),
+        };
+        let data = app.icon_data().unwrap();
+        assert_eq!(data.decode().unwrap(), png);
+
+        let tampered = IconData {
+            icon: IconRef {
+                width: 128,
+                height: 128,
+                ..icon
+            },
+            png: data.png.clone(),
+        };
+        tampered.decode().expect_err("the dimensions don't match");
+        let tampered = IconData {
+            icon: IconRef {
+                hash: "0".repeat(64),
+                ..data.icon
+            },
+            png: data.png,
+        };
+        tampered.decode().expect_err("the hash doesn't match");
+    }
+
+    #[rstest]
+    #[case(
+        "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+        true
+    )]
+    #[case("../../../etc/passwd", false)]
+    #[case("0123", false)]
+    fn test_icon_hash(#[case] hash: &str, #[case] valid: bool) {
+        let icon = IconRef {
+            hash: hash.to_owned(),
+            width: 1,
+            height: 1,
+        };
+        assert_eq!(icon.is_valid(), valid);
+    }
+
+    #[tokio::test]
+    async fn test_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("firefox.desktop");
+        fs::write(
+            &path,
+            "[Desktop Entry]
+Name=Firefox
+Exec=firefox %u
+Actions=new-window;new-private-window;
+# not listed in Actions
+[Desktop Action profile-manager]
+Name=Profile Manager
+Exec=firefox --ProfileManager
+
+[Desktop Action new-window]
+Name=New Window
+Name[fr]=Nouvelle fenetre
+Exec=firefox --new-window %u
+
+[Desktop Action new-private-window]
+Name=New Private Window
+Exec=firefox --private-window %u
+",
+        )
+        .unwrap();
+        let application = Application::parse(&path).await.unwrap();
+        assert_eq!(
+            application
+                .actions
+                .iter()
+                .map(|action| (action.id.as_str(), action.name.as_str()))
+                .collect_vec(),
+            [
+                ("new-window", "New Window"),
+                ("new-private-window", "New Private Window")
+            ]
+        );
+        let app = ApplicationInfo {
+            name: "Firefox".to_owned(),
+            icon: None,
+            icon_name: "no-icon".to_owned(),
+            exec: "firefox %u".to_owned(),
+            category: "Network".to_owned(),
+            categories: vec![],
+            generic_name: None,
+            keywords: vec![],
+            terminal: false,
+            path: None,
+            icon_key: None,
+            desktop_file: None,
+            actions: application.actions,
+            icon_png: None,
+        };
+        let url = ["https://example.com".to_owned()];
+        assert_eq!(
+            app.argv(Some("new-private-window"), &url).unwrap(),
+            ["firefox", "--private-window", "https://example.com"]
+        );
+        assert_eq!(
+            app.argv(None, &url).unwrap(),
+            ["firefox", "https://example.com"]
+        );
+        app.argv(Some("profile-manager"), &url)
+            .expect_err("unlisted actions are ignored");
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut grouped = GroupedApplication {
+            groups: HashMap::from([
+                (
+                    "Game".to_owned(),
+                    vec![display("Factorio"), display("Steam")],
+                ),
+                ("Network".to_owned(), vec![display("Firefox")]),
+            ]),
+            order: vec!["Game".to_owned(), "Network".to_owned()],
+        };
+        grouped.remove(&["Steam".to_owned(), "Firefox".to_owned()]);
+        assert_eq!(
+            grouped.groups,
+            HashMap::from([("Game".to_owned(), vec![display("Factorio")])]),
+            "empty groups should be removed"
+        );
+        assert_eq!(grouped.order, ["Game"]);
+    }
+
+    pub(in super::super) fn application(name: &str, categories: &[&str]) -> ApplicationInfo {
+        ApplicationInfo {
+            name: name.to_owned(),
+            icon: None,
+            icon_name: "no-icon".to_owned(),
+            exec: name.to_lowercase(),
+            category: categories.first().copied().unwrap_or(MISC_GROUP).to_owned(),
+            categories: categories
+                .iter()
+                .map(|&category| category.to_owned())
+                .collect(),
+            generic_name: None,
+            keywords: vec![],
+            terminal: false,
+            path: None,
+            icon_key: None,
+            desktop_file: None,
+            actions: vec![],
+            icon_png: None,
+        }
+    }
+
+    fn grouping() -> ApplicationGrouping {
+        let config: Config = Figment::new()
+            .merge(Yaml::string(include_str!(
+                "../../../tests/simple_config.yml"
+            )))
+            .extract()
+            .unwrap();
+        ApplicationGrouping::new(
+            &config.application_groups,
+            &ApplicationsCfg {
+                favourites: vec!["Firefox".to_owned(), "Factorio".to_owned()],
+                groups: vec![CustomGroupCfg {
+                    name: "Work".to_owned(),
+                    applications: vec!["Slack".to_owned()],
+                }],
+            },
+        )
+    }
+
+    #[rstest]
+    #[case(application("Factorio", &["Game", "StrategyGame"]), &["Favourites", "Games"])]
+    #[case(application("Minetest", &["ActionGame", "Game"]), &["Games"])]
+    #[case(application("Gedit", &["Utility", "TextEditor", "Development"]), &["Development"])]
+    #[case(application("Control Center", &["Settings", "System"]), &["Settings"])]
+    #[case(application("Slack", &["Network", "Chat"]), &["Work"])]
+    #[case(application("Wine", &["X-Wine"]), &["Misc"])]
+    #[case(application("Uncategorized", &[]), &["Misc"])]
+    fn test_groups_of(#[case] application: ApplicationInfo, #[case] expected: &[&str]) {
+        assert_eq!(grouping().groups_of(&application), expected);
+    }
+
+    #[test]
+    fn test_groups_of_old_agent() {
+        let mut application = application("Firefox", &["Network"]);
+        application.categories = vec![];
+        assert_eq!(
+            grouping().groups_of(&application),
+            ["Favourites", "Internet"]
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut grouped = GroupedApplication {
+            groups: HashMap::from([
+                ("Misc".to_owned(), vec![display("Wine")]),
+                ("Internet".to_owned(), vec![display("Firefox")]),
+                ("Work".to_owned(), vec![display("Slack")]),
+                ("Games".to_owned(), vec![display("Factorio")]),
+                (
+                    "Favourites".to_owned(),
+                    vec![display("Factorio"), display("Firefox")],
+                ),
+            ]),
+            order: vec![],
+        };
+        grouped.sort(&grouping());
+        assert_eq!(
+            grouped.order,
+            ["Favourites", "Work", "Games", "Internet", "Misc"]
+        );
+        assert_eq!(
+            grouped.groups["Favourites"],
+            [display("Firefox"), display("Factorio")],
+            "favourites should be in the order of the config"
+        );
     }
 }
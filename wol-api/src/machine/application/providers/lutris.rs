@@ -0,0 +1,141 @@
+//! Games installed by Lutris, listed in its `pga.db`
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use rusqlite::{Connection, OpenFlags};
+
+use super::{existing, generate, Launcher, Roots};
+use crate::machine::application::Application;
+
+const FLATPAK_ID: &str = "net.lutris.Lutris";
+const DATABASE: &str = "pga.db";
+
+/// The data directories of the native and flatpak Lutris installs
+pub fn roots(roots: &Roots) -> Vec<Launcher> {
+    let candidates = vec![
+        Launcher {
+            path: roots.data_home.join("lutris"),
+            flatpak_id: None,
+        },
+        Launcher {
+            path: roots
+                .home
+                .join(".var/app")
+                .join(FLATPAK_ID)
+                .join("data/lutris"),
+            flatpak_id: Some(FLATPAK_ID),
+        },
+    ];
+    existing(candidates, DATABASE)
+}
+
+/// The installed games of `root`, keyed by `pga.db#<id>` since they have no file of their own
+pub fn games(root: &Launcher) -> anyhow::Result<Vec<(PathBuf, Application)>> {
+    let database = root.path.join(DATABASE);
+    let connection = Connection::open_with_flags(&database, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Could not open {}", database.display()))?;
+    let mut statement = connection
+        .prepare("SELECT id, name, slug FROM games WHERE installed = 1 ORDER BY id")
+        .context("Unexpected lutris database")?;
+    let games = statement
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Unexpected lutris database")?;
+    Ok(games
+        .into_iter()
+        .map(|(id, name, slug)| {
+            let source = PathBuf::from(format!("{}#{id}", database.display()));
+            // the same command as the shortcuts created by lutris
+            let exec = root.command(
+                "env LUTRIS_SKIP_INIT=1 lutris",
+                &format!("lutris:rungameid/{id}"),
+            );
+            let icon = find_icon(&root.path, &slug);
+            let application = generate(source.clone(), &name, &exec, Some(&icon), &["Game"]);
+            (source, application)
+        })
+        .collect())
+}
+
+/// Older versions kept the icons in their data directory, they are now in the icon theme
+fn find_icon(root: &Path, slug: &str) -> PathBuf {
+    let icon = root.join("icons").join(format!("{slug}.png"));
+    if icon.is_file() {
+        return icon;
+    }
+    PathBuf::from(format!("lutris_{slug}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools as _;
+    use std::fs;
+
+    #[test]
+    fn test_games() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = Launcher {
+            path: dir.path().join("lutris"),
+            flatpak_id: None,
+        };
+        fs::create_dir_all(root.path.join("icons")).unwrap();
+        fs::write(root.path.join("icons/celeste.png"), b"").unwrap();
+        let connection = Connection::open(root.path.join(DATABASE)).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE games (
+                    id INTEGER PRIMARY KEY, name TEXT, slug TEXT, runner TEXT, installed INTEGER
+                );
+                INSERT INTO games VALUES (3, 'outerwilds3', 'outerwilds3', 'wine', 1);
+                INSERT INTO games VALUES (4, 'Celeste', 'celeste', 'linux', 1);
+                INSERT INTO games VALUES (5, 'Uninstalled', 'uninstalled', 'wine', 0);",
+            )
+            .unwrap();
+        drop(connection);
+
+        let games = games(&root)
+            .unwrap()
+            .into_iter()
+            .map(|(path, application)| {
+                (
+                    path.strip_prefix(&root.path).unwrap().display().to_string(),
+                    application.name().clone().unwrap(),
+                    application.exec().clone().unwrap(),
+                    application.entry.icon.clone(),
+                )
+            })
+            .collect_vec();
+        assert_eq!(
+            games,
+            [
+                (
+                    "pga.db#3".to_owned(),
+                    "outerwilds3".to_owned(),
+                    "env LUTRIS_SKIP_INIT=1 lutris lutris:rungameid/3".to_owned(),
+                    Some("lutris_outerwilds3".to_owned()),
+                ),
+                (
+                    "pga.db#4".to_owned(),
+                    "Celeste".to_owned(),
+                    "env LUTRIS_SKIP_INIT=1 lutris lutris:rungameid/4".to_owned(),
+                    Some(root.path.join("icons/celeste.png").display().to_string()),
+                ),
+            ]
+        );
+        assert_eq!(
+            roots(&Roots {
+                home: dir.path().to_owned(),
+                data_home: dir.path().to_owned(),
+            }),
+            [root]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Applications without a desktop entry, found in the libraries of the game launchers.
+//! A desktop entry is generated for each of them so they are read like the others
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use log::warn;
+use xdgkit::basedir;
+
+use super::{
+    desktop_file::{Locale, DESKTOP_ENTRY},
+    Application,
+};
+
+pub mod lutris;
+pub mod packages;
+pub mod steam;
+
+/// Where the launchers are installed, the tests use fixture directories
+#[derive(Debug, Clone)]
+pub struct Roots {
+    pub home: PathBuf,
+    /// `$XDG_DATA_HOME`
+    pub data_home: PathBuf,
+}
+
+impl Roots {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            home: env::var_os("HOME")
+                .map(PathBuf::from)
+                .context("$HOME is not set")?,
+            data_home: PathBuf::from(basedir::data_home()?),
+        })
+    }
+}
+
+/// The games of every launcher installed, a launcher that fails is skipped
+pub fn list(roots: &Roots) -> anyhow::Result<Vec<(PathBuf, Application)>> {
+    let mut applications = vec![];
+    for root in steam::roots(roots) {
+        applications.extend(steam::games(&root, &steam::libraries(&root)));
+    }
+    for root in lutris::roots(roots) {
+        match lutris::games(&root) {
+            Ok(games) => applications.extend(games),
+            Err(err) => warn!("Could not list the lutris games: {err:#}"),
+        }
+    }
+    Ok(applications)
+}
+
+/// Where the games are installed or removed, to watch them
+pub fn watched_dirs(roots: &Roots) -> Vec<PathBuf> {
+    steam::roots(roots)
+        .iter()
+        .flat_map(steam::libraries)
+        .map(|library| library.join("steamapps"))
+        .chain(lutris::roots(roots).into_iter().map(|root| root.path))
+        .collect()
+}
+
+/// A launcher installed natively or with flatpak, eg: `flatpak run com.valvesoftware.Steam`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Launcher {
+    pub path: PathBuf,
+    pub flatpak_id: Option<&'static str>,
+}
+
+impl Launcher {
+    /// `program` or its flatpak, followed by `arguments`
+    fn command(&self, program: &str, arguments: &str) -> String {
+        self.flatpak_id.map_or_else(
+            || format!("{program} {arguments}"),
+            |id| format!("flatpak run {id} {arguments}"),
+        )
+    }
+}
+
+/// The launchers found in `candidates`, a launcher linked from several places is only
+/// returned once
+fn existing(candidates: Vec<Launcher>, marker: &str) -> Vec<Launcher> {
+    let mut found: Vec<(PathBuf, Launcher)> = vec![];
+    for launcher in candidates {
+        let Ok(canonical) = launcher.path.canonicalize() else {
+            continue;
+        };
+        if canonical.join(marker).exists() && !found.iter().any(|(path, _)| *path == canonical) {
+            found.push((canonical, launcher));
+        }
+    }
+    found
+        .into_iter()
+        .map(|(_path, launcher)| launcher)
+        .collect()
+}
+
+/// `source` is what the application was found in, it is used as its key by the agent
+fn generate(
+    source: PathBuf,
+    name: &str,
+    exec: &str,
+    icon: Option<&Path>,
+    categories: &[&str],
+) -> Application {
+    let mut entry = format!(
+        "[{DESKTOP_ENTRY}]\nType=Application\nName={}\nExec={exec}\nCategories={};\n",
+        name.replace(['\n', '\r'], " "),
+        categories.join(";")
+    );
+    if let Some(icon) = icon {
+        entry.push_str("Icon=");
+        entry.push_str(&icon.to_string_lossy());
+        entry.push('\n');
+    }
+    let mut application = Application::from_entry(entry, source, &Locale::default());
+    application.generated = true;
+    application
+}
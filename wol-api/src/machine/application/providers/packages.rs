@@ -0,0 +1,64 @@
+//! Desktop entries exported by flatpak and snap
+
+use std::path::{Path, PathBuf};
+
+/// Sizes of the icons tried, the closest to [`crate::cache::IMAGE_SIZE`] first
+const ICON_SIZES: &[&str] = &["128x128", "256x256", "512x512", "96x96", "64x64", "48x48"];
+
+/// Flatpak `exports/share` directories of the user then of the system
+fn flatpak_exports(data_home: &Path) -> [PathBuf; 2] {
+    [
+        data_home.join("flatpak/exports/share"),
+        PathBuf::from("/var/lib/flatpak/exports/share"),
+    ]
+}
+
+/// Where flatpak and snap put the desktop entries of the applications they installed
+pub fn export_dirs(data_home: &Path) -> Vec<PathBuf> {
+    flatpak_exports(data_home)
+        .into_iter()
+        .map(|share| share.join("applications"))
+        .chain([PathBuf::from("/var/lib/snapd/desktop/applications")])
+        .collect()
+}
+
+/// The icon `name` exported by a flatpak, snaps use absolute paths
+pub fn find_exported_icon(name: &str) -> Option<PathBuf> {
+    let data_home = PathBuf::from(xdgkit::basedir::data_home().ok()?);
+    find_icon_in(&flatpak_exports(&data_home), name)
+}
+
+fn find_icon_in(shares: &[PathBuf], name: &str) -> Option<PathBuf> {
+    shares.iter().find_map(|share| {
+        ICON_SIZES
+            .iter()
+            .map(|size| share.join(format!("icons/hicolor/{size}/apps/{name}.png")))
+            .find(|path| path.is_file())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_find_icon_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let shares = flatpak_exports(dir.path());
+        assert_eq!(
+            export_dirs(dir.path())[0],
+            dir.path().join("flatpak/exports/share/applications")
+        );
+        let apps = shares[0].join("icons/hicolor");
+        for size in ["64x64", "256x256"] {
+            fs::create_dir_all(apps.join(size).join("apps")).unwrap();
+            fs::write(apps.join(size).join("apps/org.gnome.Chess.png"), b"").unwrap();
+        }
+        assert_eq!(
+            find_icon_in(&shares, "org.gnome.Chess"),
+            Some(apps.join("256x256/apps/org.gnome.Chess.png"))
+        );
+        assert_eq!(find_icon_in(&shares, "org.gnome.Mines"), None);
+    }
+}
@@ -0,0 +1,387 @@
+//! Games installed by Steam, listed in the `appmanifest_<id>.acf` of its libraries
+
+use std::{
+    fs,
+    iter::{self, Peekable},
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools as _;
+use log::debug;
+
+use super::{existing, generate, Launcher, Roots};
+use crate::machine::application::Application;
+
+const FLATPAK_ID: &str = "com.valvesoftware.Steam";
+/// `StateFlags` bit of the fully installed games
+const FULLY_INSTALLED: u32 = 4;
+/// Installed like games but not playable
+const TOOLS: &[&str] = &[
+    "Proton",
+    "Steam Linux Runtime",
+    "Steamworks Common Redistributables",
+    "SteamVR",
+];
+
+/// The native and flatpak Steam installs
+pub fn roots(roots: &Roots) -> Vec<Launcher> {
+    let flatpak = roots.home.join(".var/app").join(FLATPAK_ID);
+    let candidates = [
+        (roots.data_home.join("Steam"), None),
+        (roots.home.join(".steam/steam"), None),
+        (roots.home.join(".steam/root"), None),
+        (flatpak.join(".local/share/Steam"), Some(FLATPAK_ID)),
+        (flatpak.join(".steam/steam"), Some(FLATPAK_ID)),
+    ]
+    .into_iter()
+    .map(|(path, flatpak_id)| Launcher { path, flatpak_id })
+    .collect();
+    existing(candidates, "steamapps")
+}
+
+/// The install dir of `root` and the libraries of its `libraryfolders.vdf`
+pub fn libraries(root: &Launcher) -> Vec<PathBuf> {
+    let vdf = root.path.join("steamapps/libraryfolders.vdf");
+    let folders = fs::read_to_string(&vdf)
+        .inspect_err(|err| debug!("Could not read {}: {err}", vdf.display()))
+        .ok()
+        .and_then(|content| parse(&content))
+        .map(|(_key, folders)| {
+            folders
+                .entries()
+                .iter()
+                .filter_map(|(_index, folder)| match folder {
+                    // before 2021 it was only the path
+                    Value::String(path) => Some(PathBuf::from(path)),
+                    Value::Object(_) => folder.get("path")?.as_str().map(PathBuf::from),
+                })
+                .collect_vec()
+        })
+        .unwrap_or_default();
+    iter::once(root.path.clone())
+        .chain(folders)
+        .unique()
+        .collect()
+}
+
+/// The installed games of the `libraries` of `root`
+pub fn games(root: &Launcher, libraries: &[PathBuf]) -> Vec<(PathBuf, Application)> {
+    libraries
+        .iter()
+        .flat_map(|library| {
+            fs::read_dir(library.join("steamapps"))
+                .into_iter()
+                .flatten()
+        })
+        .filter_map(|res| res.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|extension| extension == "acf")
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("appmanifest_"))
+        })
+        .sorted()
+        .filter_map(|manifest| {
+            let content = fs::read_to_string(&manifest).ok()?;
+            let game = Game::parse(&content)?;
+            let exec = root.command("steam", &format!("-applaunch {}", game.id));
+            let icon = find_icon(&root.path, &game.id);
+            let application = generate(manifest.clone(), &game.name, &exec, Some(&icon), &["Game"]);
+            Some((manifest, application))
+        })
+        .collect()
+}
+
+/// The icon cached by the Steam client, or the one of its desktop shortcuts
+fn find_icon(root: &Path, id: &str) -> PathBuf {
+    let cache = root.join("appcache/librarycache");
+    let icon = cache.join(format!("{id}_icon.jpg"));
+    if icon.is_file() {
+        return icon;
+    }
+    // since 2024 the icon is named after its sha1
+    fs::read_dir(cache.join(id))
+        .into_iter()
+        .flatten()
+        .filter_map(|res| res.ok().map(|entry| entry.path()))
+        .find(|path| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("jpg"))
+                && path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(|stem| {
+                        stem.len() == 40 && stem.bytes().all(|byte| byte.is_ascii_hexdigit())
+                    })
+        })
+        .unwrap_or_else(|| PathBuf::from(format!("steam_icon_{id}")))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Game {
+    id: String,
+    name: String,
+}
+
+impl Game {
+    /// `None` for the tools and the games not fully installed
+    fn parse(manifest: &str) -> Option<Self> {
+        let (_key, state) = parse(manifest)?;
+        let flags: u32 = state.get("StateFlags")?.as_str()?.parse().ok()?;
+        let name = state.get("name")?.as_str()?;
+        if flags & FULLY_INSTALLED == 0 || TOOLS.iter().any(|tool| name.starts_with(tool)) {
+            return None;
+        }
+        Some(Self {
+            id: state.get("appid")?.as_str()?.to_owned(),
+            name: name.to_owned(),
+        })
+    }
+}
+
+/// Valve's `KeyValues` text format, used by the `.vdf` and `.acf` files
+#[derive(Debug, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Object(Vec<(String, Self)>),
+}
+
+impl Value {
+    /// The keys are case insensitive
+    fn get(&self, key: &str) -> Option<&Self> {
+        self.entries()
+            .iter()
+            .find(|(name, _value)| name.eq_ignore_ascii_case(key))
+            .map(|(_key, value)| value)
+    }
+
+    fn entries(&self) -> &[(String, Self)] {
+        match self {
+            Self::Object(entries) => entries,
+            Self::String(_) => &[],
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            Self::Object(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    String(String),
+}
+
+/// The root key and its value, lenient like Steam
+fn parse(content: &str) -> Option<(String, Value)> {
+    let mut tokens = tokenize(content).into_iter().peekable();
+    let Some(Token::String(key)) = tokens.next() else {
+        return None;
+    };
+    match tokens.next()? {
+        Token::Open => Some((key, Value::Object(parse_object(&mut tokens)))),
+        Token::String(value) => Some((key, Value::String(value))),
+        Token::Close => None,
+    }
+}
+
+fn parse_object(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Vec<(String, Value)> {
+    let mut entries = vec![];
+    while let Some(token) = tokens.next() {
+        let Token::String(key) = token else {
+            // the end of this object, or a stray brace
+            if token == Token::Close {
+                break;
+            }
+            continue;
+        };
+        match tokens.next() {
+            Some(Token::Open) => entries.push((key, Value::Object(parse_object(tokens)))),
+            Some(Token::String(value)) => entries.push((key, Value::String(value))),
+            Some(Token::Close) | None => break,
+        }
+    }
+    entries
+}
+
+fn tokenize(content: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = content.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '/' if chars.peek() == Some(&'/') => {
+                chars
+                    .by_ref()
+                    .take_while(|&next| next != '\n')
+                    .for_each(drop);
+            }
+            '"' => {
+                let mut string = String::new();
+                while let Some(next) = chars.next() {
+                    match next {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some(escaped) => string.push(escaped),
+                            None => break,
+                        },
+                        other => string.push(other),
+                    }
+                }
+                tokens.push(Token::String(string));
+            }
+            _ if char.is_whitespace() => {}
+            _ => {
+                let mut string = String::from(char);
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || next == '{' || next == '}' || next == '"' {
+                        break;
+                    }
+                    string.push(next);
+                    chars.next();
+                }
+                // conditionals eg: `[$WIN32]` are ignored
+                if !string.starts_with('[') {
+                    tokens.push(Token::String(string));
+                }
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::slice;
+
+    const FIXTURES: &str = "tests/assets/providers";
+
+    fn launcher() -> Launcher {
+        Launcher {
+            path: PathBuf::from(FIXTURES).join("home/.local/share/Steam"),
+            flatpak_id: None,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let (key, value) = parse(
+            r#"// comment
+"AppState"
+{
+    "appid"     "526870"
+    "Name"      "Say \"hi\"" [$WIN32]
+    "UserConfig" { "language" "english" }
+}"#,
+        )
+        .unwrap();
+        assert_eq!(key, "AppState");
+        assert_eq!(
+            value.get("name").and_then(Value::as_str),
+            Some(r#"Say "hi""#)
+        );
+        assert_eq!(
+            value
+                .get("userconfig")
+                .and_then(|config| config.get("language"))
+                .and_then(Value::as_str),
+            Some("english")
+        );
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn test_libraries() {
+        let root = launcher();
+        assert_eq!(
+            libraries(&root),
+            [
+                root.path.clone(),
+                PathBuf::from("/mnt/games/SteamLibrary"),
+                PathBuf::from("/run/media/oscar/ssd/SteamLibrary"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_games() {
+        let root = launcher();
+        let libraries = [root.path.clone(), PathBuf::from(FIXTURES).join("library")];
+        let games = games(&root, &libraries)
+            .into_iter()
+            .map(|(path, application)| {
+                (
+                    path.file_name().unwrap().to_string_lossy().into_owned(),
+                    application.name().clone().unwrap(),
+                    application.exec().clone().unwrap(),
+                    application.categories().join(";"),
+                )
+            })
+            .collect_vec();
+        let expected = [
+            (
+                "appmanifest_526870.acf",
+                "Satisfactory",
+                "steam -applaunch 526870",
+            ),
+            (
+                "appmanifest_413150.acf",
+                "Stardew Valley",
+                "steam -applaunch 413150",
+            ),
+        ]
+        .map(|(file, name, exec)| {
+            (
+                file.to_owned(),
+                name.to_owned(),
+                exec.to_owned(),
+                "Game".to_owned(),
+            )
+        });
+        assert_eq!(
+            games, expected,
+            "tools and partial installs should be skipped"
+        );
+    }
+
+    #[test]
+    fn test_flatpak_command() {
+        let root = Launcher {
+            flatpak_id: Some(FLATPAK_ID),
+            ..launcher()
+        };
+        let (_path, game) = games(&root, slice::from_ref(&root.path)).remove(0);
+        assert_eq!(
+            game.exec().as_deref(),
+            Some("flatpak run com.valvesoftware.Steam -applaunch 526870")
+        );
+    }
+
+    #[test]
+    fn test_find_icon() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("appcache/librarycache");
+        assert_eq!(
+            find_icon(dir.path(), "10"),
+            PathBuf::from("steam_icon_10"),
+            "the theme may have the icon of the shortcut"
+        );
+        let hashed = cache.join("10/0123456789abcdef0123456789abcdef01234567.jpg");
+        fs::create_dir_all(hashed.parent().unwrap()).unwrap();
+        fs::write(cache.join("10/header.jpg"), b"").unwrap();
+        fs::write(&hashed, b"").unwrap();
+        assert_eq!(find_icon(dir.path(), "10"), hashed);
+        fs::write(cache.join("10_icon.jpg"), b"").unwrap();
+        assert_eq!(find_icon(dir.path(), "10"), cache.join("10_icon.jpg"));
+    }
+}
@@ -0,0 +1,138 @@
+//! Search of the applications of a machine, ranked by how well they match and by frecency
+
+use std::{iter, path::Path};
+
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher as _};
+use itertools::Itertools as _;
+
+use super::{exec, ApplicationInfo};
+
+/// A match on the name is worth more than one on a keyword or the program
+const NAME_WEIGHT: i64 = 4;
+const GENERIC_NAME_WEIGHT: i64 = 2;
+const KEYWORD_WEIGHT: i64 = 2;
+const PROGRAM_WEIGHT: i64 = 1;
+
+/// How well `application` matches `query`, `None` if it doesn't
+fn score(matcher: &SkimMatcherV2, application: &ApplicationInfo, query: &str) -> Option<i64> {
+    let program = exec::parse(&application.exec)
+        .ok()
+        .and_then(|argv| argv.into_iter().next())
+        .and_then(|program| {
+            Path::new(&program)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        });
+    let fields = iter::once((Some(application.name.as_str()), NAME_WEIGHT))
+        .chain(iter::once((
+            application.generic_name.as_deref(),
+            GENERIC_NAME_WEIGHT,
+        )))
+        .chain(
+            application
+                .keywords
+                .iter()
+                .map(|keyword| (Some(keyword.as_str()), KEYWORD_WEIGHT)),
+        )
+        .chain(iter::once((program.as_deref(), PROGRAM_WEIGHT)));
+    fields
+        .filter_map(|(field, weight)| Some(matcher.fuzzy_match(field?, query)? * weight))
+        .max()
+}
+
+/// The applications matching `query` the best first, or every application if it is empty.
+/// Matches are boosted by the `frecency` of the applications, see
+/// [`super::launches::Launches::frecency`]
+#[expect(
+    clippy::cast_precision_loss,
+    clippy::float_arithmetic,
+    reason = "it's only used to rank the applications"
+)]
+pub fn search<'app>(
+    applications: &'app [ApplicationInfo],
+    query: &str,
+    frecency: impl Fn(&ApplicationInfo) -> f32,
+) -> Vec<&'app ApplicationInfo> {
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let query = query.trim();
+    applications
+        .iter()
+        .filter_map(|application| {
+            let score = if query.is_empty() {
+                1
+            } else {
+                score(&matcher, application, query)?
+            };
+            // the launches are a bonus, they can't make up for a bad match
+            let rank = score as f32 * (1.0 + frecency(application).ln_1p());
+            Some((rank, application))
+        })
+        .sorted_by(|(rank, application), (other_rank, other)| {
+            other_rank
+                .total_cmp(rank)
+                .then_with(|| application.name.cmp(&other.name))
+        })
+        .map(|(_rank, application)| application)
+        .unique_by(|application| &application.name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::tests::application, *};
+    use rstest::rstest;
+
+    fn applications() -> Vec<ApplicationInfo> {
+        let mut firefox = application("Firefox", &["Network"]);
+        firefox.generic_name = Some("Web Browser".to_owned());
+        firefox.keywords = vec!["internet".to_owned(), "www".to_owned()];
+        let mut files = application("Files", &["Utility"]);
+        files.exec = "/usr/bin/nautilus --new-window".to_owned();
+        files.keywords = vec!["folder".to_owned()];
+        vec![
+            firefox,
+            files,
+            application("Factorio", &["Game"]),
+            application("Steam", &["Game"]),
+        ]
+    }
+
+    #[rstest]
+    #[case("firefox", &["Firefox"])]
+    #[case("FIRE", &["Firefox"])]
+    #[case("frfx", &["Firefox"])]
+    #[case("browser", &["Firefox"])]
+    #[case("www", &["Firefox"])]
+    #[case("nautilus", &["Files"])]
+    #[case("fi", &["Files", "Firefox", "Factorio"])]
+    #[case("nothing matches", &[])]
+    #[case("  ", &["Factorio", "Files", "Firefox", "Steam"])]
+    fn test_search(#[case] query: &str, #[case] expected: &[&str]) {
+        let applications = applications();
+        let found = search(&applications, query, |_application| 0.0)
+            .into_iter()
+            .map(|application| application.name.as_str())
+            .collect_vec();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_search_frecency() {
+        let applications = applications();
+        let frecency = |application: &ApplicationInfo| {
+            if application.name == "Steam" {
+                3.0
+            } else {
+                0.0
+            }
+        };
+        let names = |query| {
+            search(&applications, query, frecency)
+                .into_iter()
+                .map(|application| application.name.as_str())
+                .collect_vec()
+        };
+        assert_eq!(names(""), ["Steam", "Factorio", "Files", "Firefox"]);
+        assert_eq!(names("firefox"), ["Firefox"], "launches don't make a match");
+    }
+}
@@ -0,0 +1,239 @@
+//! Inotify watches of the directories holding applications
+//!
+//! Desktop entries may be in subdirectories, and the directories may only be created once the
+//! agent runs, eg: by the first flatpak installed
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use inotify::{Event, EventMask, WatchDescriptor, WatchMask, Watches};
+use itertools::Itertools as _;
+use log::debug;
+
+/// A directory holding applications, with its subdirectories if `recursive`
+#[derive(Debug, Clone)]
+pub struct WatchedDir {
+    pub path: PathBuf,
+    pub recursive: bool,
+}
+
+#[derive(Debug)]
+pub struct ApplicationWatches {
+    watches: Watches,
+    roots: Vec<WatchedDir>,
+    /// Directories holding applications
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    /// Closest existing parents of the roots that don't exist yet
+    ancestors: HashMap<WatchDescriptor, PathBuf>,
+}
+
+impl ApplicationWatches {
+    pub fn new(watches: Watches, roots: Vec<WatchedDir>) -> Self {
+        let mut this = Self {
+            watches,
+            roots,
+            dirs: HashMap::new(),
+            ancestors: HashMap::new(),
+        };
+        for root in this.roots.clone() {
+            this.watch_root(&root.path);
+        }
+        this
+    }
+
+    /// Paths changed by `event`, with the files of the directories it created
+    pub fn handle<S>(&mut self, event: &Event<S>) -> Vec<PathBuf>
+    where
+        S: AsRef<OsStr>,
+    {
+        if event.mask.contains(EventMask::IGNORED) {
+            // the directory was removed, the roots in it are watched from their parents again
+            let Some(removed) = self
+                .dirs
+                .remove(&event.wd)
+                .or_else(|| self.ancestors.remove(&event.wd))
+            else {
+                return Vec::new();
+            };
+            let roots = self
+                .roots
+                .iter()
+                .filter(|root| root.path.starts_with(&removed))
+                .map(|root| root.path.clone())
+                .collect_vec();
+            return roots
+                .iter()
+                .flat_map(|root| self.watch_root(root))
+                .collect();
+        }
+        let Some(name) = &event.name else {
+            return Vec::new();
+        };
+        let created = event.mask.contains(EventMask::ISDIR)
+            && event
+                .mask
+                .intersects(EventMask::CREATE | EventMask::MOVED_TO);
+        if let Some(path) = self.dirs.get(&event.wd).map(|dir| dir.join(name.as_ref())) {
+            let mut changed = if created {
+                self.watch_created(&path)
+            } else {
+                Vec::new()
+            };
+            changed.push(path);
+            return changed;
+        }
+        match self.ancestors.get(&event.wd) {
+            Some(dir) if created => {
+                let path = dir.join(name.as_ref());
+                self.watch_created(&path)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Watches `root`, or its closest existing parent until it is created
+    fn watch_root(&mut self, root: &Path) -> Vec<PathBuf> {
+        root.ancestors()
+            .find(|dir| dir.is_dir())
+            .map(|dir| self.watch_created(dir))
+            .unwrap_or_default()
+    }
+
+    /// Watches a directory that appeared, returns the files already in it
+    fn watch_created(&mut self, dir: &Path) -> Vec<PathBuf> {
+        if let Some(root) = self.roots.iter().find(|root| dir.starts_with(&root.path)) {
+            return if root.recursive || root.path == dir {
+                let recursive = root.recursive;
+                self.watch_dir(dir, recursive)
+            } else {
+                Vec::new()
+            };
+        }
+        let next = self
+            .roots
+            .iter()
+            .filter_map(|root| {
+                let component = root.path.strip_prefix(dir).ok()?.components().next()?;
+                Some(dir.join(component))
+            })
+            .unique()
+            .collect_vec();
+        if next.is_empty() {
+            return Vec::new();
+        }
+        match self.watches.add(
+            dir,
+            WatchMask::CREATE | WatchMask::MOVED_TO | WatchMask::ONLYDIR,
+        ) {
+            Ok(watch) => {
+                self.ancestors.insert(watch, dir.to_owned());
+            }
+            Err(err) => debug!("Not watching for applications in {}: {err}", dir.display()),
+        }
+        // they may have been created before the watch
+        next.iter()
+            .filter(|dir| dir.is_dir())
+            .flat_map(|dir| self.watch_created(dir))
+            .collect()
+    }
+
+    fn watch_dir(&mut self, dir: &Path, recursive: bool) -> Vec<PathBuf> {
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        match self.watches.add(dir, mask) {
+            Ok(watch) => {
+                self.dirs.insert(watch, dir.to_owned());
+            }
+            Err(err) => {
+                debug!("Not watching applications in {}: {err}", dir.display());
+                return Vec::new();
+            }
+        }
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut files = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // symlinked directories are not followed, they could loop
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                if recursive {
+                    files.extend(self.watch_dir(&path, true));
+                }
+            } else {
+                files.push(path);
+            }
+        }
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use inotify::Inotify;
+
+    use super::*;
+
+    fn changes(inotify: &mut Inotify, watches: &mut ApplicationWatches) -> BTreeSet<PathBuf> {
+        let mut buffer = [0; 4096];
+        let mut changed = BTreeSet::new();
+        loop {
+            let events = match inotify.read_events(&mut buffer) {
+                Ok(events) => events.collect_vec(),
+                Err(err) => {
+                    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock, "{err}");
+                    return changed;
+                }
+            };
+            if events.is_empty() {
+                return changed;
+            }
+            for event in events {
+                changed.extend(watches.handle(&event));
+            }
+        }
+    }
+
+    #[test]
+    fn test_watch_created_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("share/applications");
+        let mut inotify = Inotify::init().unwrap();
+        let mut watches = ApplicationWatches::new(
+            inotify.watches(),
+            vec![WatchedDir {
+                path: root.clone(),
+                recursive: true,
+            }],
+        );
+
+        fs::create_dir_all(root.join("wine")).unwrap();
+        fs::write(root.join("wine/game.desktop"), "").unwrap();
+        assert!(changes(&mut inotify, &mut watches).contains(&root.join("wine/game.desktop")));
+
+        fs::create_dir_all(root.join("wine/programs")).unwrap();
+        fs::write(root.join("wine/programs/other.desktop"), "").unwrap();
+        fs::write(root.join("wine/game.desktop"), "[Desktop Entry]").unwrap();
+        let changed = changes(&mut inotify, &mut watches);
+        assert!(changed.contains(&root.join("wine/programs/other.desktop")));
+        assert!(changed.contains(&root.join("wine/game.desktop")));
+
+        fs::remove_dir_all(tmp.path().join("share")).unwrap();
+        changes(&mut inotify, &mut watches);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("new.desktop"), "").unwrap();
+        assert!(
+            changes(&mut inotify, &mut watches).contains(&root.join("new.desktop")),
+            "the root is watched again once recreated"
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use std::time::{Duration, Instant};
+
+use crate::config::IdleShutdownCfg;
+
+/// Shell command run over ssh to probe the idle signals of a machine.
+/// Prints the number of logged in users followed by the aggregated cpu line of `/proc/stat`
+pub const PROBE_COMMAND: &str = "who | wc -l; head -n1 /proc/stat";
+
+/// Cumulated cpu times read from the first line of `/proc/stat`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuTimes {
+    idle: u64,
+    total: u64,
+}
+
+impl CpuTimes {
+    /// Parses a line like `cpu  2255 34 2290 22625563 6290 127 456 0 0 0`
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        if fields.next()? != "cpu" {
+            return None;
+        }
+        let values: Vec<u64> = fields.map(str::parse).collect::<Result<_, _>>().ok()?;
+        // user nice system idle iowait ...
+        let idle = values.get(3)? + values.get(4).unwrap_or(&0);
+        Some(Self {
+            idle,
+            total: values.iter().sum(),
+        })
+    }
+
+    /// Cpu usage in percent between `previous` and `self`
+    pub fn usage_since(&self, previous: &Self) -> Option<u8> {
+        let total = self.total.checked_sub(previous.total)?;
+        let idle = self.idle.checked_sub(previous.idle)?;
+        if total == 0 {
+            return None;
+        }
+        let busy = total.saturating_sub(idle);
+        Some((busy * 100 / total).try_into().unwrap_or(100))
+    }
+}
+
+/// Result of [`PROBE_COMMAND`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Probe {
+    pub logged_in_users: usize,
+    pub cpu: CpuTimes,
+}
+
+impl Probe {
+    pub fn parse(output: &str) -> Option<Self> {
+        let mut lines = output.lines();
+        let logged_in_users = lines.next()?.trim().parse().ok()?;
+        let cpu = CpuTimes::parse(lines.next()?)?;
+        Some(Self {
+            logged_in_users,
+            cpu,
+        })
+    }
+}
+
+/// Everything that keeps a machine from being considered idle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Activity {
+    pub logged_in_users: usize,
+    pub ssh_sessions: usize,
+    pub vdi_opened: bool,
+    pub cpu_usage: Option<u8>,
+    /// Running machines that depend on this one
+    pub running_dependents: usize,
+}
+
+impl Activity {
+    pub fn is_idle(&self, policy: &IdleShutdownCfg) -> bool {
+        self.logged_in_users == 0
+            && self.ssh_sessions == 0
+            && !self.vdi_opened
+            && self.running_dependents == 0
+            && self
+                .cpu_usage
+                .is_some_and(|usage| usage < policy.cpu_threshold)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdleAction {
+    Nothing,
+    /// Schedule a shutdown after the grace period
+    Warn(Duration),
+    /// The machine became active again during the grace period
+    Cancel,
+}
+
+#[derive(Debug, Default)]
+pub struct IdleTracker {
+    idle_since: Option<Instant>,
+    warned: bool,
+    postponed_until: Option<Instant>,
+    vetoed: bool,
+    last_probe: Option<Instant>,
+    last_cpu: Option<CpuTimes>,
+}
+
+impl IdleTracker {
+    /// Returns true if enough time passed since the last probe
+    pub fn should_probe(&self, interval: Duration, now: Instant) -> bool {
+        self.last_probe
+            .is_none_or(|last| now.saturating_duration_since(last) >= interval)
+    }
+
+    /// Records a probe and returns the cpu usage since the previous one
+    pub fn record_probe(&mut self, probe: &Probe, now: Instant) -> Option<u8> {
+        self.last_probe = Some(now);
+        let usage = self
+            .last_cpu
+            .and_then(|previous| probe.cpu.usage_since(&previous));
+        self.last_cpu = Some(probe.cpu);
+        usage
+    }
+
+    pub fn update(
+        &mut self,
+        policy: &IdleShutdownCfg,
+        activity: &Activity,
+        now: Instant,
+    ) -> IdleAction {
+        if !activity.is_idle(policy) {
+            self.idle_since = None;
+            return if self.warned {
+                self.warned = false;
+                IdleAction::Cancel
+            } else {
+                IdleAction::Nothing
+            };
+        }
+        let idle_since = *self.idle_since.get_or_insert(now);
+        if self.warned
+            || self.vetoed
+            || self
+                .postponed_until
+                .is_some_and(|postponed_until| now < postponed_until)
+        {
+            return IdleAction::Nothing;
+        }
+        if now.saturating_duration_since(idle_since)
+            < Duration::from_secs(policy.after_minutes * 60)
+        {
+            return IdleAction::Nothing;
+        }
+        self.warned = true;
+        IdleAction::Warn(Duration::from_secs(policy.grace_period_secs))
+    }
+
+    /// Waits another idle period before warning again
+    pub fn postpone(&mut self, policy: &IdleShutdownCfg, now: Instant) {
+        self.warned = false;
+        self.postponed_until = Some(now + Duration::from_secs(policy.after_minutes * 60));
+    }
+
+    /// Disables idle shutdown until the machine is turned off
+    pub fn veto(&mut self) {
+        self.warned = false;
+        self.vetoed = true;
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const POLICY: IdleShutdownCfg = IdleShutdownCfg {
+        after_minutes: 30,
+        cpu_threshold: 5,
+        grace_period_secs: 300,
+    };
+
+    const IDLE: Activity = Activity {
+        logged_in_users: 0,
+        ssh_sessions: 0,
+        vdi_opened: false,
+        cpu_usage: Some(1),
+        running_dependents: 0,
+    };
+
+    const fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[rstest]
+    #[case("cpu  100 0 100 700 100 0 0 0 0 0", Some(CpuTimes { idle: 800, total: 1000 }))]
+    #[case("cpu  10 20 30 40", Some(CpuTimes { idle: 40, total: 100 }))]
+    #[case("cpu0 100 0 100 700 100 0 0 0 0 0", None)]
+    #[case("cpu  abc", None)]
+    #[case("", None)]
+    fn test_parse_cpu_times(#[case] line: &str, #[case] expected: Option<CpuTimes>) {
+        assert_eq!(CpuTimes::parse(line), expected);
+    }
+
+    #[test]
+    fn test_cpu_usage() {
+        let previous = CpuTimes {
+            idle: 800,
+            total: 1000,
+        };
+        let current = CpuTimes {
+            idle: 1750,
+            total: 2000,
+        };
+        assert_eq!(current.usage_since(&previous), Some(5));
+        assert_eq!(previous.usage_since(&previous), None);
+        assert_eq!(previous.usage_since(&current), None);
+    }
+
+    #[test]
+    fn test_parse_probe() {
+        assert_eq!(
+            Probe::parse("2\ncpu  10 20 30 40\n"),
+            Some(Probe {
+                logged_in_users: 2,
+                cpu: CpuTimes {
+                    idle: 40,
+                    total: 100
+                }
+            })
+        );
+        assert_eq!(Probe::parse("bash: who: command not found\n"), None);
+    }
+
+    #[rstest]
+    #[case(IDLE, true)]
+    #[case(Activity { logged_in_users: 1, ..IDLE }, false)]
+    #[case(Activity { ssh_sessions: 1, ..IDLE }, false)]
+    #[case(Activity { vdi_opened: true, ..IDLE }, false)]
+    #[case(Activity { cpu_usage: Some(5), ..IDLE }, false)]
+    #[case(Activity { cpu_usage: None, ..IDLE }, false)]
+    #[case(Activity { running_dependents: 1, ..IDLE }, false)]
+    fn test_is_idle(#[case] activity: Activity, #[case] expected: bool) {
+        assert_eq!(activity.is_idle(&POLICY), expected);
+    }
+
+    #[test]
+    fn test_idle_shutdown() {
+        let start = Instant::now();
+        let mut tracker = IdleTracker::default();
+        assert_eq!(tracker.update(&POLICY, &IDLE, start), IdleAction::Nothing);
+        assert_eq!(
+            tracker.update(&POLICY, &IDLE, start + minutes(29)),
+            IdleAction::Nothing
+        );
+        assert_eq!(
+            tracker.update(&POLICY, &IDLE, start + minutes(30)),
+            IdleAction::Warn(minutes(5))
+        );
+        assert_eq!(
+            tracker.update(&POLICY, &IDLE, start + minutes(35)),
+            IdleAction::Nothing,
+            "The shutdown is only announced once"
+        );
+    }
+
+    #[test]
+    fn test_activity_resets_idle_timer() {
+        let start = Instant::now();
+        let busy = Activity {
+            logged_in_users: 1,
+            ..IDLE
+        };
+        let mut tracker = IdleTracker::default();
+        tracker.update(&POLICY, &IDLE, start);
+        tracker.update(&POLICY, &busy, start + minutes(20));
+        assert_eq!(
+            tracker.update(&POLICY, &IDLE, start + minutes(40)),
+            IdleAction::Nothing
+        );
+        assert_eq!(
+            tracker.update(&POLICY, &IDLE, start + minutes(70)),
+            IdleAction::Warn(minutes(5))
+        );
+        assert_eq!(
+            tracker.update(&POLICY, &busy, start + minutes(71)),
+            IdleAction::Cancel
+        );
+    }
+
+    #[test]
+    fn test_postpone() {
+        let start = Instant::now();
+        let mut tracker = IdleTracker::default();
+        tracker.update(&POLICY, &IDLE, start);
+        tracker.update(&POLICY, &IDLE, start + minutes(30));
+        tracker.postpone(&POLICY, start + minutes(31));
+        assert_eq!(
+            tracker.update(&POLICY, &IDLE, start + minutes(40)),
+            IdleAction::Nothing
+        );
+        assert_eq!(
+            tracker.update(&POLICY, &IDLE, start + minutes(61)),
+            IdleAction::Warn(minutes(5))
+        );
+    }
+
+    #[test]
+    fn test_veto() {
+        let start = Instant::now();
+        let mut tracker = IdleTracker::default();
+        tracker.update(&POLICY, &IDLE, start);
+        tracker.update(&POLICY, &IDLE, start + minutes(30));
+        tracker.veto();
+        assert_eq!(
+            tracker.update(&POLICY, &IDLE, start + minutes(600)),
+            IdleAction::Nothing
+        );
+        tracker.reset();
+        tracker.update(&POLICY, &IDLE, start + minutes(600));
+        assert_eq!(
+            tracker.update(&POLICY, &IDLE, start + minutes(630)),
+            IdleAction::Warn(minutes(5))
+        );
+    }
+}
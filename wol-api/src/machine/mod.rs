@@ -1,6 +1,8 @@
 pub mod api;
 pub mod application;
+pub mod idle;
 pub mod service;
+pub mod session;
 pub mod wol;
 
 pub mod ssh;
@@ -1,30 +1,56 @@
 use super::{
-    api::responses::{AgentComunicationError, OpenVdiError},
-    application::{ApplicationInfo, GroupedApplication},
+    api::responses::{AgentComunicationError, CloseApplicationError, OpenVdiError},
+    application::{
+        exec,
+        launches::{self, Launches},
+        search, ApplicationDisplay, ApplicationGrouping, ApplicationInfo, GroupedApplication,
+        RunningApplication,
+    },
+    idle::{self, Activity, IdleAction, IdleTracker, Probe},
+    session::{self, GraphicalSession},
     wol,
 };
 use crate::{
-    agent::messages::{AgentMessage, ServerMessage, WebtransportCertificateHash},
-    config,
+    agent::{
+        exec::{ExecResult, Executions},
+        heartbeat::HeartbeatCfg,
+        messages::{
+            self, AgentMessage, Capability, Decoded, PowerAction, ServerMessage,
+            WebtransportCertificateHash,
+        },
+        metrics::{Metrics, MetricsHistory},
+        pairing::{self, Pairings},
+    },
+    cache, config,
+    consts::{
+        AGENT_EXEC_TIMEOUT, IDLE_PROBE_INTERVAL, METRICS_HISTORY_LEN, POWER_ACTION_TIMEOUT,
+        TIME_BEFORE_ASSUMING_POWER_ACTION_FAILED, TIME_BEFORE_ASSUMING_WOL_FAILED,
+    },
+    monitoring::MONITORING,
+    utils::time::unix_timestamp,
 };
 use anyhow::anyhow;
 use anyhow::Context as _;
 use futures_util::StreamExt as _;
 use futures_util::{stream::SplitSink, SinkExt as _, Stream};
-use log::{debug, error, info};
+use itertools::Itertools as _;
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use std::{
-    io,
+    collections::BTreeMap,
+    mem,
     net::{SocketAddr, ToSocketAddrs as _},
-    process,
+    path::Path,
+    slice,
     sync::{
         self,
         mpsc::{self, Receiver},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::process::Command;
+use thiserror::Error;
+use tokio::{process::Command, sync::mpsc::UnboundedReceiver, time};
 use utoipa::ToSchema;
 use warp::filters::ws::{Message, WebSocket};
 
@@ -33,6 +59,10 @@ pub type Store = sync::Arc<tokio::sync::Mutex<StoreInner>>;
 #[derive(Debug)]
 pub struct StoreInner {
     pub machines: Vec<Machine>,
+    pub pairings: Pairings,
+    pub heartbeat: HeartbeatCfg,
+    /// Applications opened from the panel, to rank the search results
+    pub launches: Launches,
 }
 
 pub async fn recv_agent_msg<R>(websocket: &mut R) -> anyhow::Result<AgentMessage>
@@ -43,14 +73,17 @@ where
         .next()
         .await
         .context("Agent closed his websocket")?;
-    decode_agent_msg(msg)
+    match decode_agent_msg(msg)? {
+        Decoded::Known(msg) => Ok(msg),
+        Decoded::Unknown(kind) => Err(anyhow!("Agent sent an unknown message `{kind}`")),
+    }
 }
-fn decode_agent_msg(msg: Result<Message, warp::Error>) -> anyhow::Result<AgentMessage> {
+fn decode_agent_msg(msg: Result<Message, warp::Error>) -> anyhow::Result<Decoded<AgentMessage>> {
     let msg = msg.context("Failed to received agent message")?;
     let msg_str = msg
         .to_str()
         .map_err(|_empty: ()| anyhow!("Agent sent a message that was not a string"))?;
-    serde_json::from_str(msg_str).context("Agent sent an incorrect formatted message")
+    messages::decode(msg_str).context("Agent sent an incorrect formatted message")
 }
 
 impl StoreInner {
@@ -64,21 +97,137 @@ impl StoreInner {
             .iter_mut()
             .find(|machine| machine.infos.name == name)
     }
+    pub fn by_tag_mut<'store>(
+        &'store mut self,
+        tag: &'store str,
+    ) -> impl Iterator<Item = &'store mut Machine> {
+        self.machines
+            .iter_mut()
+            .filter(move |machine| machine.infos.config.tags.iter().any(|t| t == tag))
+    }
 
-    pub fn new(config: &config::Config) -> anyhow::Result<Self> {
+    /// `data_dir` holds the agent secrets and the launches of the applications
+    pub fn new(config: &config::Config, data_dir: &Path) -> anyhow::Result<Self> {
         let machines: anyhow::Result<Vec<Machine>> = config
             .machines
             .iter()
-            .map(|(name, config)| Machine::new(config, name))
+            .map(|(name, machine)| {
+                Machine::new(
+                    machine,
+                    name,
+                    &config.application_groups,
+                    &config.icon_provider,
+                )
+            })
             .collect();
         Ok(Self {
             machines: machines?,
+            pairings: Pairings::load(data_dir.join(pairing::SECRETS_FILENAME))?,
+            launches: Launches::load(data_dir.join(launches::LAUNCHES_FILENAME)),
+            heartbeat: config.agent_heartbeat,
         })
     }
 
-    pub async fn refresh_machine_state(&mut self) {
-        for machine in &mut self.machines {
-            machine.update_state().await;
+    pub async fn refresh_machine_state(&mut self, dry_run: bool) {
+        let running_dependents: Vec<_> = self
+            .machines
+            .iter()
+            .map(|machine| self.running_dependents(&machine.infos.name))
+            .collect();
+        for (machine, running_dependents) in self.machines.iter_mut().zip(running_dependents) {
+            machine.running_dependents = running_dependents;
+            machine.update_state(dry_run).await;
+        }
+        self.wake_dependencies(dry_run);
+    }
+
+    /// Machines that are on or waking up and depend (directly or not) on `name`
+    pub fn running_dependents(&self, name: &str) -> Vec<String> {
+        let mut dependents: Vec<&Machine> = vec![];
+        let mut to_visit = vec![name];
+        while let Some(current) = to_visit.pop() {
+            for machine in &self.machines {
+                if machine
+                    .infos
+                    .config
+                    .depends_on
+                    .iter()
+                    .any(|dep| dep == current)
+                    && !dependents
+                        .iter()
+                        .any(|dependent| dependent.infos.name == machine.infos.name)
+                {
+                    dependents.push(machine);
+                    to_visit.push(&machine.infos.name);
+                }
+            }
+        }
+        dependents
+            .into_iter()
+            .filter(|machine| matches!(machine.infos.state, State::On | State::PendingOn))
+            .map(|machine| machine.infos.name.clone())
+            .collect()
+    }
+
+    /// Wakes the dependencies of the machines waiting for them, then the machines
+    /// once all their dependencies are on
+    pub fn wake_dependencies(&mut self, dry_run: bool) {
+        let waiting: Vec<_> = self
+            .machines
+            .iter()
+            .filter(|machine| machine.waiting_for_dependencies)
+            .map(|machine| {
+                (
+                    machine.infos.name.clone(),
+                    machine.infos.config.depends_on.clone(),
+                )
+            })
+            .collect();
+        for (name, dependencies) in waiting {
+            let mut ready = true;
+            let mut failed = None;
+            for dependency in &dependencies {
+                let Some(dependency) = self.by_name_mut(dependency) else {
+                    continue;
+                };
+                match dependency.infos.state {
+                    State::On => (),
+                    State::PendingOn => ready = false,
+                    _ if dependency.woken_as_dependency => {
+                        dependency.woken_as_dependency = false;
+                        failed = Some(dependency.infos.name.clone());
+                        break;
+                    }
+                    _ => {
+                        ready = false;
+                        dependency.woken_as_dependency = true;
+                        if let Err(err) = dependency.wake(dry_run) {
+                            error!("{err}");
+                            failed = Some(dependency.infos.name.clone());
+                            break;
+                        }
+                    }
+                }
+            }
+            let machine = self.by_name_mut(&name).expect("machine to exist");
+            match failed {
+                Some(dependency) => {
+                    error!(
+                        "Could not wake `{name}` because its dependency `{dependency}` did not wake up"
+                    );
+                    machine.waiting_for_dependencies = false;
+                    machine.infos.state = State::Off;
+                }
+                None if ready => {
+                    machine.waiting_for_dependencies = false;
+                    if let Err(err) = machine.send_wol(dry_run) {
+                        error!("Could not wake `{name}`: {err}");
+                        machine.infos.state = State::Off;
+                    }
+                }
+                // dependencies are still booting
+                None => {}
+            }
         }
     }
 }
@@ -92,8 +241,17 @@ pub struct MachineInfos {
     pub tasks: Vec<Task>,
     pub vdi_opened: bool,
     pub vdi_cert_hash: Option<WebtransportCertificateHash>,
+    /// Number of ssh terminals opened from the panel
+    pub ssh_sessions: usize,
+    /// Unix timestamp (in seconds) at which the machine will be shut down
+    #[schema(example = 1_735_689_600)]
+    pub pending_shutdown_at: Option<u64>,
     pub config: config::MachineCfg,
     pub applications: Option<GroupedApplication>,
+    /// Latest metrics sent by the agent, `None` while it is disconnected
+    pub metrics: Option<Metrics>,
+    /// Applications opened from the panel that are still running, as reported by the agent
+    pub running_applications: Vec<RunningApplication>,
 }
 
 #[derive(Debug)]
@@ -101,21 +259,100 @@ pub struct Machine {
     pub infos: MachineInfos,
     pub addr: SocketAddr,
     applications_list: Vec<ApplicationInfo>,
-    connection: Option<SplitSink<WebSocket, Message>>,
+    /// Groups the `applications_list` is shown in
+    grouping: ApplicationGrouping,
+    /// Searches the icons the agent didn't send
+    icon_provider: config::IconProviderCfg,
+    connection: Option<Arc<tokio::sync::Mutex<SplitSink<WebSocket, Message>>>>,
     agent_messages: Option<Receiver<AgentMessage>>,
     listen_message_task: Option<tokio::task::JoinHandle<()>>,
+    /// Sent by the agent in its hello
+    agent_capabilities: Vec<Capability>,
+    /// Commands running on the agent
+    executions: Executions,
+    metrics: MetricsHistory,
+    idle: IdleTracker,
+    /// The machine was off and woken up to run the queued tasks
+    woken_for_tasks: bool,
+    /// Running machines that depend on this one
+    running_dependents: Vec<String>,
+    /// The wake on lan will be sent once all the dependencies are on
+    waiting_for_dependencies: bool,
+    /// The machine was woken up because another one depends on it
+    woken_as_dependency: bool,
+    wol_sent_at: Option<Instant>,
+    /// The machine is `PendingOff` since then
+    power_action_sent_at: Option<Instant>,
 }
 
 /// SAFETY: its fine :)
 unsafe impl Sync for Machine {}
 
 impl Machine {
-    pub async fn update_state(&mut self) {
-        self.check_agent_msg();
-        self.update_status().await;
+    pub async fn update_state(&mut self, dry_run: bool) {
+        self.check_agent_msg().await;
+        self.update_status(dry_run).await;
+    }
+
+    async fn update_status(&mut self, dry_run: bool) {
+        if self.agent_alive() {
+            // the agent answering heartbeats is proof enough that the machine is on
+            if self.infos.state != State::PendingOff {
+                self.infos.state = State::On;
+            }
+        } else {
+            self.probe_status().await;
+        }
+
+        match self.infos.state {
+            State::On => {
+                self.waiting_for_dependencies = false;
+                self.woken_as_dependency = false;
+                self.wol_sent_at = None;
+            }
+            State::PendingOn
+                if self.wol_sent_at.is_some_and(|sent_at| {
+                    sent_at.elapsed() >= TIME_BEFORE_ASSUMING_WOL_FAILED
+                }) =>
+            {
+                warn!("Machine `{}` did not wake up", self.infos.name);
+                self.infos.state = State::Off;
+                self.wol_sent_at = None;
+            }
+            // the agent is still there, neither it nor ssh managed to turn the machine off
+            State::PendingOff
+                if self.agent_alive()
+                    && self.power_action_sent_at.is_some_and(|sent_at| {
+                        sent_at.elapsed() >= TIME_BEFORE_ASSUMING_POWER_ACTION_FAILED
+                    }) =>
+            {
+                warn!("Machine `{}` did not go down", self.infos.name);
+                self.infos.state = State::On;
+                self.power_action_sent_at = None;
+            }
+            _ => (),
+        }
+
+        match self.infos.state {
+            State::On => {
+                self.flush_tasks(dry_run).await;
+                self.check_pending_shutdown(dry_run).await;
+                self.check_idle(dry_run).await;
+            }
+            State::Off | State::PendingOff => {
+                self.infos.pending_shutdown_at = None;
+                self.idle.reset();
+                if self.infos.state == State::Off {
+                    self.woken_for_tasks = false;
+                }
+            }
+            // a failed probe doesn't cancel the shutdown users were warned about
+            State::PendingOn | State::Unknown => (),
+        }
     }
 
-    async fn update_status(&mut self) {
+    async fn probe_status(&mut self) {
+        let start = Instant::now();
         let ping_res = ping_rs::send_ping_async(
             &self.addr.ip(),
             Duration::from_secs(1),
@@ -136,12 +373,183 @@ impl Machine {
                     .is_ok();
             self.infos.state = Self::next_state(res, ping_res, self.infos.state);
         }
+        MONITORING.record_probe(&self.infos.name, start.elapsed());
+    }
+
+    async fn check_pending_shutdown(&mut self, dry_run: bool) {
+        if self
+            .infos
+            .pending_shutdown_at
+            .is_some_and(|shutdown_at| unix_timestamp(SystemTime::now()) >= shutdown_at)
+        {
+            info!("Countdown of `{}` is over", self.infos.name);
+            self.shutdown(dry_run).await;
+        }
+    }
+
+    async fn check_idle(&mut self, dry_run: bool) {
+        let Some(policy) = self.infos.config.idle_shutdown.clone() else {
+            return;
+        };
+        let now = Instant::now();
+        if !self.idle.should_probe(IDLE_PROBE_INTERVAL, now) {
+            return;
+        }
+        let probe = match self.probe_idle().await {
+            Ok(probe) => probe,
+            Err(err) => {
+                warn!(
+                    "Could not probe if `{}` is idle: {:#}",
+                    self.infos.name, err
+                );
+                return;
+            }
+        };
+        let activity = Activity {
+            logged_in_users: probe.logged_in_users,
+            ssh_sessions: self.infos.ssh_sessions,
+            vdi_opened: self.infos.vdi_opened,
+            cpu_usage: self.idle.record_probe(&probe, now),
+            running_dependents: self.running_dependents.len(),
+        };
+        debug!("`{}` activity: {:?}", self.infos.name, activity);
+        match self.idle.update(&policy, &activity, now) {
+            IdleAction::Nothing => (),
+            IdleAction::Warn(grace_period) => {
+                info!("Machine `{}` is idle", self.infos.name);
+                self.schedule_shutdown(grace_period, true, dry_run).await;
+            }
+            IdleAction::Cancel => {
+                info!(
+                    "Machine `{}` is active again, cancelling idle shutdown",
+                    self.infos.name
+                );
+                self.infos.pending_shutdown_at = None;
+                self.warn_users(
+                    &ServerMessage::ShutdownCancelled,
+                    "The idle shutdown was cancelled",
+                    dry_run,
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn probe_idle(&self) -> anyhow::Result<Probe> {
+        let output = self.ssh().arg(idle::PROBE_COMMAND).output().await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Probe::parse(&stdout).with_context(|| format!("Unexpected probe output: {stdout}"))
+    }
 
-        if self.infos.state == State::On {
-            self.flush_tasks().await;
+    /// Notifies the desktop session through the agent, or with `wall` if there is no agent
+    async fn warn_users(&self, msg: &ServerMessage, text: &str, dry_run: bool) {
+        if dry_run {
+            debug!("Warning `{}`'s users: {text} (dry run)", self.infos.name);
+            return;
+        }
+        if self.send_message(msg).await.is_ok() {
+            return;
+        }
+        let res = self
+            .ssh()
+            .arg(format!("wall {}", exec::shell_join(&[text.to_owned()])))
+            .output()
+            .await;
+        if let Err(err) = res {
+            warn!("Could not warn `{}`'s users: {:#}", self.infos.name, err);
         }
     }
 
+    /// Shuts the machine down after `delay`, users can cancel it from their desktop if `cancellable`
+    pub async fn schedule_shutdown(
+        &mut self,
+        delay: Duration,
+        cancellable: bool,
+        dry_run: bool,
+    ) -> String {
+        let shutdown_at = SystemTime::now() + delay;
+        info!(
+            "Shutting down machine `{}` in {}s",
+            self.infos.name,
+            delay.as_secs()
+        );
+        self.infos.pending_shutdown_at = Some(unix_timestamp(shutdown_at));
+        self.warn_users(
+            &ServerMessage::ShutdownWarning {
+                in_secs: delay.as_secs(),
+                cancellable,
+            },
+            &format!(
+                "This machine will shut down in {} minutes",
+                delay.as_secs().div_ceil(60)
+            ),
+            dry_run,
+        )
+        .await;
+        format!("Machine will shut down in {}s", delay.as_secs())
+    }
+
+    pub async fn cancel_shutdown(&mut self, dry_run: bool) -> Result<String, String> {
+        if self.infos.pending_shutdown_at.take().is_none() {
+            return Err(format!(
+                "Machine {} has no pending shutdown",
+                self.infos.name
+            ));
+        }
+        if let Some(policy) = &self.infos.config.idle_shutdown {
+            // don't warn again right away if the shutdown came from the idle policy
+            self.idle.postpone(policy, Instant::now());
+        }
+        info!("Cancelled the shutdown of `{}`", self.infos.name);
+        self.warn_users(
+            &ServerMessage::ShutdownCancelled,
+            "The shutdown was cancelled",
+            dry_run,
+        )
+        .await;
+        Ok("Cancelled the shutdown successfully".to_owned())
+    }
+
+    pub async fn postpone_idle_shutdown(&mut self, dry_run: bool) -> Result<String, String> {
+        let policy = self.idle_policy()?;
+        self.idle.postpone(&policy, Instant::now());
+        if self.infos.pending_shutdown_at.take().is_some() {
+            self.warn_users(
+                &ServerMessage::ShutdownCancelled,
+                "The idle shutdown was postponed",
+                dry_run,
+            )
+            .await;
+        }
+        Ok(format!(
+            "Postponed idle shutdown by {} minutes",
+            policy.after_minutes
+        ))
+    }
+
+    pub async fn veto_idle_shutdown(&mut self, dry_run: bool) -> Result<String, String> {
+        self.idle_policy()?;
+        self.idle.veto();
+        if self.infos.pending_shutdown_at.take().is_some() {
+            self.warn_users(
+                &ServerMessage::ShutdownCancelled,
+                "The idle shutdown was cancelled",
+                dry_run,
+            )
+            .await;
+        }
+        Ok("Idle shutdown disabled until the machine is turned off".to_owned())
+    }
+
+    fn idle_policy(&self) -> Result<config::IdleShutdownCfg, String> {
+        self.infos.config.idle_shutdown.clone().ok_or_else(|| {
+            format!(
+                "Machine {} has no idle shutdown configured",
+                self.infos.name
+            )
+        })
+    }
+
     fn next_state(res: bool, ping_res: bool, state: State) -> State {
         match (res, ping_res, state) {
             (_, true, State::PendingOff) | (false, true, State::On) => State::PendingOff,
@@ -152,16 +560,7 @@ impl Machine {
         }
     }
     fn ssh(&self) -> Command {
-        debug!("sshing into oscar@{}", self.addr);
-        let mut cmd = Command::new("ssh");
-        cmd.arg("-i")
-            .arg("~/.ssh/id_ed25519")
-            .arg("-o")
-            .arg("StrictHostKeyChecking=no")
-            .arg("-p")
-            .arg(self.addr.port().to_string())
-            .arg(format!("oscar@{}", self.addr.ip()));
-        cmd
+        ssh(self.addr)
     }
 
     pub async fn open_vdi(&mut self) -> Result<(), OpenVdiError> {
@@ -175,36 +574,103 @@ impl Machine {
         Ok(())
     }
     pub async fn shutdown(&mut self, dry_run: bool) -> String {
+        self.power(PowerAction::Shutdown, dry_run).await
+    }
+
+    /// Asks the agent to do `action`, or runs it over ssh if there is no agent able to
+    pub async fn power(&mut self, action: PowerAction, dry_run: bool) -> String {
         self.infos.state = State::PendingOff;
-        let mut cmd = self.ssh();
-        cmd.arg("sudo")
-            // .arg("systemctl").arg("poweroff")
-            .arg("poweroff");
-        info!("Shutting down machine '{}'", self.infos.name);
-        debug!(
-            "Running command: {:?}{}",
-            &cmd,
-            if dry_run { " (dry run)" } else { "" }
-        );
-        if !dry_run {
-            let output = cmd.output().await;
-            if let Err(err) = output {
-                return format!("ssh command failed: {err}");
+        self.infos.pending_shutdown_at = None;
+        self.power_action_sent_at = Some(Instant::now());
+        self.power_with_agent(action, dry_run)
+            .await
+            .unwrap_or_else(|err| err)
+    }
+
+    async fn power_with_agent(&self, action: PowerAction, dry_run: bool) -> Result<String, String> {
+        if self.agent_supports(Capability::Power) {
+            info!(
+                "Asking the agent of '{}' to {action:?} the machine{}",
+                self.infos.name,
+                if dry_run { " (dry run)" } else { "" }
+            );
+            let res = if dry_run {
+                Ok(())
+            } else if self.agent_supports(Capability::PowerResults) {
+                let (id, events) = self.executions.start();
+                let res = self
+                    .send_message(&ServerMessage::Power { id, action })
+                    .await;
+                if res.is_ok() {
+                    // its outcome is recorded once the agent answered
+                    tokio::spawn(confirm_power_action(
+                        self.addr,
+                        self.infos.name.clone(),
+                        action,
+                        events,
+                    ));
+                    return Ok(format!("Sent {action:?} request to the agent successfully"));
+                }
+                self.executions.cancel(id);
+                res
+            } else {
+                self.send_message(&ServerMessage::PowerAction(action)).await
             };
-            debug!("Command output: {:?}", &output);
+            match res {
+                Ok(()) => {
+                    record_power_action(&self.infos.name, action, true);
+                    return Ok(format!("Sent {action:?} request to the agent successfully"));
+                }
+                Err(err) => warn!(
+                    "Could not ask the agent of '{}' to {action:?}, falling back to ssh: {err:?}",
+                    self.infos.name
+                ),
+            }
         }
-        "Send shutdown command to machine successfully".to_owned()
+        info!(
+            "Running {action:?} on machine '{}' over ssh",
+            self.infos.name
+        );
+        let res = if dry_run {
+            Ok(())
+        } else {
+            ssh_power_action(self.addr, action).await
+        };
+        record_power_action(&self.infos.name, action, res.is_ok());
+        res.map_err(|err| format!("ssh command failed: {err}"))?;
+        Ok(format!("Send {action:?} command to machine successfully"))
     }
 
     pub fn wake(&mut self, dry_run: bool) -> Result<String, String> {
+        // someone needs the machine, it is not only on for its tasks anymore
+        self.woken_for_tasks = false;
+        if self.infos.state != State::On && !self.infos.config.depends_on.is_empty() {
+            info!(
+                "Waking the dependencies of {} first: {}",
+                self.infos.name,
+                self.infos.config.depends_on.join(", ")
+            );
+            self.infos.state = State::PendingOn;
+            self.waiting_for_dependencies = true;
+            return Ok(format!(
+                "Waking dependencies first: {}",
+                self.infos.config.depends_on.join(", ")
+            ));
+        }
+        self.send_wol(dry_run)
+    }
+
+    fn send_wol(&mut self, dry_run: bool) -> Result<String, String> {
         info!(
             "Sending wake on lan to {} (mac = {})",
             self.infos.name,
             self.infos.config.mac.to_uppercase()
         );
         self.infos.state = State::PendingOn;
+        self.wol_sent_at = Some(Instant::now());
 
         let send = wol::send(&self.infos.config.mac, dry_run);
+        MONITORING.record_wake(&self.infos.name, send.is_ok());
         match send {
             Ok(()) => Ok("Sent wake on lan successfully".to_owned()),
             Err(e) => Err(e.to_string()),
@@ -225,29 +691,115 @@ impl Machine {
         self.infos.tasks.push(task);
         if self.infos.state == State::Off {
             let res = self.wake(dry_run)?;
+            // after the wake, which forgets why the machine was previously woken up
+            self.woken_for_tasks = true;
             debug!("Push task: wake on lan result: {res}");
         }
         Ok(format!("Pushed task '{name}' successfully"))
     }
 
-    async fn flush_tasks(&mut self) {
-        #[expect(
-            clippy::collection_is_never_read,
-            reason = "TODO: send them to front and do a popup"
-        )]
-        let mut errors = Vec::new(); // TODO: report them somehow
+    pub fn push_group_task(&mut self, task: &GroupTask, dry_run: bool) -> Result<String, String> {
+        let id = self
+            .infos
+            .config
+            .tasks
+            .iter()
+            .position(|cfg| cfg.name == task.name)
+            .ok_or_else(|| {
+                format!(
+                    "Machine {} has no task named '{}'",
+                    self.infos.name, task.name
+                )
+            })?;
+        self.push_task(
+            Task {
+                id,
+                shutdown_after: task.shutdown_after,
+            },
+            dry_run,
+        )
+    }
+
+    async fn flush_tasks(&mut self, dry_run: bool) {
+        if self.infos.tasks.is_empty() {
+            return;
+        }
+        let mut errors = Vec::new(); // TODO: send them to front and do a popup
+        let mut shutdown_after = false;
         while let Some(task) = self.infos.tasks.pop() {
+            shutdown_after |= task.shutdown_after;
+            let start = Instant::now();
             let res = task
                 .execute(self)
                 .await
                 .with_context(|| format!("Failed to execute task {task:?}"));
+            MONITORING.record_task(
+                &self.infos.name,
+                &self.infos.config.tasks[task.id].name,
+                start.elapsed(),
+                res.is_ok(),
+            );
             if let Err(err) = res {
+                error!("{:#}", err);
                 errors.push(err);
             }
         }
+        let woken_for_tasks = mem::take(&mut self.woken_for_tasks);
+        if !shutdown_after {
+            return;
+        }
+        if !errors.is_empty() {
+            info!(
+                "Not shutting down `{}` because {} task(s) failed",
+                self.infos.name,
+                errors.len()
+            );
+            return;
+        }
+        if !woken_for_tasks {
+            info!(
+                "Not shutting down `{}` because it was not woken up for its tasks",
+                self.infos.name
+            );
+            return;
+        }
+        match self.is_in_use().await {
+            Ok(false) => {
+                info!("Tasks of `{}` are done, shutting it down", self.infos.name);
+                self.shutdown(dry_run).await;
+            }
+            Ok(true) => info!(
+                "Not shutting down `{}` because someone is using it",
+                self.infos.name
+            ),
+            Err(err) => warn!(
+                "Not shutting down `{}`, could not check if someone is using it: {:#}",
+                self.infos.name, err
+            ),
+        }
     }
 
-    fn new(config: &config::MachineCfg, name: &str) -> anyhow::Result<Self> {
+    /// Queued tasks or running dependents need the machine to be on
+    pub fn is_needed(&self) -> bool {
+        !self.infos.tasks.is_empty() || !self.running_dependents.is_empty()
+    }
+
+    async fn is_in_use(&self) -> anyhow::Result<bool> {
+        if self.infos.ssh_sessions > 0
+            || self.infos.vdi_opened
+            || !self.running_dependents.is_empty()
+        {
+            return Ok(true);
+        }
+        Ok(self.probe_idle().await?.logged_in_users > 0)
+    }
+
+    fn new(
+        config: &config::MachineCfg,
+        name: &str,
+        application_groups: &[config::CategoryGroupCfg],
+        icon_provider: &config::IconProviderCfg,
+    ) -> anyhow::Result<Self> {
         Ok(Self {
             infos: MachineInfos {
                 config: config.to_owned(),
@@ -257,6 +809,10 @@ impl Machine {
                 applications: None,
                 vdi_opened: false,
                 vdi_cert_hash: None,
+                ssh_sessions: 0,
+                pending_shutdown_at: None,
+                metrics: None,
+                running_applications: vec![],
             },
             addr: config
                 .ip
@@ -265,53 +821,313 @@ impl Machine {
                 .next()
                 .context("Error while resolving '{name}' ip")?,
             applications_list: vec![],
+            grouping: ApplicationGrouping::new(application_groups, &config.applications),
+            icon_provider: icon_provider.clone(),
             connection: None,
             agent_messages: None,
             listen_message_task: None,
+            agent_capabilities: vec![],
+            executions: Executions::default(),
+            metrics: MetricsHistory::new(METRICS_HISTORY_LEN),
+            idle: IdleTracker::default(),
+            woken_for_tasks: false,
+            running_dependents: vec![],
+            waiting_for_dependencies: false,
+            woken_as_dependency: false,
+            wol_sent_at: None,
+            power_action_sent_at: None,
         })
     }
 
+    /// Replaces the applications, only the icons of the new or changed ones are processed.
+    /// The agent is asked again for every missing icon, it may have disconnected before sending
+    /// them
     pub async fn set_applications(&mut self, applications: Vec<ApplicationInfo>) {
-        self.infos.applications = Some(GroupedApplication::from_list(applications.clone()).await);
-        self.applications_list = applications;
+        let removed = self
+            .applications_list
+            .iter()
+            .filter(|app| !applications.contains(app))
+            .map(|app| app.name.clone())
+            .collect();
+        let added = applications
+            .iter()
+            .filter(|app| !self.applications_list.contains(app))
+            .cloned()
+            .collect();
+        self.replace_applications(added, removed).await;
+        self.request_missing_icons(&self.applications_list).await;
+    }
+
+    /// Adds or replaces `added` and removes the applications named `removed`
+    pub async fn update_applications(&mut self, added: Vec<ApplicationInfo>, removed: Vec<String>) {
+        self.request_missing_icons(&added).await;
+        self.replace_applications(added, removed).await;
+    }
+
+    async fn replace_applications(&mut self, added: Vec<ApplicationInfo>, removed: Vec<String>) {
+        self.applications_list.retain(|app| {
+            !removed.contains(&app.name) && !added.iter().any(|new| new.name == app.name)
+        });
+        self.applications_list.extend(added.iter().cloned());
+        let grouped = self
+            .infos
+            .applications
+            .get_or_insert_with(GroupedApplication::default);
+        grouped.remove(&removed);
+        grouped
+            .insert(added, &self.grouping, &self.icon_provider)
+            .await;
+    }
+
+    /// Asks the agent for the icons of `applications` that aren't cached yet
+    async fn request_missing_icons(&self, applications: &[ApplicationInfo]) {
+        if !self.agent_supports(Capability::Icons) {
+            return;
+        }
+        let missing: Vec<String> = applications
+            .iter()
+            .filter_map(|app| app.icon.as_ref())
+            .filter(|icon| icon.is_valid() && !cache::is_icon_cached(&icon.hash))
+            .map(|icon| icon.hash.clone())
+            .unique()
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+        debug!(
+            "Requesting {} icon(s) from `{}`'s agent",
+            missing.len(),
+            self.infos.name
+        );
+        if let Err(err) = self
+            .send_message(&ServerMessage::RequestIcons(missing))
+            .await
+        {
+            warn!(
+                "Could not request the icons of `{}`: {err:?}",
+                self.infos.name
+            );
+        }
     }
 
-    pub fn set_connection(&mut self, connection: WebSocket) {
+    pub fn set_connection(
+        &mut self,
+        connection: WebSocket,
+        capabilities: Vec<Capability>,
+        heartbeat: HeartbeatCfg,
+    ) {
+        if let Some(task) = self.listen_message_task.take() {
+            // the commands sent through the replaced connection won't be answered
+            task.abort();
+            self.executions.clear();
+        }
         let (ws_send, mut ws_recv) = connection.split();
+        let ws_send = Arc::new(tokio::sync::Mutex::new(ws_send));
+        self.agent_capabilities = capabilities;
         let (ch_send, ch_recv) = mpsc::channel();
-        self.connection = Some(ws_send);
+        self.connection = Some(ws_send.clone());
         self.agent_messages = Some(ch_recv);
         let name = self.infos.name.clone();
+        let executions = self.executions.clone();
         let task = async move {
-            while let Some(msg) = ws_recv.next().await {
+            let mut pings = time::interval(heartbeat.interval());
+            let mut last_seen = Instant::now();
+            loop {
+                let msg = tokio::select! {
+                    msg = ws_recv.next() => msg,
+                    _ = pings.tick() => {
+                        if heartbeat.is_expired(last_seen, Instant::now()) {
+                            warn!(
+                                "Agent of `{name}` missed {} heartbeats",
+                                heartbeat.missed_beats
+                            );
+                            break;
+                        }
+                        if let Err(err) = ws_send.lock().await.send(Message::ping([])).await {
+                            debug!("Could not ping `{name}`'s agent: {err:#}");
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                let Some(msg) = msg else {
+                    break;
+                };
+                last_seen = Instant::now();
+                if msg.as_ref().is_ok_and(|msg| msg.is_ping() || msg.is_pong()) {
+                    continue;
+                }
                 let msg = match decode_agent_msg(msg) {
-                    Ok(msg) => msg,
+                    Ok(Decoded::Known(msg)) => msg,
+                    Ok(Decoded::Unknown(kind)) => {
+                        warn!("Ignoring unknown message `{kind}` from `{name}`'s agent, it may be newer than the backend");
+                        continue;
+                    }
                     Err(err) => {
                         error!("Receiving `{}`'s agent message: {:#}", &name, err);
                         continue;
                     }
                 };
+                let Some(msg) = executions.dispatch(msg) else {
+                    continue;
+                };
                 debug!("Received message from `{}`'s agent: {:?}", &name, msg);
                 ch_send.send(msg).expect("Backend to be alive");
             }
+            // nothing will answer the running commands anymore
+            executions.clear();
             debug!("Agent of `{}` disconnected", &name);
         };
         self.listen_message_task = Some(tokio::spawn(task));
     }
 
-    pub async fn open_app(&self, application_name: &str, dry_run: bool) -> anyhow::Result<()> {
-        let app_command = self
+    /// The agent is connected and answered the last heartbeats
+    pub fn agent_alive(&self) -> bool {
+        self.listen_message_task
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
+    }
+
+    pub fn disconnect_agent(&mut self) {
+        if let Some(task) = self.listen_message_task.take() {
+            task.abort();
+        }
+        self.connection = None;
+        self.agent_messages = None;
+        self.agent_capabilities.clear();
+        self.executions.clear();
+        self.infos.vdi_opened = false; // agent was killed so we assume the vdi died too
+        self.infos.vdi_cert_hash = None;
+        self.infos.metrics = None;
+        self.infos.running_applications.clear();
+    }
+
+    /// The machine was off and woken up by a pushed task, it may be shut down after its tasks
+    pub const fn woken_for_tasks(&self) -> bool {
+        self.woken_for_tasks
+    }
+
+    /// Metrics received from the agent, oldest first
+    pub fn metrics(&self) -> Vec<Metrics> {
+        self.metrics.samples()
+    }
+
+    /// Opens an application, or one of its desktop actions, with the files or urls in `arguments`.
+    /// It is opened by the agent in its own session unless another graphical `session` is picked,
+    /// the returned launch must then be run, without holding the store as it goes through ssh
+    pub async fn open_app(
+        &self,
+        application_name: &str,
+        action: Option<&str>,
+        arguments: &[String],
+        session: Option<&str>,
+        dry_run: bool,
+    ) -> Result<Option<DesktopLaunch>, OpenApplicationError> {
+        let application = self
             .find_application(application_name)
-            .ok_or_else(|| anyhow::anyhow!("No application found with name {application_name}"))?
-            .exec
-            .clone();
+            .ok_or_else(|| OpenApplicationError::UnknownApplication(application_name.to_owned()))?;
+        if let Some(id) =
+            action.filter(|id| !application.actions.iter().any(|action| action.id == *id))
+        {
+            return Err(OpenApplicationError::UnknownAction {
+                application: application_name.to_owned(),
+                action: id.to_owned(),
+            });
+        }
+        let argv = application.argv(action, arguments)?;
+        if dry_run {
+            return Ok(None);
+        }
+        if session.is_none() && self.agent_supports(Capability::Exec) {
+            // the agent runs in the desktop session, no need to guess its display
+            self.agent_exec(argv.clone(), Some(application))
+                .await
+                .with_context(|| format!("Could not open app with command {argv:?}"))?;
+            return Ok(None);
+        }
+        Ok(Some(DesktopLaunch::new(
+            self.addr,
+            application,
+            &argv,
+            session,
+        )))
+    }
+
+    fn agent_supports(&self, capability: Capability) -> bool {
+        self.agent_alive() && self.agent_capabilities.contains(&capability)
+    }
+
+    /// Runs `argv` through the agent and waits for it to exit.
+    /// Applications are only waited for to start, they are then tracked by the agent
+    async fn agent_exec(
+        &self,
+        argv: Vec<String>,
+        application: Option<&ApplicationInfo>,
+    ) -> anyhow::Result<ExecResult> {
+        let (id, events) = self.executions.start();
+        let msg = ServerMessage::Exec {
+            id,
+            argv,
+            env: BTreeMap::new(),
+            cwd: application.and_then(|app| app.path.clone()),
+            detach: application.is_some(),
+            name: application.map(|app| app.name.clone()),
+            terminal: application.is_some_and(|app| app.terminal),
+        };
+        if let Err(err) = self.send_message(&msg).await {
+            self.executions.cancel(id);
+            return Err(anyhow!("Could not send the command to the agent: {err:?}"));
+        }
+        if let Ok(res) = time::timeout(AGENT_EXEC_TIMEOUT, ExecResult::wait(events)).await {
+            return res;
+        }
+        self.executions.cancel(id);
+        Err(anyhow!(
+            "The agent did not report the command exit after {AGENT_EXEC_TIMEOUT:?}"
+        ))
+    }
+
+    /// Asks the agent to close an application opened from the panel
+    pub async fn close_application(
+        &self,
+        pid: u32,
+        dry_run: bool,
+    ) -> Result<(), CloseApplicationError> {
+        let application = self
+            .infos
+            .running_applications
+            .iter()
+            .find(|app| app.pid == pid)
+            .ok_or(CloseApplicationError::NotRunning)?;
+        info!(
+            "Closing `{}` (pid {pid}) on `{}`",
+            application.name, self.infos.name
+        );
         if dry_run {
             return Ok(());
         }
-        self.exec_desktop_cmd(&app_command)
+        self.send_message(&ServerMessage::CloseApplication { pid })
             .await
-            .with_context(|| format!("Could not open app with command {app_command}"))?;
-        Ok(())
+            .map_err(CloseApplicationError::AgentComunicationError)
+    }
+
+    /// The applications matching `query` the best first, the ones opened often and recently
+    /// are ranked higher
+    pub fn search_applications(
+        &self,
+        query: &str,
+        launches: &Launches,
+        now: u64,
+    ) -> Vec<ApplicationDisplay> {
+        let Some(grouped) = &self.infos.applications else {
+            return vec![];
+        };
+        search::search(&self.applications_list, query, |application| {
+            launches.frecency(&self.infos.name, &application.name, now)
+        })
+        .into_iter()
+        .filter_map(|application| grouped.display(&application.name).cloned())
+        .collect()
     }
 
     fn find_application(&self, application_name: &str) -> Option<&ApplicationInfo> {
@@ -320,20 +1136,20 @@ impl Machine {
             .find(|app| app.name == application_name)
     }
 
-    async fn exec_desktop_cmd(&self, app_command: &str) -> Result<process::Output, io::Error> {
-        // TODO: unhardcode display
-        self.ssh()
-            .arg(format!("DISPLAY=:0 {app_command} >/dev/null 2>&1 & disown"))
-            .output()
-            .await
-    }
-
-    async fn send_message(&mut self, msg: &ServerMessage) -> Result<(), AgentComunicationError> {
-        let Some(connection) = &mut self.connection else {
+    async fn send_message(&self, msg: &ServerMessage) -> Result<(), AgentComunicationError> {
+        let Some(connection) = &self.connection else {
             return Err(AgentComunicationError::NotConnected);
         };
+        if msg
+            .capability()
+            .is_some_and(|capability| !self.agent_capabilities.contains(&capability))
+        {
+            return Err(AgentComunicationError::Unsupported);
+        }
         let message = Message::text(serde_json::to_string(msg).unwrap());
         connection
+            .lock()
+            .await
             .send(message)
             .await
             .with_context(|| format!("Could not send message {msg:?} to {}", self.infos.name))
@@ -342,34 +1158,85 @@ impl Machine {
         Ok(())
     }
 
-    fn check_agent_msg(&mut self) {
+    async fn check_agent_msg(&mut self) {
+        while let Some(msg) = self
+            .agent_messages
+            .as_ref()
+            .and_then(|recv| recv.try_recv().ok())
+        {
+            self.handle_agent_msg(msg).await;
+        }
         if self
             .listen_message_task
             .as_ref()
             .is_some_and(tokio::task::JoinHandle::is_finished)
         {
             debug!("Stopped listening for {}'s agent messages", self.infos.name);
-            self.listen_message_task = None;
-            self.infos.vdi_opened = false; // agent was killed so we assume the vdi died too
-            self.infos.vdi_cert_hash = None;
-        }
-        if let Some(recv) = &self.agent_messages {
-            if let Ok(msg) = recv.try_recv() {
-                self.handle_agent_msg(msg);
-            }
+            self.disconnect_agent();
         }
     }
 
-    fn handle_agent_msg(&mut self, msg: AgentMessage) {
+    #[expect(
+        clippy::rest_pattern_accessible_field,
+        reason = "exec replies never get here"
+    )]
+    async fn handle_agent_msg(&mut self, msg: AgentMessage) {
         #[expect(clippy::enum_glob_use, reason = "Cool")]
         use AgentMessage::*;
         match msg {
             Hello(_) => unreachable!("it's handled in main atm"),
+            exec_reply @ (ExecOutput { .. } | ExecExit { .. }) => {
+                unreachable!(
+                    "{:?} is dispatched by the listen task",
+                    exec_reply.exec_id()
+                )
+            }
             VdiCertificateHash(hash) => self.infos.vdi_cert_hash = Some(hash),
             VdiClosed => {
                 self.infos.vdi_opened = false;
                 self.infos.vdi_cert_hash = None;
             }
+            PostponeShutdown => {
+                if let Some(policy) = &self.infos.config.idle_shutdown {
+                    info!(
+                        "A user of `{}` postponed its idle shutdown",
+                        self.infos.name
+                    );
+                    self.idle.postpone(policy, Instant::now());
+                    self.infos.pending_shutdown_at = None;
+                }
+            }
+            VetoShutdown => {
+                info!("A user of `{}` vetoed its idle shutdown", self.infos.name);
+                self.idle.veto();
+                self.infos.pending_shutdown_at = None;
+            }
+            ApplicationsAdded(applications) => {
+                debug!(
+                    "{} application(s) added on `{}`",
+                    applications.len(),
+                    self.infos.name
+                );
+                self.update_applications(applications, vec![]).await;
+            }
+            ApplicationsRemoved(names) => {
+                debug!("Applications removed from `{}`: {names:?}", self.infos.name);
+                self.update_applications(vec![], names).await;
+            }
+            Icons(icons) => {
+                for icon in icons {
+                    if let Err(err) = cache::cache_icon(&icon) {
+                        warn!("Invalid icon from `{}`'s agent: {err:#}", self.infos.name);
+                    }
+                }
+            }
+            RunningApplications(applications) => {
+                self.infos.running_applications = applications;
+            }
+            Metrics(metrics) => {
+                self.infos.metrics = Some(metrics.clone());
+                self.metrics.push(metrics);
+            }
         }
     }
 }
@@ -403,14 +1270,36 @@ pub enum State {
 #[serde(rename_all = "snake_case")]
 pub struct Task {
     id: usize,
+    /// Shut the machine down once the queue drained successfully, if it was woken up for it
+    #[serde(default)]
+    shutdown_after: bool,
+}
+/// Task pushed to every machine of a group, referenced by name since ids differ between machines
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct GroupTask {
+    #[schema(example = "Backup")]
+    name: String,
+    #[serde(default)]
+    shutdown_after: bool,
 }
+
+/// Runs `command` with a shell, like ssh does with its arguments
+fn shell_argv(command: &str) -> Vec<String> {
+    vec!["/bin/sh".to_owned(), "-c".to_owned(), command.to_owned()]
+}
+
 impl Task {
     async fn execute(&self, on: &Machine) -> anyhow::Result<()> {
-        let res = on
-            .ssh()
-            .args(&on.infos.config.tasks[self.id].command)
-            .output()
-            .await?;
+        let command = &on.infos.config.tasks[self.id].command;
+        if on.agent_supports(Capability::Exec) {
+            let res = on.agent_exec(shell_argv(&command.join(" ")), None).await?;
+            if !res.success() {
+                anyhow::bail!("{res}");
+            }
+            return Ok(());
+        }
+        let res = on.ssh().args(command).output().await?;
         if !res.status.success() {
             anyhow::bail!(
                 "stderr: {}\nstdout: {}\nreturn code: {}",
@@ -423,6 +1312,178 @@ impl Task {
     }
 }
 
+/// Runs `action` with systemctl over ssh
+async fn ssh_power_action(addr: SocketAddr, action: PowerAction) -> anyhow::Result<()> {
+    let verb = match action {
+        PowerAction::Shutdown => "poweroff",
+        PowerAction::Reboot => "reboot",
+        PowerAction::Suspend => "suspend",
+    };
+    let mut cmd = ssh(addr);
+    cmd.args(["sudo", "systemctl", verb]);
+    debug!("Running command: {:?}", &cmd);
+    // the connection may be closed by the machine going down, the status means nothing
+    let output = cmd.output().await?;
+    debug!("Command output: {:?}", &output);
+    Ok(())
+}
+
+/// Waits for the agent to confirm that it did `action`, runs it over ssh otherwise
+#[expect(
+    clippy::rest_pattern_accessible_field,
+    reason = "the id was checked by `Executions::dispatch`"
+)]
+async fn confirm_power_action(
+    addr: SocketAddr,
+    name: String,
+    action: PowerAction,
+    mut events: UnboundedReceiver<AgentMessage>,
+) {
+    let answer = time::timeout(POWER_ACTION_TIMEOUT, async {
+        loop {
+            match events.recv().await {
+                // the agent goes down with the machine, it may not have answered before
+                None | Some(AgentMessage::ExecExit { error: None, .. }) => return Ok(()),
+                Some(AgentMessage::ExecExit {
+                    error: Some(error), ..
+                }) => return Err(error),
+                Some(_) => (),
+            }
+        }
+    })
+    .await;
+    let error = match answer {
+        Ok(Ok(())) => {
+            record_power_action(&name, action, true);
+            return;
+        }
+        Ok(Err(error)) => error,
+        Err(_elapsed) => "it did not answer".to_owned(),
+    };
+    warn!("The agent of '{name}' could not {action:?} the machine ({error}), trying over ssh");
+    let res = ssh_power_action(addr, action).await;
+    if let Err(err) = &res {
+        error!("Could not {action:?} '{name}' over ssh: {err:#}");
+    }
+    record_power_action(&name, action, res.is_ok());
+}
+
+/// Only the shutdowns are monitored, reboots and suspends don't leave the machine off
+fn record_power_action(name: &str, action: PowerAction, success: bool) {
+    if action == PowerAction::Shutdown {
+        MONITORING.record_shutdown(name, success);
+    }
+}
+
+fn ssh(addr: SocketAddr) -> Command {
+    debug!("sshing into oscar@{addr}");
+    let mut cmd = Command::new("ssh");
+    cmd.arg("-i")
+        .arg("~/.ssh/id_ed25519")
+        .arg("-o")
+        .arg("StrictHostKeyChecking=no")
+        .arg("-p")
+        .arg(addr.port().to_string())
+        .arg(format!("oscar@{}", addr.ip()));
+    cmd
+}
+
+/// The graphical sessions of the ssh user on the machine at `addr`, according to logind
+pub async fn graphical_sessions(addr: SocketAddr) -> anyhow::Result<Vec<GraphicalSession>> {
+    let output = ssh(addr).arg(session::SESSIONS_COMMAND).output().await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Could not list the sessions: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(GraphicalSession::parse_all(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+#[derive(Debug, Error)]
+pub enum OpenApplicationError {
+    #[error("No application found with name {0}")]
+    UnknownApplication(String),
+    #[error("{application} has no action {action}")]
+    UnknownAction { application: String, action: String },
+    #[error(transparent)]
+    Failed(#[from] anyhow::Error),
+}
+
+/// An application to open over ssh in a graphical session, for the machines without an agent
+pub struct DesktopLaunch {
+    addr: SocketAddr,
+    program: String,
+    /// Shell command opening the application in the background
+    command: String,
+    session: Option<String>,
+}
+
+impl DesktopLaunch {
+    fn new(
+        addr: SocketAddr,
+        application: &ApplicationInfo,
+        argv: &[String],
+        session: Option<&str>,
+    ) -> Self {
+        let (program, mut command) = if application.terminal {
+            (
+                "x-terminal-emulator".to_owned(),
+                format!("x-terminal-emulator -e {}", exec::shell_join(argv)),
+            )
+        } else {
+            (
+                argv.first().cloned().unwrap_or_default(),
+                exec::shell_join(argv),
+            )
+        };
+        if let Some(path) = &application.path {
+            command = format!(
+                "cd {} && {command}",
+                exec::shell_join(&[path.display().to_string()])
+            );
+        }
+        Self {
+            addr,
+            program,
+            command,
+            session: session.map(ToOwned::to_owned),
+        }
+    }
+
+    /// Opens the application in the requested session, or the only active one.
+    /// Its output is discarded, so only a missing program can be reported
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let sessions = graphical_sessions(self.addr).await?;
+        let session = GraphicalSession::choose(&sessions, self.session.as_deref())?;
+        let env = session
+            .env()
+            .into_iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect_vec();
+        let program = exec::shell_join(slice::from_ref(&self.program));
+        let output = ssh(self.addr)
+            .arg(format!(
+                "command -v {program} >/dev/null || {{ echo {program} not found >&2; exit 127; }}; export {}; {} >/dev/null 2>&1 & disown",
+                exec::shell_join(&env),
+                self.command
+            ))
+            .output()
+            .await
+            .with_context(|| format!("Could not open app with command {}", self.command))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Could not open app with command {}: {}",
+                self.command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
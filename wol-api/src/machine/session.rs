@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+/// Shell command run over ssh to list the sessions known to logind.
+///
+/// Prints the uid of the ssh user, then the properties of each session followed by the wayland
+/// socket of its user and the X display of the user services (`XWayland` in wayland sessions),
+/// separated by empty lines
+pub const SESSIONS_COMMAND: &str = r#"echo "SshUser=$(id -u)"
+echo
+for session in $(loginctl list-sessions --no-legend | awk '{print $1}'); do
+loginctl show-session "$session" -p Id -p Name -p User -p Seat -p Type -p Class -p Display -p Active
+uid=$(loginctl show-session "$session" -p User --value)
+echo "WaylandDisplay=$(ls /run/user/"$uid" 2>/dev/null | grep -E '^wayland-[0-9]+$' | head -n1)"
+if [ "$uid" = "$(id -u)" ]; then
+XDG_RUNTIME_DIR="/run/user/$uid" systemctl --user show-environment 2>/dev/null | sed -n 's/^DISPLAY=/UserDisplay=/p'
+fi
+echo
+done"#;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionKind {
+    X11,
+    Wayland,
+}
+
+/// Desktop session of a user, graphical applications are opened in one of them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+pub struct GraphicalSession {
+    /// Logind session id
+    #[schema(example = "2")]
+    pub id: String,
+    #[schema(example = "oscar")]
+    pub user: String,
+    #[schema(example = 1000)]
+    pub uid: u32,
+    #[schema(example = "seat0")]
+    pub seat: Option<String>,
+    pub kind: SessionKind,
+    /// X11 display, the one of `XWayland` in wayland sessions if it runs
+    #[schema(example = ":0")]
+    pub display: Option<String>,
+    #[schema(example = "wayland-0")]
+    pub wayland_display: Option<String>,
+    /// In the foreground of its seat
+    pub active: bool,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SessionError {
+    #[error("Nobody is logged in a graphical session")]
+    NoSession,
+    #[error("No graphical session with id {0}")]
+    UnknownSession(String),
+    #[error("Several graphical sessions are active, pick one of {}", .0.join(", "))]
+    Ambiguous(Vec<String>),
+}
+
+impl GraphicalSession {
+    /// The graphical sessions of the ssh user in the output of [`SESSIONS_COMMAND`], it can't
+    /// open applications in the sessions of the other users. The greeters and ttys are skipped
+    pub fn parse_all(output: &str) -> Vec<Self> {
+        let mut blocks = output.split("\n\n").map(|block| {
+            block
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .collect::<HashMap<&str, &str>>()
+        });
+        let Some(ssh_uid) = blocks
+            .next()
+            .and_then(|properties| properties.get("SshUser")?.parse::<u32>().ok())
+        else {
+            return vec![];
+        };
+        blocks
+            .filter_map(|properties| Self::parse(&properties))
+            .filter(|session| session.uid == ssh_uid)
+            .collect()
+    }
+
+    fn parse(properties: &HashMap<&str, &str>) -> Option<Self> {
+        let non_empty = |key| {
+            properties
+                .get(key)
+                .filter(|value| !value.is_empty())
+                .map(|value| (*value).to_owned())
+        };
+        let kind = match *properties.get("Type")? {
+            "x11" => SessionKind::X11,
+            "wayland" => SessionKind::Wayland,
+            _ => return None,
+        };
+        if properties.get("Class") != Some(&"user") {
+            return None;
+        }
+        Some(Self {
+            id: non_empty("Id")?,
+            user: non_empty("Name")?,
+            uid: properties.get("User")?.parse().ok()?,
+            seat: non_empty("Seat"),
+            kind,
+            // the display of the session for x11, the one of XWayland for wayland
+            display: non_empty("Display").or_else(|| non_empty("UserDisplay")),
+            wayland_display: (kind == SessionKind::Wayland)
+                .then(|| non_empty("WaylandDisplay"))
+                .flatten(),
+            active: properties.get("Active") == Some(&"yes"),
+        })
+    }
+
+    /// The `requested` session, or the only one, or the only active one
+    pub fn choose<'sessions>(
+        sessions: &'sessions [Self],
+        requested: Option<&str>,
+    ) -> Result<&'sessions Self, SessionError> {
+        if let Some(id) = requested {
+            return sessions
+                .iter()
+                .find(|session| session.id == id)
+                .ok_or_else(|| SessionError::UnknownSession(id.to_owned()));
+        }
+        if let [session] = sessions {
+            return Ok(session);
+        }
+        let active: Vec<&Self> = sessions.iter().filter(|session| session.active).collect();
+        match active.as_slice() {
+            [session] => Ok(session),
+            [] if sessions.is_empty() => Err(SessionError::NoSession),
+            [] => Err(SessionError::Ambiguous(
+                sessions.iter().map(|session| session.id.clone()).collect(),
+            )),
+            _ => Err(SessionError::Ambiguous(
+                active.iter().map(|session| session.id.clone()).collect(),
+            )),
+        }
+    }
+
+    /// Variables a graphical application needs to run in this session
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let runtime_dir = format!("/run/user/{}", self.uid);
+        self.display
+            .iter()
+            .map(|display| ("DISPLAY", display.clone()))
+            .chain(
+                self.wayland_display
+                    .iter()
+                    .map(|display| ("WAYLAND_DISPLAY", display.clone())),
+            )
+            .chain([
+                (
+                    "DBUS_SESSION_BUS_ADDRESS",
+                    format!("unix:path={runtime_dir}/bus"),
+                ),
+                ("XDG_RUNTIME_DIR", runtime_dir),
+            ])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const OUTPUT: &str = "SshUser=1000
+
+Id=2
+Name=oscar
+User=1000
+Seat=seat0
+Type=wayland
+Class=user
+Display=
+Active=yes
+WaylandDisplay=wayland-0
+UserDisplay=:0
+
+Id=c1
+Name=gdm
+User=120
+Seat=seat0
+Type=wayland
+Class=greeter
+Display=
+Active=no
+WaylandDisplay=
+
+Id=5
+Name=oscar
+User=1000
+Seat=
+Type=tty
+Class=user
+Display=
+Active=yes
+WaylandDisplay=wayland-0
+
+Id=7
+Name=guest
+User=1001
+Seat=seat1
+Type=x11
+Class=user
+Display=:2
+Active=yes
+WaylandDisplay=
+
+Id=8
+Name=oscar
+User=1000
+Seat=seat1
+Type=x11
+Class=user
+Display=:1
+Active=no
+WaylandDisplay=wayland-0
+UserDisplay=:0
+";
+
+    fn session(id: &str, active: bool) -> GraphicalSession {
+        GraphicalSession {
+            id: id.to_owned(),
+            user: "oscar".to_owned(),
+            uid: 1000,
+            seat: Some("seat0".to_owned()),
+            kind: SessionKind::X11,
+            display: Some(":0".to_owned()),
+            wayland_display: None,
+            active,
+        }
+    }
+
+    #[test]
+    fn test_parse_all() {
+        assert_eq!(
+            GraphicalSession::parse_all(OUTPUT),
+            [
+                GraphicalSession {
+                    id: "2".to_owned(),
+                    user: "oscar".to_owned(),
+                    uid: 1000,
+                    seat: Some("seat0".to_owned()),
+                    kind: SessionKind::Wayland,
+                    display: Some(":0".to_owned()),
+                    wayland_display: Some("wayland-0".to_owned()),
+                    active: true,
+                },
+                GraphicalSession {
+                    id: "8".to_owned(),
+                    user: "oscar".to_owned(),
+                    uid: 1000,
+                    seat: Some("seat1".to_owned()),
+                    kind: SessionKind::X11,
+                    display: Some(":1".to_owned()),
+                    wayland_display: None,
+                    active: false,
+                },
+            ]
+        );
+        assert_eq!(GraphicalSession::parse_all(""), []);
+        assert_eq!(
+            GraphicalSession::parse_all(OUTPUT.strip_prefix("SshUser=1000\n\n").unwrap()),
+            [],
+            "the sessions can't be filtered without the ssh user"
+        );
+    }
+
+    #[rstest]
+    #[case(&[session("2", false)], None, Ok("2"))]
+    #[case(&[session("2", false), session("3", true)], None, Ok("3"))]
+    #[case(&[session("2", true), session("3", true)], Some("2"), Ok("2"))]
+    #[case(&[session("2", true), session("3", true)], None, Err(SessionError::Ambiguous(vec!["2".to_owned(), "3".to_owned()])))]
+    #[case(&[session("2", false), session("3", false)], None, Err(SessionError::Ambiguous(vec!["2".to_owned(), "3".to_owned()])))]
+    #[case(&[session("2", true)], Some("4"), Err(SessionError::UnknownSession("4".to_owned())))]
+    #[case(&[], None, Err(SessionError::NoSession))]
+    fn test_choose(
+        #[case] sessions: &[GraphicalSession],
+        #[case] requested: Option<&str>,
+        #[case] expected: Result<&str, SessionError>,
+    ) {
+        assert_eq!(
+            GraphicalSession::choose(sessions, requested).map(|session| session.id.as_str()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        let sessions = GraphicalSession::parse_all(OUTPUT);
+        assert_eq!(
+            sessions[0].env(),
+            [
+                ("DISPLAY", ":0".to_owned()),
+                ("WAYLAND_DISPLAY", "wayland-0".to_owned()),
+                (
+                    "DBUS_SESSION_BUS_ADDRESS",
+                    "unix:path=/run/user/1000/bus".to_owned()
+                ),
+                ("XDG_RUNTIME_DIR", "/run/user/1000".to_owned()),
+            ]
+        );
+        assert_eq!(sessions[1].env()[0], ("DISPLAY", ":1".to_owned()));
+    }
+}
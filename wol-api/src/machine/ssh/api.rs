@@ -1,4 +1,4 @@
-use std::{io::Cursor, path::PathBuf, sync::Arc};
+use std::{io::Cursor, mem, path::PathBuf, sync::Arc};
 
 use async_trait::async_trait;
 use futures_util::{SinkExt as _, StreamExt as _};
@@ -161,6 +161,9 @@ async fn connect(
         .unwrap();
     channel.exec(true, "$0").await.unwrap();
 
+    // opened terminals keep the machine from being considered idle
+    let _session = OpenedSession::new(store, machine_name).await;
+
     loop {
         tokio::select! {
             client_data = rx.next() => {
@@ -212,6 +215,37 @@ async fn connect(
     }
 }
 
+/// Counts a terminal in the `ssh_sessions` of its machine until it is dropped, even if the
+/// session ends with a panic
+struct OpenedSession {
+    store: Store,
+    machine_name: String,
+}
+
+impl OpenedSession {
+    async fn new(store: Store, machine_name: &str) -> Self {
+        if let Some(machine) = store.lock().await.by_name_mut(machine_name) {
+            machine.infos.ssh_sessions += 1;
+        }
+        Self {
+            store,
+            machine_name: machine_name.to_owned(),
+        }
+    }
+}
+
+impl Drop for OpenedSession {
+    fn drop(&mut self) {
+        let store = Arc::clone(&self.store);
+        let machine_name = mem::take(&mut self.machine_name);
+        tokio::spawn(async move {
+            if let Some(machine) = store.lock().await.by_name_mut(&machine_name) {
+                machine.infos.ssh_sessions = machine.infos.ssh_sessions.saturating_sub(1);
+            }
+        });
+    }
+}
+
 struct Client;
 
 #[async_trait]
@@ -0,0 +1,322 @@
+use core::convert::Infallible;
+use std::{sync::LazyLock, time::Duration};
+
+use log::error;
+use prometheus::{
+    core::Collector, Encoder as _, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use warp::{
+    http::{Method, StatusCode},
+    reject::Rejection,
+    reply::{self, Reply},
+    Filter,
+};
+
+use crate::machine::service::{Machine, State, Store};
+
+const STATES: [State; 5] = [
+    State::Unknown,
+    State::On,
+    State::Off,
+    State::PendingOn,
+    State::PendingOff,
+];
+
+/// Buckets of the task durations, in seconds
+const TASK_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0, 14_400.0];
+/// Buckets of the probe durations, in seconds
+const PROBE_BUCKETS: &[f64] = &[0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Global so it keeps counting across config reloads
+pub static MONITORING: LazyLock<Monitoring> =
+    LazyLock::new(|| Monitoring::new().expect("the prometheus metrics to be valid"));
+
+/// Prometheus metrics of the backend, exposed on `/metrics`
+pub struct Monitoring {
+    registry: Registry,
+    /// 1 for the current state of each machine, 0 for the others
+    machine_state: IntGaugeVec,
+    /// 1 if the machine is off while queued tasks or running dependents need it
+    /// eg: alert on `wol_machine_off_while_needed == 1`
+    off_while_needed: IntGaugeVec,
+    wakes: IntCounterVec,
+    shutdowns: IntCounterVec,
+    task_duration: HistogramVec,
+    task_failures: IntCounterVec,
+    probe_duration: HistogramVec,
+    connected_agents: IntGauge,
+    ssh_sessions: IntGaugeVec,
+    vdi_sessions: IntGaugeVec,
+    http_requests: IntCounterVec,
+}
+
+/// Label of the outcome of an action
+const fn outcome(success: bool) -> &'static str {
+    if success {
+        "success"
+    } else {
+        "failure"
+    }
+}
+
+impl Monitoring {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("wol".to_owned()), None)?;
+        let monitoring = Self {
+            machine_state: IntGaugeVec::new(
+                Opts::new("machine_state", "Current state of the machine"),
+                &["machine", "state"],
+            )?,
+            off_while_needed: IntGaugeVec::new(
+                Opts::new(
+                    "machine_off_while_needed",
+                    "1 if the machine is off while queued tasks or running dependents need it",
+                ),
+                &["machine"],
+            )?,
+            wakes: IntCounterVec::new(
+                Opts::new("wakes_total", "Wake requests by outcome"),
+                &["machine", "outcome"],
+            )?,
+            shutdowns: IntCounterVec::new(
+                Opts::new("shutdowns_total", "Shutdown requests by outcome"),
+                &["machine", "outcome"],
+            )?,
+            task_duration: HistogramVec::new(
+                HistogramOpts::new("task_duration_seconds", "Duration of the tasks")
+                    .buckets(TASK_BUCKETS.to_vec()),
+                &["machine", "task"],
+            )?,
+            task_failures: IntCounterVec::new(
+                Opts::new("task_failures_total", "Tasks that failed"),
+                &["machine", "task"],
+            )?,
+            probe_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "probe_duration_seconds",
+                    "Time taken to probe the state of a machine without agent",
+                )
+                .buckets(PROBE_BUCKETS.to_vec()),
+                &["machine"],
+            )?,
+            connected_agents: IntGauge::new("connected_agents", "Agents currently connected")?,
+            ssh_sessions: IntGaugeVec::new(
+                Opts::new("ssh_sessions", "Ssh terminals opened from the panel"),
+                &["machine"],
+            )?,
+            vdi_sessions: IntGaugeVec::new(
+                Opts::new("vdi_sessions", "1 if a vdi is opened on the machine"),
+                &["machine"],
+            )?,
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "Http requests by method and status"),
+                &["method", "status"],
+            )?,
+            registry,
+        };
+        let collectors: [Box<dyn Collector>; 11] = [
+            Box::new(monitoring.machine_state.clone()),
+            Box::new(monitoring.off_while_needed.clone()),
+            Box::new(monitoring.wakes.clone()),
+            Box::new(monitoring.shutdowns.clone()),
+            Box::new(monitoring.task_duration.clone()),
+            Box::new(monitoring.task_failures.clone()),
+            Box::new(monitoring.probe_duration.clone()),
+            Box::new(monitoring.connected_agents.clone()),
+            Box::new(monitoring.ssh_sessions.clone()),
+            Box::new(monitoring.vdi_sessions.clone()),
+            Box::new(monitoring.http_requests.clone()),
+        ];
+        for collector in collectors {
+            monitoring.registry.register(collector)?;
+        }
+        Ok(monitoring)
+    }
+
+    pub fn record_wake(&self, machine: &str, success: bool) {
+        self.wakes
+            .with_label_values(&[machine, outcome(success)])
+            .inc();
+    }
+
+    pub fn record_shutdown(&self, machine: &str, success: bool) {
+        self.shutdowns
+            .with_label_values(&[machine, outcome(success)])
+            .inc();
+    }
+
+    pub fn record_task(&self, machine: &str, task: &str, duration: Duration, success: bool) {
+        self.task_duration
+            .with_label_values(&[machine, task])
+            .observe(duration.as_secs_f64());
+        if !success {
+            self.task_failures.with_label_values(&[machine, task]).inc();
+        }
+    }
+
+    pub fn record_probe(&self, machine: &str, duration: Duration) {
+        self.probe_duration
+            .with_label_values(&[machine])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn record_http_request(&self, method: &Method, status: StatusCode) {
+        self.http_requests
+            .with_label_values(&[method.as_str(), status.as_str()])
+            .inc();
+    }
+
+    /// Updates the gauges from the current machines, removed machines are forgotten
+    pub fn observe_machines(&self, machines: &[Machine]) {
+        self.machine_state.reset();
+        self.off_while_needed.reset();
+        self.ssh_sessions.reset();
+        self.vdi_sessions.reset();
+        let mut connected_agents = 0;
+        for machine in machines {
+            let name = machine.infos.name.as_str();
+            for state in STATES {
+                self.machine_state
+                    .with_label_values(&[name, state_label(state)])
+                    .set((machine.infos.state == state).into());
+            }
+            self.off_while_needed
+                .with_label_values(&[name])
+                .set((machine.infos.state == State::Off && machine.is_needed()).into());
+            self.ssh_sessions
+                .with_label_values(&[name])
+                .set(machine.infos.ssh_sessions.try_into().unwrap_or(i64::MAX));
+            self.vdi_sessions
+                .with_label_values(&[name])
+                .set(machine.infos.vdi_opened.into());
+            if machine.agent_alive() {
+                connected_agents += 1;
+            }
+        }
+        self.connected_agents.set(connected_agents);
+    }
+
+    /// Metrics in the prometheus text format
+    pub fn encode(&self) -> prometheus::Result<String> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+const fn state_label(state: State) -> &'static str {
+    match state {
+        State::Unknown => "unknown",
+        State::On => "on",
+        State::Off => "off",
+        State::PendingOn => "pending_on",
+        State::PendingOff => "pending_off",
+    }
+}
+
+async fn metrics(store: Store) -> Result<Box<dyn Reply>, Infallible> {
+    MONITORING.observe_machines(&store.lock().await.machines);
+    Ok(match MONITORING.encode() {
+        Ok(metrics) => Box::new(reply::with_header(
+            metrics,
+            "content-type",
+            TextEncoder::new().format_type(),
+        )),
+        Err(err) => {
+            error!("Could not encode the prometheus metrics: {err:#}");
+            Box::new(reply::with_status(
+                err.to_string(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    })
+}
+
+/// `/metrics` endpoint, meant to be scraped by prometheus
+pub fn handler(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and_then(move || metrics(store.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, machine::service::StoreInner};
+    use figment::{
+        providers::{Format as _, Yaml},
+        Figment,
+    };
+
+    #[test]
+    fn test_encode() {
+        let monitoring = Monitoring::new().unwrap();
+        monitoring.record_wake("machine1", true);
+        monitoring.record_wake("machine1", false);
+        monitoring.record_wake("machine1", false);
+        monitoring.record_task("machine1", "Backup", Duration::from_secs(2), false);
+        monitoring.record_http_request(&Method::GET, StatusCode::NOT_FOUND);
+        let metrics = monitoring.encode().unwrap();
+        for expected in [
+            r#"wol_wakes_total{machine="machine1",outcome="success"} 1"#,
+            r#"wol_wakes_total{machine="machine1",outcome="failure"} 2"#,
+            r#"wol_task_duration_seconds_count{machine="machine1",task="Backup"} 1"#,
+            r#"wol_task_failures_total{machine="machine1",task="Backup"} 1"#,
+            r#"wol_http_requests_total{method="GET",status="404"} 1"#,
+            "wol_connected_agents 0",
+        ] {
+            assert!(
+                metrics.lines().any(|line| line == expected),
+                "`{expected}` not found in:\n{metrics}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_observe_machines() {
+        let config: Config = Figment::new()
+            .merge(Yaml::string(include_str!("../tests/simple_config.yml")))
+            .extract()
+            .unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        let mut store = StoreInner::new(&config, data_dir.path()).unwrap();
+        store.by_name_mut("machine1").unwrap().infos.state = State::Off;
+        let monitoring = Monitoring::new().unwrap();
+        monitoring.observe_machines(&store.machines);
+        let metrics = monitoring.encode().unwrap();
+        for expected in [
+            r#"wol_machine_state{machine="machine1",state="off"} 1"#,
+            r#"wol_machine_state{machine="machine1",state="on"} 0"#,
+            r#"wol_ssh_sessions{machine="machine1"} 0"#,
+            r#"wol_machine_off_while_needed{machine="machine1"} 0"#,
+        ] {
+            assert!(
+                metrics.lines().any(|line| line == expected),
+                "`{expected}` not found in:\n{metrics}"
+            );
+        }
+
+        let machine = store.by_name_mut("machine1").unwrap();
+        machine.infos.state = State::Unknown;
+        let task = serde_json::from_str(r#"{"name": "Fake task"}"#).unwrap();
+        machine.push_group_task(&task, true).unwrap();
+        machine.infos.state = State::Off;
+        monitoring.observe_machines(&store.machines);
+        let expected = r#"wol_machine_off_while_needed{machine="machine1"} 1"#;
+        assert!(
+            monitoring
+                .encode()
+                .unwrap()
+                .lines()
+                .any(|line| line == expected),
+            "a queued task needs the machine"
+        );
+
+        monitoring.observe_machines(&[]);
+        assert!(
+            !monitoring.encode().unwrap().contains("machine1"),
+            "removed machines should be forgotten"
+        );
+    }
+}
@@ -1 +1,2 @@
 pub mod comparable_floats;
+pub mod time;
@@ -0,0 +1,191 @@
+use rstest::rstest;
+use std::collections::BTreeMap;
+use wol_relay_server::agent::messages::{
+    decode, AgentCredentials, AgentHello, AgentMessage, Capability, Decoded, PowerAction,
+    ServerMessage, CAPABILITIES, PROTOCOL_VERSION,
+};
+
+fn decode_known<T>(msg: &str) -> T
+where
+    T: serde::de::DeserializeOwned,
+{
+    match decode(msg) {
+        Ok(Decoded::Known(msg)) => msg,
+        Ok(Decoded::Unknown(kind)) => unreachable!("`{kind}` should be known"),
+        Err(err) => unreachable!("failed to decode {msg}: {err}"),
+    }
+}
+
+#[test]
+fn hello_round_trip() {
+    let hello = AgentMessage::Hello(AgentHello {
+        machine_name: "machine1".to_owned(),
+        applications: vec![],
+        vdi_certificate_hash: Some(vec![1, 2, 3]),
+        credentials: Some(AgentCredentials::PairingRequest {
+            code: "123-456".to_owned(),
+        }),
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: CAPABILITIES.to_vec(),
+    });
+    let json = serde_json::to_string(&hello).unwrap();
+    let AgentMessage::Hello(decoded) = decode_known(&json) else {
+        unreachable!("expected a hello");
+    };
+    assert_eq!(decoded.machine_name, "machine1");
+    assert_eq!(decoded.vdi_certificate_hash, Some(vec![1, 2, 3]));
+    assert_eq!(decoded.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(decoded.capabilities, CAPABILITIES);
+}
+
+#[test]
+fn hello_from_unversioned_agent() {
+    let AgentMessage::Hello(hello) =
+        decode_known(r#"{"Hello":{"machine_name":"machine1","applications":[]}}"#)
+    else {
+        unreachable!("expected a hello");
+    };
+    assert_eq!(hello.protocol_version, 0);
+    assert_eq!(hello.capabilities, []);
+    assert_eq!(hello.credentials, None);
+}
+
+#[test]
+fn hello_with_raw_icons() {
+    let AgentMessage::Hello(hello) = decode_known(
+        r#"{"Hello":{"machine_name":"machine1","applications":[{
+            "name":"Firefox",
+            "icon_bytes":[255,0,0,255],
+            "icon_name":"firefox.png",
+            "exec":"firefox",
+            "category":"Network"
+        }]}}"#,
+    ) else {
+        unreachable!("expected a hello");
+    };
+    assert_eq!(
+        hello.applications[0].icon, None,
+        "raw icons of older agents are ignored"
+    );
+}
+
+#[test]
+fn hello_from_newer_agent() {
+    let AgentMessage::Hello(hello) = decode_known(
+        r#"{"Hello":{
+            "machine_name":"machine1",
+            "applications":[],
+            "protocol_version":42,
+            "capabilities":["vdi","teleportation"],
+            "some_new_field":{"nested":true}
+        }}"#,
+    ) else {
+        unreachable!("expected a hello");
+    };
+    assert_eq!(hello.protocol_version, 42);
+    assert_eq!(
+        hello.capabilities,
+        [Capability::Vdi, Capability::Unknown],
+        "unknown capabilities should not fail the decoding"
+    );
+}
+
+#[rstest]
+#[case(ServerMessage::Welcome { protocol_version: PROTOCOL_VERSION, capabilities: CAPABILITIES.to_vec() })]
+#[case(ServerMessage::OpenVdi)]
+#[case(ServerMessage::ShutdownWarning { in_secs: 300, cancellable: true })]
+#[case(ServerMessage::ShutdownCancelled)]
+#[case(ServerMessage::Paired { secret: "secret".to_owned() })]
+#[case(ServerMessage::Rejected { reason: "nope".to_owned(), pairing_required: false })]
+#[case(ServerMessage::PowerAction(PowerAction::Reboot))]
+#[case(ServerMessage::Power { id: 3, action: PowerAction::Suspend })]
+#[case(ServerMessage::RequestIcons(vec!["a".repeat(64)]))]
+#[case(ServerMessage::Exec {
+    id: 1,
+    argv: vec!["echo".to_owned(), "hello world".to_owned()],
+    env: [("LANG".to_owned(), "C".to_owned())].into(),
+    cwd: Some("/tmp".into()),
+    detach: false,
+    name: None,
+    terminal: false,
+})]
+#[case(ServerMessage::Exec {
+    id: 2,
+    argv: vec!["firefox".to_owned()],
+    env: BTreeMap::new(),
+    cwd: None,
+    detach: true,
+    name: Some("Firefox".to_owned()),
+    terminal: true,
+})]
+#[case(ServerMessage::CloseApplication { pid: 1234 })]
+fn server_message_round_trip(#[case] msg: ServerMessage) {
+    let json = serde_json::to_string(&msg).unwrap();
+    assert_eq!(decode_known::<ServerMessage>(&json), msg);
+}
+
+/// Messages as sent by version 0 peers must still be understood
+#[rstest]
+#[case(r#""OpenVdi""#)]
+#[case(r#"{"ShutdownWarning":{"in_secs":300,"cancellable":false}}"#)]
+#[case(r#""ShutdownCancelled""#)]
+fn server_message_from_older_backend(#[case] json: &str) {
+    let _msg: ServerMessage = decode_known(json);
+}
+
+#[test]
+fn exec_with_defaults() {
+    assert_eq!(
+        decode_known::<ServerMessage>(r#"{"Exec":{"id":1,"argv":["true"]}}"#),
+        ServerMessage::Exec {
+            id: 1,
+            argv: vec!["true".to_owned()],
+            env: BTreeMap::new(),
+            cwd: None,
+            detach: false,
+            name: None,
+            terminal: false,
+        }
+    );
+    assert_eq!(
+        decode_known::<AgentMessage>(r#"{"ExecExit":{"id":1,"code":null}}"#),
+        AgentMessage::ExecExit {
+            id: 1,
+            code: None,
+            error: None,
+        }
+    );
+}
+
+#[rstest]
+#[case(r#"{"VdiCertificateHash":[1,2,3]}"#)]
+#[case(r#""VdiClosed""#)]
+#[case(r#""PostponeShutdown""#)]
+#[case(r#""VetoShutdown""#)]
+fn agent_message_from_older_agent(#[case] json: &str) {
+    let _msg: AgentMessage = decode_known(json);
+}
+
+#[rstest]
+#[case(r#""Teleport""#, "Teleport")]
+#[case(r#"{"Teleport":{"to":"mars"}}"#, "Teleport")]
+#[case(r#"{"Teleport":[1,2]}"#, "Teleport")]
+fn unknown_messages_are_not_errors(#[case] json: &str, #[case] kind: &str) {
+    assert_eq!(
+        decode::<ServerMessage>(json).unwrap(),
+        Decoded::Unknown(kind.to_owned())
+    );
+    assert_eq!(
+        decode::<AgentMessage>(json).unwrap(),
+        Decoded::Unknown(kind.to_owned())
+    );
+}
+
+#[rstest]
+#[case("not json")]
+#[case(r#"{"ShutdownWarning":{"in_secs":"soon"}}"#)]
+#[case(r#"{"PowerAction":"Hibernate"}"#)]
+#[case("42")]
+fn malformed_messages_are_errors(#[case] json: &str) {
+    decode::<ServerMessage>(json).unwrap_err();
+}
@@ -2,7 +2,12 @@ use itertools::Itertools as _;
 use rstest::rstest;
 use std::path::PathBuf;
 use std::time::Duration;
-use wol_relay_server::machine::application::{list_local_applications, Application};
+use wol_relay_server::machine::application::{
+    desktop_file::Locale,
+    list_applications_in, list_local_applications,
+    providers::{self, Roots},
+    Application,
+};
 
 #[rstest]
 #[timeout(Duration::from_secs(1))]
@@ -52,3 +57,109 @@ async fn test_list_applications() -> anyhow::Result<()> {
     assert!(!applications.is_empty());
     Ok(())
 }
+
+/// Names of the applications shown on `desktops`, with the dirs of `tests/assets/xdg` by priority
+async fn list_fixtures(locale: &str, desktops: &[&str]) -> Vec<(String, String)> {
+    let dirs = ["high", "low"].map(|dir| {
+        PathBuf::from("tests/assets/xdg")
+            .join(dir)
+            .join("applications")
+    });
+    let desktops = desktops
+        .iter()
+        .map(|&desktop| desktop.to_owned())
+        .collect_vec();
+    list_applications_in(&dirs, &Locale::parse(locale), &desktops)
+        .await
+        .into_iter()
+        .map(|(path, application)| {
+            let path = path
+                .strip_prefix("tests/assets/xdg")
+                .unwrap()
+                .display()
+                .to_string();
+            (application.name().clone().unwrap_or_default(), path)
+        })
+        .sorted()
+        .collect()
+}
+
+#[rstest]
+#[case("C", &["GNOME"], &[
+    ("Chess", "high/applications/games/chess.desktop"),
+    ("Editor", "high/applications/editor.desktop"),
+    ("Gnome Only", "low/applications/gnome-only.desktop"),
+])]
+#[case("C", &["KDE"], &[
+    ("Chess", "high/applications/games/chess.desktop"),
+    ("Editor", "high/applications/editor.desktop"),
+    ("Kde Only", "low/applications/kde-only.desktop"),
+    ("Not Gnome", "low/applications/not-gnome.desktop"),
+])]
+#[case("C", &[], &[
+    ("Chess", "high/applications/games/chess.desktop"),
+    ("Editor", "high/applications/editor.desktop"),
+    ("Not Gnome", "low/applications/not-gnome.desktop"),
+])]
+#[case("fr_FR.UTF-8", &["ubuntu", "GNOME"], &[
+    ("Echecs", "high/applications/games/chess.desktop"),
+    ("Editeur", "high/applications/editor.desktop"),
+    ("Gnome Only", "low/applications/gnome-only.desktop"),
+])]
+#[case("fr_BE.UTF-8", &["GNOME"], &[
+    ("Editeur", "high/applications/editor.desktop"),
+    ("Gnome Only", "low/applications/gnome-only.desktop"),
+    ("Jeu d'echecs", "high/applications/games/chess.desktop"),
+])]
+#[tokio::test]
+async fn test_visibility(
+    #[case] locale: &str,
+    #[case] desktops: &[&str],
+    #[case] expected: &[(&str, &str)],
+) {
+    let expected = expected
+        .iter()
+        .map(|&(name, path)| (name.to_owned(), path.to_owned()))
+        .collect_vec();
+    assert_eq!(list_fixtures(locale, desktops).await, expected);
+}
+
+#[tokio::test]
+async fn test_parse_localized() -> anyhow::Result<()> {
+    let path = PathBuf::from("tests/assets/xdg/high/applications/editor.desktop");
+    let application = Application::parse_localized(&path, &Locale::parse("fr_CA.UTF-8")).await?;
+    assert_eq!(application.name().as_deref(), Some("Editeur"));
+    let application = Application::parse_localized(&path, &Locale::parse("de_DE.UTF-8")).await?;
+    assert_eq!(application.name().as_deref(), Some("Editor"));
+    Ok(())
+}
+
+#[test]
+fn test_providers() -> anyhow::Result<()> {
+    let home = PathBuf::from("tests/assets/providers/home");
+    let roots = Roots {
+        data_home: home.join(".local/share"),
+        home,
+    };
+    let games = providers::list(&roots)?
+        .into_iter()
+        .map(|(_path, application)| (application.name().clone(), application.exec().clone()))
+        .collect_vec();
+    assert_eq!(
+        games,
+        [(
+            Some("Satisfactory".to_owned()),
+            Some("steam -applaunch 526870".to_owned())
+        )],
+        "the missing libraries should be skipped"
+    );
+    assert_eq!(
+        providers::watched_dirs(&roots),
+        [
+            roots.data_home.join("Steam/steamapps"),
+            PathBuf::from("/mnt/games/SteamLibrary/steamapps"),
+            PathBuf::from("/run/media/oscar/ssd/SteamLibrary/steamapps"),
+        ]
+    );
+    Ok(())
+}
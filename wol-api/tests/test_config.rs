@@ -12,7 +12,7 @@ use figment::{
 use rstest::{fixture, rstest};
 use tempfile::TempDir;
 use tokio::time::timeout;
-use wol_relay_server::config::{self, Config};
+use wol_relay_server::config::{self, Config, CustomGroupCfg, IconProviderCfg};
 use wol_relay_server::test;
 
 #[fixture]
@@ -250,3 +250,82 @@ async fn config_reload_edit_like_editor() -> Result<()> {
 
     Ok(())
 }
+
+#[rstest]
+#[case(&[("machine1", "machine2"), ("machine2", "machine1")], "Dependency cycle")]
+#[case(&[("machine1", "machine1")], "Dependency cycle")]
+#[case(&[("machine1", "unknown")], "unknown machine")]
+fn config_invalid_dependencies(
+    mut test_config: Config,
+    #[case] dependencies: &[(&str, &str)],
+    #[case] expected: &str,
+) -> Result<()> {
+    let dir = TempDir::new()?;
+    let config_filename = dir.path().join("wol-config.yml");
+
+    let machine2 = test_config.machines["machine1"].clone();
+    test_config.machines.insert("machine2".to_owned(), machine2);
+    for (machine, dependency) in dependencies {
+        test_config
+            .machines
+            .get_mut(*machine)
+            .unwrap()
+            .depends_on
+            .push((*dependency).to_owned());
+    }
+
+    let config_file = File::create_new(&config_filename).context("Could not create config file")?;
+    serde_yaml::to_writer(&config_file, &test_config)
+        .with_context(|| format!("Failed to write to {}", config_filename.display()))?;
+
+    let err =
+        config::open(&config_filename, false).expect_err("expected the config to be rejected");
+    assert!(
+        format!("{err:#}").contains(expected),
+        "unexpected error: {err:#}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn config_duplicate_application_groups() {
+    let mut config = test_config();
+    let group = CustomGroupCfg {
+        name: "Work".to_owned(),
+        applications: vec!["Slack".to_owned()],
+    };
+    let machine = config.machines.get_mut("machine1").unwrap();
+    machine.applications.groups = vec![group.clone()];
+    config.validate().unwrap();
+
+    let machine = config.machines.get_mut("machine1").unwrap();
+    machine.applications.groups.push(group);
+    let err = config
+        .validate()
+        .expect_err("expected the config to be rejected");
+    assert!(
+        format!("{err:#}").contains("several application groups named `Work`"),
+        "unexpected error: {err:#}"
+    );
+}
+
+#[rstest]
+#[case("", IconProviderCfg::Disabled)]
+#[case("icon_provider: disabled", IconProviderCfg::Disabled)]
+#[case(
+    "icon_provider: { searxng: { url: \"https://searx.example.com\" } }",
+    IconProviderCfg::Searxng { url: "https://searx.example.com".to_owned() }
+)]
+#[case(
+    "icon_provider: { directory: { path: /srv/icons } }",
+    IconProviderCfg::Directory { path: "/srv/icons".into() }
+)]
+fn config_icon_provider(#[case] icon_provider: &str, #[case] expected: IconProviderCfg) {
+    let config: Config = Figment::new()
+        .merge(Yaml::string(include_str!("./simple_config.yml")))
+        .merge(Yaml::string(icon_provider))
+        .extract()
+        .unwrap();
+    assert_eq!(config.icon_provider, expected);
+}
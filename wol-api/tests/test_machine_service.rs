@@ -1,9 +1,20 @@
 use anyhow::Context as _;
+use core::time::Duration;
 use figment::{
     providers::{Format as _, Yaml},
     Figment,
 };
-use wol_relay_server::{config::Config, machine::service::*};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use warp::Filter as _;
+use wol_relay_server::{
+    agent::{
+        heartbeat::HeartbeatCfg,
+        messages::{decode, Capability, Decoded, ServerMessage},
+    },
+    config::Config,
+    machine::{api::responses::CloseApplicationError, application::RunningApplication, service::*},
+};
 
 #[tokio::test]
 async fn machine_wake_shutdown_test_dry_run() -> anyhow::Result<()> {
@@ -12,7 +23,8 @@ async fn machine_wake_shutdown_test_dry_run() -> anyhow::Result<()> {
         .merge(Yaml::string(include_str!("./simple_config.yml")))
         .extract()
         .context("Failed to parse config file")?;
-    let mut store = StoreInner::new(&config).context("Could not create store")?;
+    let data_dir = tempfile::tempdir()?;
+    let mut store = StoreInner::new(&config, data_dir.path()).context("Could not create store")?;
     let machine = store
         .by_name_mut(config.machines.keys().next().unwrap())
         .unwrap();
@@ -33,7 +45,7 @@ async fn machine_wake_shutdown_test_dry_run() -> anyhow::Result<()> {
         State::PendingOn,
         "Sending a wake on lan should put the machine in PendingOn"
     );
-    machine.update_state().await;
+    machine.update_state(DRY_RUN).await;
     assert_eq!(
         machine.infos.state,
         State::On,
@@ -48,8 +60,241 @@ async fn machine_wake_shutdown_test_dry_run() -> anyhow::Result<()> {
         "Shutdown should turn the machine.state Off"
     );
 
-    machine.update_state().await;
+    machine.update_state(DRY_RUN).await;
     assert_eq!(machine.infos.state, State::PendingOff,);
 
     Ok(())
 }
+
+#[tokio::test]
+async fn machine_delayed_shutdown_dry_run() -> anyhow::Result<()> {
+    const DRY_RUN: bool = true;
+    let config: Config = Figment::new()
+        .merge(Yaml::string(include_str!("./simple_config.yml")))
+        .extract()
+        .context("Failed to parse config file")?;
+    let data_dir = tempfile::tempdir()?;
+    let mut store = StoreInner::new(&config, data_dir.path()).context("Could not create store")?;
+    let machine = store.by_name_mut("machine1").unwrap();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    machine
+        .schedule_shutdown(Duration::from_secs(300), false, DRY_RUN)
+        .await;
+    let shutdown_at = machine
+        .infos
+        .pending_shutdown_at
+        .expect("A delayed shutdown should set pending_shutdown_at");
+    assert!((now + 300..=now + 301).contains(&shutdown_at));
+    assert_eq!(
+        machine.infos.state,
+        State::Unknown,
+        "A delayed shutdown should not change the state right away"
+    );
+
+    machine
+        .cancel_shutdown(DRY_RUN)
+        .await
+        .expect("failed to cancel the pending shutdown");
+    assert_eq!(machine.infos.pending_shutdown_at, None);
+    machine
+        .cancel_shutdown(DRY_RUN)
+        .await
+        .expect_err("There is no shutdown left to cancel");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn machine_group_task_dry_run() -> anyhow::Result<()> {
+    const DRY_RUN: bool = true;
+    let config: Config = Figment::new()
+        .merge(Yaml::string(include_str!("./simple_config.yml")))
+        .extract()
+        .context("Failed to parse config file")?;
+    let data_dir = tempfile::tempdir()?;
+    let mut store = StoreInner::new(&config, data_dir.path()).context("Could not create store")?;
+    assert_eq!(store.by_tag_mut("unknown").count(), 0);
+
+    let task: GroupTask = serde_json::from_str(r#"{"name": "Fake task"}"#)?;
+    assert_eq!(
+        store.by_tag_mut("test").count(),
+        1,
+        "machine1 is the only machine tagged test"
+    );
+    for machine in store.by_tag_mut("test") {
+        machine
+            .push_group_task(&task, DRY_RUN)
+            .expect("failed to push a task by name");
+        assert_eq!(machine.infos.tasks.len(), 1);
+    }
+
+    let task: GroupTask = serde_json::from_str(r#"{"name": "Missing task"}"#)?;
+    let machine = store.by_name_mut("machine1").unwrap();
+    machine
+        .push_group_task(&task, DRY_RUN)
+        .expect_err("the machine has no task with this name");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn machine_woken_for_tasks_dry_run() -> anyhow::Result<()> {
+    const DRY_RUN: bool = true;
+    let config: Config = Figment::new()
+        .merge(Yaml::string(include_str!("./simple_config.yml")))
+        .extract()
+        .context("Failed to parse config file")?;
+    let data_dir = tempfile::tempdir()?;
+    let mut store = StoreInner::new(&config, data_dir.path()).context("Could not create store")?;
+    let machine = store.by_name_mut("machine1").unwrap();
+    let task: GroupTask = serde_json::from_str(r#"{"name": "Fake task", "shutdown_after": true}"#)?;
+
+    machine.infos.state = State::On;
+    machine.push_group_task(&task, DRY_RUN).unwrap();
+    assert!(
+        !machine.woken_for_tasks(),
+        "The machine was already on, it must not be shut down after its tasks"
+    );
+
+    machine.infos.state = State::Off;
+    machine.push_group_task(&task, DRY_RUN).unwrap();
+    assert_eq!(machine.infos.state, State::PendingOn);
+    assert!(machine.woken_for_tasks());
+
+    machine.push_group_task(&task, DRY_RUN).unwrap();
+    assert!(
+        machine.woken_for_tasks(),
+        "Another task doesn't change why the machine is waking up"
+    );
+
+    machine.wake(DRY_RUN).unwrap();
+    assert!(
+        !machine.woken_for_tasks(),
+        "Someone woke the machine up, it must stay on after its tasks"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn machine_wake_dependencies_dry_run() -> anyhow::Result<()> {
+    const DRY_RUN: bool = true;
+    let mut config: Config = Figment::new()
+        .merge(Yaml::string(include_str!("./simple_config.yml")))
+        .extract()
+        .context("Failed to parse config file")?;
+    let mut machine2 = config.machines["machine1"].clone();
+    machine2.depends_on = vec!["machine1".to_owned()];
+    config.machines.insert("machine2".to_owned(), machine2);
+    let data_dir = tempfile::tempdir()?;
+    let mut store = StoreInner::new(&config, data_dir.path()).context("Could not create store")?;
+
+    let machine = store.by_name_mut("machine2").unwrap();
+    let msg = machine
+        .wake(DRY_RUN)
+        .expect("failed to wake the machine in dry_run mode");
+    assert!(msg.contains("machine1"), "unexpected message: {msg}");
+    assert_eq!(machine.infos.state, State::PendingOn);
+
+    store.wake_dependencies(DRY_RUN);
+    assert_eq!(
+        store.by_name_mut("machine1").unwrap().infos.state,
+        State::PendingOn,
+        "The dependency should be woken first"
+    );
+    assert_eq!(store.running_dependents("machine1"), vec!["machine2"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn machine_close_application_dry_run() -> anyhow::Result<()> {
+    const DRY_RUN: bool = true;
+    let config: Config = Figment::new()
+        .merge(Yaml::string(include_str!("./simple_config.yml")))
+        .extract()
+        .context("Failed to parse config file")?;
+    let data_dir = tempfile::tempdir()?;
+    let mut store = StoreInner::new(&config, data_dir.path()).context("Could not create store")?;
+    let machine = store.by_name_mut("machine1").unwrap();
+    assert_eq!(
+        machine.close_application(1234, DRY_RUN).await,
+        Err(CloseApplicationError::NotRunning),
+        "no application was opened from the panel"
+    );
+
+    machine.infos.running_applications = vec![RunningApplication {
+        pid: 1234,
+        name: "Firefox".to_owned(),
+        started_at: 1_735_689_600,
+    }];
+    assert_eq!(machine.close_application(1234, DRY_RUN).await, Ok(()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn machine_open_unknown_application_dry_run() -> anyhow::Result<()> {
+    const DRY_RUN: bool = true;
+    let config: Config = Figment::new()
+        .merge(Yaml::string(include_str!("./simple_config.yml")))
+        .extract()
+        .context("Failed to parse config file")?;
+    let data_dir = tempfile::tempdir()?;
+    let store = StoreInner::new(&config, data_dir.path()).context("Could not create store")?;
+    let machine = store.by_name("machine1").unwrap();
+    assert!(
+        matches!(
+            machine.open_app("Firefox", None, &[], None, DRY_RUN).await,
+            Err(OpenApplicationError::UnknownApplication(name)) if name == "Firefox"
+        ),
+        "the agent never sent its applications"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn machine_agent_disconnected_during_task_dry_run() -> anyhow::Result<()> {
+    const DRY_RUN: bool = true;
+    let config: Config = Figment::new()
+        .merge(Yaml::string(include_str!("./simple_config.yml")))
+        .extract()
+        .context("Failed to parse config file")?;
+    let data_dir = tempfile::tempdir()?;
+    let mut store = StoreInner::new(&config, data_dir.path()).context("Could not create store")?;
+    let machine = store.by_name_mut("machine1").unwrap();
+
+    let (sockets_send, mut sockets) = mpsc::unbounded_channel();
+    let route = warp::ws().map(move |ws: warp::ws::Ws| {
+        let sockets_send = sockets_send.clone();
+        ws.on_upgrade(move |socket| async move {
+            sockets_send.send(socket).unwrap();
+        })
+    });
+    let mut agent = warp::test::ws().handshake(route).await?;
+    let socket = sockets.recv().await.context("The agent did not connect")?;
+    machine.set_connection(socket, vec![Capability::Exec], HeartbeatCfg::default());
+
+    let task: GroupTask = serde_json::from_str(r#"{"name": "Fake task"}"#)?;
+    machine.push_group_task(&task, DRY_RUN).unwrap();
+    let agent = tokio::spawn(async move {
+        let msg = agent.recv().await?;
+        // the agent dies while running the task
+        drop(agent);
+        anyhow::Ok(msg)
+    });
+    tokio::time::timeout(Duration::from_secs(10), machine.update_state(DRY_RUN))
+        .await
+        .context("The task kept waiting for the disconnected agent")?;
+    let msg = agent.await??;
+    let Decoded::Known(msg) = decode::<ServerMessage>(msg.to_str().unwrap())? else {
+        anyhow::bail!("The agent received an unknown message");
+    };
+    assert_eq!(
+        msg.capability(),
+        Some(Capability::Exec),
+        "the task is run by the agent"
+    );
+    assert_eq!(machine.infos.tasks, [], "the failed task is not retried");
+    Ok(())
+}
@@ -175,8 +175,11 @@ export interface components {
         };
     /** @description Application data for the web */
     ApplicationDisplay: {
-      /** @example /api/cache/images/steam_icon_526870.png */
-      icon: string;
+      /**
+       * @description `None` if the agent didn't send one and the icon provider found nothing
+       * @example /api/cache/images/steam_icon_526870.png
+       */
+      icon?: string | null;
       /** @example Satisfactory */
       name: string;
     };
@@ -184,6 +187,16 @@ export interface components {
       groups: {
         [key: string]: components["schemas"]["ApplicationDisplay"][];
       };
+      /**
+       * @description Names of the `groups`, in the order they should be shown
+       * @example [
+       *       "Favourites",
+       *       "Games",
+       *       "Internet",
+       *       "Misc"
+       *     ]
+       */
+      order?: string[];
     };
     ListMachineResponse: {
       machines: components["schemas"]["MachineInfos"][];
@@ -236,6 +249,8 @@ export interface components {
     State: "unknown" | "on" | "off" | "pending_on" | "pending_off";
     Task: {
       id: number;
+      /** @description Shut the machine down once the queue drained successfully, if it was woken up for it */
+      shutdown_after?: boolean;
     };
     TaskCfg: {
       /** @example ["echo", "hello", "world"] */